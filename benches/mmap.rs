@@ -0,0 +1,37 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image_metadata::{extract_file_with_options, Options};
+
+const IMAGES: &[&str] = &[
+    "tests/images/JAM19896.jpg",
+    "tests/images/JAM26284.jpg",
+    "tests/images/JAM26496.jpg",
+    "tests/images/rotated_CCW90.jpg",
+];
+
+/// Compares extracting a batch of images by memory mapping them against reading them
+fn mmap(c: &mut Criterion) {
+    let mapped = Options {
+        mmap: true,
+        ..Default::default()
+    };
+    let read = Options::default();
+    let mut group = c.benchmark_group("mmap");
+    group.bench_function("mapped", |b| {
+        b.iter(|| {
+            for image in IMAGES {
+                extract_file_with_options(black_box(image), &mapped).unwrap();
+            }
+        })
+    });
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            for image in IMAGES {
+                extract_file_with_options(black_box(image), &read).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mmap);
+criterion_main!(benches);
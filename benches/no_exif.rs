@@ -0,0 +1,29 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::io::Cursor;
+
+/// A jpeg with a JFIF header but no exif, padded out with some image data
+fn jpeg_without_exif() -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 16];
+    jpeg.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2]);
+    jpeg.resize(jpeg.len() + 1024 * 1024, 0);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+/// Compares our check for jpegs without exif against running the exif reader over them
+/// until it fails, as we used to
+fn no_exif(c: &mut Criterion) {
+    let jpeg = jpeg_without_exif();
+    let mut group = c.benchmark_group("no_exif");
+    group.bench_function("extract_metadata_from_bytes", |b| {
+        b.iter(|| image_metadata::extract_metadata_from_bytes(black_box(&jpeg)))
+    });
+    group.bench_function("exif_reader", |b| {
+        b.iter(|| exif::Reader::new().read_from_container(&mut Cursor::new(black_box(&jpeg))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, no_exif);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Reads a json file mapping camera model strings to the names they should be output as,
+/// ie `{"EOS 5D Mark IV": "Canon EOS 5D Mark IV"}`, for `Options::camera_aliases`
+pub fn load_camera_aliases<P: AsRef<Path>>(path: P) -> std::io::Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_file_with_options, ImageError, Options};
+
+    #[test]
+    fn test_camera_aliases() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/camera_aliases");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let aliases_path = dir.join("aliases.json");
+        std::fs::write(
+            &aliases_path,
+            r#"{"Canon EOS 5D Mark IV": "5D4", "Canon EOS R": "EOS R"}"#,
+        )?;
+
+        let options = Options {
+            camera_aliases: load_camera_aliases(&aliases_path)?,
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options("tests/images/JAM19896.jpg", &options)?;
+        assert_eq!(
+            metadata.unwrap().image_metadata.camera_model.as_deref(),
+            Some("5D4")
+        );
+
+        // Models without an alias are left as they are
+        let options = Options {
+            camera_aliases: vec![("Canon EOS R".to_string(), "EOS R".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options("tests/images/JAM19896.jpg", &options)?;
+        assert_eq!(
+            metadata.unwrap().image_metadata.camera_model.as_deref(),
+            Some("Canon EOS 5D Mark IV")
+        );
+
+        std::fs::write(&aliases_path, "[\"not a map\"]")?;
+        assert!(load_camera_aliases(&aliases_path).is_err());
+        Ok(())
+    }
+}
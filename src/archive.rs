@@ -0,0 +1,242 @@
+use crate::native::{
+    apply_image_options, error_stub, extract_reader_with_options, json_path, write_metadata_to_file,
+};
+use crate::walk::is_image_path;
+use crate::{output, CombinedMetadata, FileMetadata, ImageError, ImageMetadata, OnError, Options};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+/// What came of processing the images inside a zip archive
+#[derive(Debug, Default)]
+pub struct ZipEntries {
+    /// The metadata for each of the images that matched the filters
+    pub metadata: Vec<CombinedMetadata>,
+    /// The images that failed to be extracted, by their name in the archive
+    pub failures: Vec<(String, ImageError)>,
+}
+
+/// Process the images inside a zip archive without unpacking it to disk, following the
+/// given options. The metadata for all the images gets written out as a json array to a
+/// single file next to the archive, or with an `output_dir` to a json file per image
+/// under it, laid out as they are in the archive.
+///
+/// Images that fail to be extracted don't stop the rest, they're returned in the
+/// failures and as per `on_error` either stop things there without writing out the
+/// json array, get left out, or get their `error_stub` written in place of the metadata
+pub fn process_zip<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+    on_error: OnError,
+) -> Result<ZipEntries, ImageError> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(&path)?))?;
+    let mut entries = ZipEntries::default();
+    let mut values = vec![];
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if !entry.is_file() || !is_image_path(entry.name()) {
+            continue;
+        }
+        let name = entry.name().to_string();
+        if let Some(max_file_size) = options
+            .max_file_size
+            .filter(|max_file_size| entry.size() > *max_file_size)
+        {
+            if let Some(on_skip) = options.on_skip {
+                let reason = format!(
+                    "Skipping as it's {} bytes, over the maximum file size of {} bytes",
+                    entry.size(),
+                    max_file_size
+                );
+                on_skip(path.as_ref().join(&name), reason);
+            }
+            continue;
+        }
+        let output_path = match &options.output_dir {
+            Some(output_dir) => match entry.enclosed_name() {
+                Some(enclosed_name) => Some(json_path(output_dir.join(enclosed_name))),
+                // Names like "../../photo.jpg" would have us writing outside of it
+                None => {
+                    let error = std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is outside of the archive", name),
+                    );
+                    entries.failures.push((name, error.into()));
+                    match on_error {
+                        OnError::Stop => return Ok(entries),
+                        _ => continue,
+                    }
+                }
+            },
+            None => None,
+        };
+
+        let value = match extract_entry(&mut entry, options) {
+            Ok(Some(metadata)) => {
+                let value =
+                    output::to_output_value(&metadata, options).map_err(std::io::Error::from)?;
+                entries.metadata.push(metadata);
+                value
+            }
+            Ok(None) => continue,
+            Err(error) => {
+                let mut stub = error_stub(&name, &error, options);
+                // The filename is the whole path in the archive, as for the metadata
+                stub["filename"] = name.clone().into();
+                entries.failures.push((name, error));
+                match on_error {
+                    OnError::Stop => return Ok(entries),
+                    OnError::Skip => continue,
+                    OnError::Record => stub,
+                }
+            }
+        };
+        match output_path {
+            Some(output_path) => {
+                if let Some(parent) = output_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                write_metadata_to_file(output_path, &value)?;
+            }
+            None => values.push(value),
+        }
+    }
+
+    if options.output_dir.is_none() {
+        write_metadata_to_file(json_path(path), &values)?;
+    }
+    Ok(entries)
+}
+
+/// Extract the metadata from an image in the archive as per `extract_file_with_options`,
+/// returns None if the image was skipped as it didn't match the filters
+fn extract_entry(
+    entry: &mut ZipFile,
+    options: &Options,
+) -> Result<Option<CombinedMetadata>, ImageError> {
+    // Zip entries can't be seeked so we need to pull each image into memory. The size
+    // in the entry's header can't be trusted to allocate up front
+    let mut bytes = vec![];
+    entry.read_to_end(&mut bytes)?;
+
+    #[cfg_attr(not(feature = "sha2"), allow(unused_mut))]
+    let mut file_metadata = FileMetadata {
+        filename: entry.name().to_string(),
+        size: bytes.len() as u64,
+        // Zip only records the modified time in local time with no timezone
+        // so there's not much we can do with it
+        created_time: None,
+        modified_time: None,
+        ..Default::default()
+    };
+    #[cfg(feature = "sha2")]
+    if let Some(algorithm) = options.checksum {
+        file_metadata.set_checksum(algorithm, algorithm.checksum_reader(&bytes[..])?);
+    }
+    let image_metadata = if options.file_only {
+        ImageMetadata::default()
+    } else {
+        apply_image_options(
+            extract_reader_with_options(&mut Cursor::new(&bytes), options),
+            options,
+        )?
+    };
+    let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+    if options.selects(&metadata) {
+        Ok(Some(metadata))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    /// Zips up a couple of the samples under photos/ along with a text file and a
+    /// broken image
+    fn write_zip(zip_path: &str) -> Result<(), ImageError> {
+        let mut writer = ZipWriter::new(File::create(zip_path)?);
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for name in &["JAM19896.jpg", "JAM26284.jpg"] {
+            writer.start_file(format!("photos/{}", name), options)?;
+            writer.write_all(&std::fs::read(format!("tests/images/{}", name))?)?;
+        }
+        writer.start_file("photos/README.txt", options)?;
+        writer.write_all(b"Not an image")?;
+        writer.start_file("photos/broken.jpg", options)?;
+        writer.write_all(b"Not an image either")?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_zip() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/zip")?;
+        let zip_path = "target/test/zip/images.zip";
+        let expected_json_path = "target/test/zip/images.json";
+        write_zip(zip_path)?;
+
+        let entries = process_zip(zip_path, &Options::default(), OnError::Skip)?;
+        assert_eq!(entries.metadata.len(), 2);
+        assert_eq!(entries.failures.len(), 1);
+        assert_eq!(entries.failures[0].0, "photos/broken.jpg");
+
+        let metadata: Vec<CombinedMetadata> =
+            serde_json::from_slice(&std::fs::read(expected_json_path)?).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0].file_metadata.filename, "photos/JAM19896.jpg");
+        assert_eq!(metadata[0].file_metadata.size, 953458);
+        assert_eq!(
+            metadata[1].image_metadata.camera_serial,
+            Some("025021000535".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_zip_with_options() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/zip_options")?;
+        let zip_path = "target/test/zip_options/images.zip";
+        write_zip(zip_path)?;
+
+        let output_dir = PathBuf::from("target/test/zip_options/out");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let options = Options {
+            output_dir: Some(output_dir.clone()),
+            include: Some(vec!["filename".to_string(), "camera_model".to_string()]),
+            ..Default::default()
+        };
+        let entries = process_zip(zip_path, &options, OnError::Record)?;
+        assert_eq!(entries.metadata.len(), 2);
+        let read_json = |name: &str| -> serde_json::Value {
+            serde_json::from_slice(&std::fs::read(output_dir.join(name)).unwrap()).unwrap()
+        };
+        assert_eq!(
+            read_json("photos/JAM19896.json"),
+            serde_json::json!({
+                "filename": "photos/JAM19896.jpg",
+                "camera_model": "Canon EOS 5D Mark IV",
+            })
+        );
+        assert_eq!(
+            read_json("photos/broken.json")["filename"],
+            "photos/broken.jpg"
+        );
+        assert!(!Path::new("target/test/zip_options/images.json").exists());
+
+        // Stopping at the failure doesn't write out the images before it either
+        let entries = process_zip(zip_path, &Options::default(), OnError::Stop)?;
+        assert_eq!(entries.metadata.len(), 2);
+        assert_eq!(entries.failures.len(), 1);
+        assert!(!Path::new("target/test/zip_options/images.json").exists());
+        Ok(())
+    }
+}
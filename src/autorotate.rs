@@ -0,0 +1,167 @@
+use crate::{jpeg, ImageError};
+use image::codecs::jpeg::JpegEncoder;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+/// The quality the rotated copies are encoded at
+const JPEG_QUALITY: u8 = 90;
+
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_PIXEL_X_DIMENSION: u16 = 0xA002;
+const TAG_PIXEL_Y_DIMENSION: u16 = 0xA003;
+
+/// Writes a copy of a jpeg with its pixels rotated and flipped to match its orientation,
+/// ie rotated_CCW90_rotated.jpg next to rotated_CCW90.jpg, so it displays the right way
+/// up even in viewers that ignore the orientation. The exif is carried over with the
+/// orientation reset to 1 and the pixel dimensions swapped to match, though the embedded
+/// thumbnail is left as it was. Rotating a jpeg losslessly means shuffling its DCT blocks
+/// which only works for some image sizes, so the image is always decoded and re-encoded,
+/// at a quality of 90, losing a little detail. Returns the path of the copy, or None if
+/// the image was already the right way up
+pub fn autorotate<P: AsRef<Path>>(path: P) -> Result<Option<PathBuf>, ImageError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    let orientation = match crate::extract_metadata_from_bytes(&bytes)?.orientation {
+        Some(orientation @ 2..=8) => orientation,
+        _ => return Ok(None),
+    };
+
+    let image = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg)?;
+    let rotated = match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        _ => image.rotate270(),
+    };
+    let mut rotated_jpeg = vec![];
+    JpegEncoder::new_with_quality(&mut rotated_jpeg, JPEG_QUALITY).encode_image(&rotated)?;
+
+    if let Some(segment) = jpeg::exif_segment(&bytes) {
+        let mut segment = segment.to_vec();
+        // The tiff starts after the marker, length and exif identifier
+        reset_orientation(&mut segment[10..], orientation >= 5);
+        // The exif goes after the encoder's JFIF segment, which has to come first
+        let jfif_length = u16::from_be_bytes([rotated_jpeg[4], rotated_jpeg[5]]) as usize;
+        let pos = 4 + jfif_length;
+        rotated_jpeg.splice(pos..pos, segment);
+    }
+
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push("_rotated");
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    let new_path = path.with_file_name(file_name);
+    std::fs::write(&new_path, rotated_jpeg)?;
+    Ok(Some(new_path))
+}
+
+/// Sets the orientation tags in the exif to 1 in place, swapping the pixel dimensions
+/// over if the image was turned on its side. Anything malformed is left alone
+fn reset_orientation(tiff: &mut [u8], transposed: bool) -> Option<()> {
+    let big_endian = tiff.starts_with(b"MM");
+    let read_u16 = |tiff: &[u8], pos: usize| {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |tiff: &[u8], pos: usize| {
+        let bytes = [
+            *tiff.get(pos)?,
+            *tiff.get(pos + 1)?,
+            *tiff.get(pos + 2)?,
+            *tiff.get(pos + 3)?,
+        ];
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        } as usize)
+    };
+    let one = if big_endian {
+        1u16.to_be_bytes()
+    } else {
+        1u16.to_le_bytes()
+    };
+    // The offsets of the entries of the IFD at the given offset
+    let entries = |tiff: &[u8], ifd: usize| {
+        let count = read_u16(tiff, ifd)? as usize;
+        Some((0..count).map(move |i| ifd + 2 + i * 12))
+    };
+    let find_entry = |tiff: &[u8], ifd: usize, tag: u16| {
+        entries(tiff, ifd)?.find(|&entry| read_u16(tiff, entry) == Some(tag))
+    };
+
+    let ifd0 = read_u32(tiff, 4)?;
+    let ifd1 = read_u32(tiff, 2 + ifd0 + read_u16(tiff, ifd0)? as usize * 12)?;
+    for &ifd in [ifd0, ifd1].iter().filter(|&&ifd| ifd != 0) {
+        // Being a single short the orientation is stored inline
+        if let Some(entry) = find_entry(tiff, ifd, TAG_ORIENTATION) {
+            tiff.get_mut(entry + 8..entry + 10)?.copy_from_slice(&one);
+        }
+    }
+
+    if transposed {
+        let exif_ifd = read_u32(tiff, find_entry(tiff, ifd0, TAG_EXIF_IFD)? + 8)?;
+        let x = find_entry(tiff, exif_ifd, TAG_PIXEL_X_DIMENSION)?;
+        let y = find_entry(tiff, exif_ifd, TAG_PIXEL_Y_DIMENSION)?;
+        // Swapping everything but the tags, as one might be a short and the other a long
+        let x_value = tiff.get(x + 2..x + 12)?.to_vec();
+        let y_value = tiff.get(y + 2..y + 12)?.to_vec();
+        tiff[x + 2..x + 12].copy_from_slice(&y_value);
+        tiff[y + 2..y + 12].copy_from_slice(&x_value);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autorotate() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/autorotate");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("rotated_CCW90.jpg");
+        std::fs::copy("tests/images/rotated_CCW90.jpg", &image_path)?;
+
+        let rotated_path = autorotate(&image_path)?.unwrap();
+        assert_eq!(rotated_path, dir.join("rotated_CCW90_rotated.jpg"));
+        let rotated = crate::extract_metadata_from_bytes(&std::fs::read(&rotated_path)?)?;
+        assert_eq!(rotated.orientation, Some(1));
+        // The rest of the exif comes along
+        let original = crate::extract_metadata_from_bytes(&std::fs::read(&image_path)?)?;
+        assert_eq!(rotated.camera_model, original.camera_model);
+
+        let (width, height) = image::image_dimensions(&image_path)?;
+        assert_eq!(image::image_dimensions(&rotated_path)?, (height, width));
+
+        // Upright images are left be
+        assert_eq!(autorotate("tests/images/JAM26496.jpg")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_orientation() {
+        let jpeg = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let mut segment = jpeg::exif_segment(&jpeg).unwrap().to_vec();
+        // Pretending it was on its side
+        reset_orientation(&mut segment[10..], true).unwrap();
+        let exif = exif::Reader::new()
+            .read_raw(segment[10..].to_vec())
+            .unwrap();
+        let metadata = crate::exif_metadata(&exif);
+        assert_eq!(metadata.orientation, Some(1));
+        assert_eq!(metadata.pixel_width, Some(3360));
+        assert_eq!(metadata.pixel_height, Some(5040));
+    }
+}
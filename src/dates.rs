@@ -0,0 +1,63 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+/// The datetime formats accepted for date arguments, on top of a bare date
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    // As written in the exif itself
+    "%Y:%m:%d %H:%M:%S",
+];
+
+/// Parses a date argument like --since or --until as either a date, ie "2020-01-30", or
+/// a datetime, ie "2020-01-30T09:30:00". A bare date is taken as the very start of the
+/// day, or the very end of it when `end_of_day` is set, so a range of dates covers every
+/// image taken on them
+pub fn parse_date_bound(s: &str, end_of_day: bool) -> Result<NaiveDateTime, String> {
+    let s = s.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let time = if end_of_day {
+            NaiveTime::from_hms_nano(23, 59, 59, 999_999_999)
+        } else {
+            NaiveTime::from_hms(0, 0, 0)
+        };
+        return Ok(date.and_time(time));
+    }
+    DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .ok_or_else(|| {
+            format!(
+                "Expected a date like 2020-01-30 or a datetime like 2020-01-30T09:30:00 but got \"{}\"",
+                s
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date_bound() {
+        let date = NaiveDate::from_ymd(2020, 1, 30);
+        assert_eq!(
+            parse_date_bound("2020-01-30", false),
+            Ok(date.and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            parse_date_bound("2020-01-30", true),
+            Ok(date.and_hms_nano(23, 59, 59, 999_999_999))
+        );
+        assert_eq!(
+            parse_date_bound("2020-01-30T09:30:00", true),
+            Ok(date.and_hms(9, 30, 0))
+        );
+        assert_eq!(
+            parse_date_bound("2020-01-30 09:30", false),
+            Ok(date.and_hms(9, 30, 0))
+        );
+        assert!(parse_date_bound("30/01/2020", false).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+#[cfg(feature = "native")]
+use crate::{
+    TAG_AS_SHOT_NEUTRAL, TAG_COLOR_MATRIX_1, TAG_COLOR_MATRIX_2, TAG_NOISE_REDUCTION_APPLIED,
+    TAG_RATING, TAG_RATING_PERCENT, TAG_XP_KEYWORDS,
+};
+#[cfg(feature = "native")]
+use exif::{Exif, In, Tag, Value};
+use serde_json::{json, Map};
+
+/// The tags each of the fields we extract comes from, the first of them present in the
+/// image being the one that was used
+#[cfg(feature = "native")]
+const FIELD_TAGS: &[(&str, In, &[Tag])] = &[
+    ("orientation", In::PRIMARY, &[Tag::Orientation]),
+    ("thumbnail_orientation", In::THUMBNAIL, &[Tag::Orientation]),
+    ("thumbnail_width", In::THUMBNAIL, &[Tag::ImageWidth]),
+    ("thumbnail_height", In::THUMBNAIL, &[Tag::ImageLength]),
+    (
+        "pixel_width",
+        In::PRIMARY,
+        &[Tag::PixelXDimension, Tag::ImageWidth],
+    ),
+    (
+        "pixel_height",
+        In::PRIMARY,
+        &[Tag::PixelYDimension, Tag::ImageLength],
+    ),
+    ("capture_time", In::PRIMARY, &[Tag::DateTimeOriginal]),
+    ("digitized_time", In::PRIMARY, &[Tag::DateTimeDigitized]),
+    ("modify_datetime", In::PRIMARY, &[Tag::DateTime]),
+    (
+        "capture_time_offset",
+        In::PRIMARY,
+        &[Tag::OffsetTimeOriginal],
+    ),
+    ("timezone_offset", In::PRIMARY, &[Tag::MakerNote]),
+    ("camera_model", In::PRIMARY, &[Tag::Model]),
+    ("camera_serial", In::PRIMARY, &[Tag::BodySerialNumber]),
+    ("camera_owner", In::PRIMARY, &[Tag::CameraOwnerName]),
+    ("lens_model", In::PRIMARY, &[Tag::LensModel, Tag::MakerNote]),
+    ("lens_serial", In::PRIMARY, &[Tag::LensSerialNumber]),
+    ("lens_spec", In::PRIMARY, &[Tag::LensSpecification]),
+    ("image_unique_id", In::PRIMARY, &[Tag::ImageUniqueID]),
+    ("is_composite", In::PRIMARY, &[Tag::CompositeImage]),
+    ("description", In::PRIMARY, &[Tag::ImageDescription]),
+    ("rating", In::PRIMARY, &[TAG_RATING, TAG_RATING_PERCENT]),
+    ("keywords", In::PRIMARY, &[TAG_XP_KEYWORDS]),
+    ("sensing_method", In::PRIMARY, &[Tag::SensingMethod]),
+    ("file_source", In::PRIMARY, &[Tag::FileSource]),
+    ("compression", In::PRIMARY, &[Tag::Compression]),
+    (
+        "photometric_interpretation",
+        In::PRIMARY,
+        &[Tag::PhotometricInterpretation],
+    ),
+    ("thumbnail_compression", In::THUMBNAIL, &[Tag::Compression]),
+    ("gain_control", In::PRIMARY, &[Tag::GainControl]),
+    ("iso", In::PRIMARY, &[Tag::PhotographicSensitivity]),
+    ("sensitivity_type", In::PRIMARY, &[Tag::SensitivityType]),
+    (
+        "recommended_exposure_index",
+        In::PRIMARY,
+        &[Tag::RecommendedExposureIndex],
+    ),
+    (
+        "spectral_sensitivity",
+        In::PRIMARY,
+        &[Tag::SpectralSensitivity],
+    ),
+    ("oecf", In::PRIMARY, &[Tag::OECF]),
+    ("white_balance_kelvin", In::PRIMARY, &[Tag::MakerNote]),
+    (
+        "noise_reduction",
+        In::PRIMARY,
+        &[TAG_NOISE_REDUCTION_APPLIED],
+    ),
+    (
+        "long_exposure_noise_reduction",
+        In::PRIMARY,
+        &[Tag::MakerNote],
+    ),
+    ("camera_temperature_c", In::PRIMARY, &[Tag::MakerNote]),
+    ("af_points_used", In::PRIMARY, &[Tag::MakerNote]),
+    ("as_shot_neutral", In::PRIMARY, &[TAG_AS_SHOT_NEUTRAL]),
+    ("color_matrix_1", In::PRIMARY, &[TAG_COLOR_MATRIX_1]),
+    ("color_matrix_2", In::PRIMARY, &[TAG_COLOR_MATRIX_2]),
+    (
+        "exposure_time",
+        In::PRIMARY,
+        &[Tag::ExposureTime, Tag::ShutterSpeedValue],
+    ),
+    ("shutter_speed_apex", In::PRIMARY, &[Tag::ShutterSpeedValue]),
+    ("f_number", In::PRIMARY, &[Tag::FNumber, Tag::ApertureValue]),
+    ("aperture_apex", In::PRIMARY, &[Tag::ApertureValue]),
+    (
+        "subject_area",
+        In::PRIMARY,
+        &[Tag::SubjectArea, Tag::SubjectLocation],
+    ),
+    ("gps_latitude", In::PRIMARY, &[Tag::GPSLatitude]),
+    ("gps_longitude", In::PRIMARY, &[Tag::GPSLongitude]),
+    ("gps_datetime", In::PRIMARY, &[Tag::GPSTimeStamp]),
+    ("gps_differential", In::PRIMARY, &[Tag::GPSDifferential]),
+    ("gps_status", In::PRIMARY, &[Tag::GPSStatus]),
+    ("gps_measure_mode", In::PRIMARY, &[Tag::GPSMeasureMode]),
+    (
+        "gps_area_information",
+        In::PRIMARY,
+        &[Tag::GPSAreaInformation],
+    ),
+    ("gps_version", In::PRIMARY, &[Tag::GPSVersionID]),
+    (
+        "gps_h_positioning_error_m",
+        In::PRIMARY,
+        &[Tag::GPSHPositioningError],
+    ),
+    ("gps_dest_latitude", In::PRIMARY, &[Tag::GPSDestLatitude]),
+    ("gps_dest_longitude", In::PRIMARY, &[Tag::GPSDestLongitude]),
+    ("gps_dest_bearing", In::PRIMARY, &[Tag::GPSDestBearing]),
+    ("gps_track", In::PRIMARY, &[Tag::GPSTrack]),
+    ("gps_track_ref", In::PRIMARY, &[Tag::GPSTrackRef]),
+];
+
+/// The raw exif type and component count of the tag a field was extracted from
+#[derive(PartialEq, Debug, Clone)]
+pub struct TagType {
+    pub field: &'static str,
+    /// The type as named in the exif spec, ie "SHORT"
+    pub type_name: &'static str,
+    pub count: usize,
+}
+
+/// Records the types of the tags behind each of the fields we extract, only needed when
+/// reading images with the debug_tags option
+#[cfg(feature = "native")]
+pub(crate) fn tag_types(exif: &Exif) -> Vec<TagType> {
+    FIELD_TAGS
+        .iter()
+        .filter_map(|(field, ifd, tags)| {
+            let value = tags
+                .iter()
+                .find_map(|tag| exif.get_field(*tag, *ifd))
+                .map(|f| &f.value)?;
+            let (type_name, count) = type_and_count(value);
+            Some(TagType {
+                field,
+                type_name,
+                count,
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "native")]
+fn type_and_count(value: &Value) -> (&'static str, usize) {
+    match value {
+        Value::Byte(values) => ("BYTE", values.len()),
+        // Each string is NUL terminated in the file
+        Value::Ascii(strings) => ("ASCII", strings.iter().map(|s| s.len() + 1).sum()),
+        Value::Short(values) => ("SHORT", values.len()),
+        Value::Long(values) => ("LONG", values.len()),
+        Value::Rational(values) => ("RATIONAL", values.len()),
+        Value::SByte(values) => ("SBYTE", values.len()),
+        Value::Undefined(bytes, _) => ("UNDEFINED", bytes.len()),
+        Value::SShort(values) => ("SSHORT", values.len()),
+        Value::SLong(values) => ("SLONG", values.len()),
+        Value::SRational(values) => ("SRATIONAL", values.len()),
+        Value::Float(values) => ("FLOAT", values.len()),
+        Value::Double(values) => ("DOUBLE", values.len()),
+        Value::Unknown(_, count, _) => ("UNKNOWN", *count as usize),
+    }
+}
+
+/// Replaces each field's value with `{value, type, count}` for the fields we know the
+/// tag types of
+pub(crate) fn add_tag_types(map: &mut Map<String, serde_json::Value>, tag_types: &[TagType]) {
+    for tag_type in tag_types {
+        if let Some(value) = map.get_mut(tag_type.field) {
+            *value = json!({
+                "value": value.take(),
+                "type": tag_type.type_name,
+                "count": tag_type.count,
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use crate::{to_output_value, Options};
+    use serde_json::json;
+
+    #[test]
+    fn test_debug_tags() {
+        let path = "tests/images/JAM19896.jpg";
+        // The tag types are only read when they're wanted
+        let metadata = crate::extract_file_with_options(path, &Options::default())
+            .unwrap()
+            .unwrap();
+        assert!(metadata.image_metadata.tag_types.is_empty());
+
+        let options = Options {
+            debug_tags: true,
+            ..Default::default()
+        };
+        let metadata = crate::extract_file_with_options(path, &options)
+            .unwrap()
+            .unwrap();
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(
+            output["orientation"],
+            json!({"value": 1, "type": "SHORT", "count": 1})
+        );
+        // Derived fields don't come from any one tag so are left alone
+        assert_eq!(output["orientation_degrees"], json!(0));
+        assert_eq!(output["filename"], json!("JAM19896.jpg"));
+    }
+}
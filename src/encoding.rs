@@ -0,0 +1,120 @@
+use crate::ImageMetadata;
+use exif::{Exif, In, Tag};
+use std::str::FromStr;
+
+/// How to decode the bytes of the free text exif fields. They're meant to be ASCII but
+/// plenty of older cameras write Latin-1 or Shift-JIS into them instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StringEncoding {
+    /// Anything that isn't valid UTF-8 comes out as replacement characters
+    Utf8,
+    /// Decoded as windows-1252, the superset of Latin-1 that's usually what's meant
+    Latin1,
+    /// Common on older Japanese cameras
+    ShiftJis,
+}
+
+impl Default for StringEncoding {
+    fn default() -> Self {
+        StringEncoding::Utf8
+    }
+}
+
+/// Parses "utf8", "latin1" or "shiftjis" as passed on the command line
+impl FromStr for StringEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(StringEncoding::Utf8),
+            "latin1" => Ok(StringEncoding::Latin1),
+            "shiftjis" => Ok(StringEncoding::ShiftJis),
+            _ => Err(format!(
+                "Expected utf8, latin1 or shiftjis but got \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+impl StringEncoding {
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let encoding = match self {
+            StringEncoding::Utf8 => encoding_rs::UTF_8,
+            StringEncoding::Latin1 => encoding_rs::WINDOWS_1252,
+            StringEncoding::ShiftJis => encoding_rs::SHIFT_JIS,
+        };
+        encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+}
+
+/// Decodes the free text fields again from the raw exif bytes in the given encoding. The
+/// rest of the string fields are dates, offsets and the like which are always ASCII
+pub(crate) fn decode_strings(metadata: &mut ImageMetadata, exif: &Exif, encoding: StringEncoding) {
+    for (field, tag) in vec![
+        (&mut metadata.camera_model, Tag::Model),
+        (&mut metadata.camera_serial, Tag::BodySerialNumber),
+        (&mut metadata.camera_owner, Tag::CameraOwnerName),
+        (&mut metadata.lens_serial, Tag::LensSerialNumber),
+        (&mut metadata.image_unique_id, Tag::ImageUniqueID),
+    ] {
+        if let Some(bytes) = raw_ascii(exif, tag) {
+            *field = Some(encoding.decode(&bytes));
+        }
+    }
+    if let Some(bytes) = raw_ascii(exif, Tag::ImageDescription) {
+        metadata.description = crate::trim_description(encoding.decode(&bytes));
+    }
+}
+
+fn raw_ascii(exif: &Exif, tag: Tag) -> Option<Vec<u8>> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        exif::Value::Ascii(raw) => Some(raw.concat()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::{Field, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_latin1_camera_model() {
+        let model = Field {
+            tag: Tag::Model,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"Appareil r\xe9flex".to_vec()]),
+        };
+        let mut writer = Writer::new();
+        writer.push_field(&model);
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        let tiff = buf.into_inner();
+
+        let metadata = crate::extract_metadata_from_bytes(&tiff).unwrap();
+        assert_eq!(
+            metadata.camera_model.as_deref(),
+            Some("Appareil r\u{fffd}flex")
+        );
+
+        let metadata = crate::extract_metadata_from_reader_with_encoding(
+            &mut Cursor::new(&tiff),
+            StringEncoding::Latin1,
+        )
+        .unwrap();
+        assert_eq!(metadata.camera_model.as_deref(), Some("Appareil réflex"));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            StringEncoding::ShiftJis.decode(b"\x83J\x83\x81\x83\x89"),
+            "カメラ"
+        );
+        assert_eq!(StringEncoding::Latin1.decode(b"caf\xe9"), "café");
+        assert_eq!(StringEncoding::Utf8.decode("café".as_bytes()), "café");
+    }
+}
@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+/// Mean radius of the earth in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// The great circle distance in kilometers between two points given as
+/// (latitude, longitude) in decimal degrees
+pub fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// The digits geohashes are written in, base 32 without a, i, l or o
+const GEOHASH_BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The number of characters geohashes are worked out to by default, 9 characters being a
+/// cell of roughly 5m by 5m
+pub const DEFAULT_GEOHASH_PRECISION: usize = 9;
+
+/// Encodes a point given in decimal degrees as a geohash of the given number of
+/// characters, each character narrowing the cell down by 5 bits alternating between
+/// longitude and latitude, so nearby points share a prefix
+pub fn geohash(latitude: f64, longitude: f64, precision: usize) -> String {
+    let mut latitude_range = (-90.0, 90.0);
+    let mut longitude_range = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even_bit = true;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if even_bit {
+                (&mut longitude_range, longitude)
+            } else {
+                (&mut latitude_range, latitude)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even_bit = !even_bit;
+        }
+        hash.push(GEOHASH_BASE32[index] as char);
+    }
+    hash
+}
+
+/// A circular area around a point, used to select images taken near somewhere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Near {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl Near {
+    /// Whether the given coordinates fall within the area
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        haversine_km((self.latitude, self.longitude), (latitude, longitude)) <= self.radius_km
+    }
+}
+
+/// Parses "LAT,LON,RADIUS_KM" as passed on the command line
+impl FromStr for Near {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid number in \"{}\": {}", s, err))?;
+        match parts.as_slice() {
+            &[latitude, longitude, radius_km] => Ok(Near {
+                latitude,
+                longitude,
+                radius_km,
+            }),
+            _ => Err(format!("Expected LAT,LON,RADIUS_KM but got \"{}\"", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_haversine_km() {
+        assert_close(haversine_km((51.5074, -0.1278), (51.5074, -0.1278)), 0.0);
+        // One degree of longitude at the equator
+        assert_close(haversine_km((0.0, 0.0), (0.0, 1.0)), 111.195);
+        // London to Paris
+        assert_close(haversine_km((51.5074, -0.1278), (48.8566, 2.3522)), 343.556);
+        // Auckland to Wellington
+        assert_close(
+            haversine_km((-36.8485, 174.7633), (-41.2865, 174.7762)),
+            493.484,
+        );
+    }
+
+    #[test]
+    fn test_geohash() {
+        // The example from the geohash wikipedia page
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(57.64911, 10.40744, 5), "u4pru");
+        assert_eq!(geohash(0.0, 0.0, 1), "s");
+        assert_eq!(geohash(57.64911, 10.40744, 0), "");
+    }
+
+    #[test]
+    fn test_near() {
+        let near: Near = "-44.7, 169.16, 60".parse().unwrap();
+        assert_eq!(
+            near,
+            Near {
+                latitude: -44.7,
+                longitude: 169.16,
+                radius_km: 60.0
+            }
+        );
+        // Queenstown is ~54km from Wanaka
+        assert!(near.contains(-45.0312, 168.6626));
+        assert!(!near.contains(-36.8485, 174.7633));
+
+        assert!("-44.7,169.16".parse::<Near>().is_err());
+        assert!("-44.7,east,60".parse::<Near>().is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::template::UNKNOWN;
+use crate::{output, CombinedMetadata, Options};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Images grouped by the day they were captured, ie "2019-07-26", for building date
+/// organised albums. Images without a capture time go under "unknown"
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct DayGroups {
+    days: BTreeMap<String, Vec<Value>>,
+}
+
+impl DayGroups {
+    /// Adds an image under its day, laid out as per the output options
+    pub fn add(
+        &mut self,
+        metadata: &CombinedMetadata,
+        options: &Options,
+    ) -> serde_json::Result<()> {
+        let day = metadata.image_metadata.capture_time.map_or_else(
+            || UNKNOWN.to_string(),
+            |time| time.format("%Y-%m-%d").to_string(),
+        );
+        let value = output::to_output_value(metadata, options)?;
+        self.days.entry(day).or_default().push(value);
+        Ok(())
+    }
+}
+
+/// Images keyed by their filename, for use as a lookup table. Filenames that turn up more
+/// than once, ie from different directories, are keyed by their path instead
+#[derive(Debug, Default)]
+pub struct FilenameMap {
+    /// The path, filename and output of each image
+    images: Vec<(String, String, Value)>,
+}
+
+impl FilenameMap {
+    /// Adds an image, laid out as per the output options
+    pub fn add<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        metadata: &CombinedMetadata,
+        options: &Options,
+    ) -> serde_json::Result<()> {
+        let value = output::to_output_value(metadata, options)?;
+        self.images.push((
+            path.as_ref().to_string_lossy().into_owned(),
+            metadata.file_metadata.filename.clone(),
+            value,
+        ));
+        Ok(())
+    }
+}
+
+impl Serialize for FilenameMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut counts = HashMap::new();
+        for (_, filename, _) in &self.images {
+            *counts.entry(filename).or_insert(0) += 1;
+        }
+        let mut map = serializer.serialize_map(Some(self.images.len()))?;
+        for (path, filename, value) in &self.images {
+            let key = if counts[filename] > 1 { path } else { filename };
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileMetadata, ImageMetadata};
+    use chrono::NaiveDate;
+
+    fn image(filename: &str, capture_date: Option<(i32, u32, u32)>) -> CombinedMetadata {
+        let file_metadata = FileMetadata {
+            filename: filename.to_string(),
+            size: 1,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            capture_time: capture_date
+                .map(|(year, month, day)| NaiveDate::from_ymd(year, month, day).and_hms(9, 0, 0)),
+            ..Default::default()
+        };
+        CombinedMetadata::new(file_metadata, image_metadata)
+    }
+
+    #[test]
+    fn test_day_groups() {
+        let options = Options {
+            include: Some(vec!["filename".to_string()]),
+            ..Default::default()
+        };
+        let mut groups = DayGroups::default();
+        groups.add(&image("c.jpg", None), &options).unwrap();
+        groups
+            .add(&image("b.jpg", Some((2020, 1, 30))), &options)
+            .unwrap();
+        groups
+            .add(&image("a.jpg", Some((2019, 7, 26))), &options)
+            .unwrap();
+        groups
+            .add(&image("d.jpg", Some((2020, 1, 30))), &options)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&groups).unwrap(),
+            r#"{"2019-07-26":[{"filename":"a.jpg"}],"2020-01-30":[{"filename":"b.jpg"},{"filename":"d.jpg"}],"unknown":[{"filename":"c.jpg"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_filename_map() {
+        let options = Options {
+            include: Some(vec!["filename".to_string()]),
+            ..Default::default()
+        };
+        let mut map = FilenameMap::default();
+        map.add("2019/a.jpg", &image("a.jpg", None), &options)
+            .unwrap();
+        map.add("2019/b.jpg", &image("b.jpg", None), &options)
+            .unwrap();
+        map.add("2020/a.jpg", &image("a.jpg", None), &options)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&map).unwrap(),
+            r#"{"2019/a.jpg":{"filename":"a.jpg"},"b.jpg":{"filename":"b.jpg"},"2020/a.jpg":{"filename":"a.jpg"}}"#
+        );
+    }
+}
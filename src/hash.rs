@@ -0,0 +1,335 @@
+use crate::recompute::read_output_file;
+use crate::{FileMetadata, ImageError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The algorithms files can be checksummed with, MD5 and BLAKE3 need the checksums
+/// feature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    /// Broken for security purposes but still used by plenty of catalogs
+    #[cfg(feature = "checksums")]
+    Md5,
+    #[cfg(feature = "checksums")]
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// The names of the algorithms built in, as passed on the command line
+    pub const NAMES: &'static [&'static str] = &[
+        "sha256",
+        #[cfg(feature = "checksums")]
+        "md5",
+        #[cfg(feature = "checksums")]
+        "blake3",
+    ];
+
+    /// The name of the algorithm, which is also the field the checksum is output as
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Md5 => "md5",
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The checksum of everything left in the reader as lowercase hex
+    pub(crate) fn checksum_reader<R: Read>(self, reader: R) -> std::io::Result<String> {
+        match self {
+            ChecksumAlgorithm::Sha256 => sha256_reader(reader),
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Md5 => {
+                let mut reader = reader;
+                let mut hasher = md5::Md5::new();
+                std::io::copy(&mut reader, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Blake3 => {
+                let mut reader = reader;
+                let mut hasher = blake3::Hasher::new();
+                std::io::copy(&mut reader, &mut hasher)?;
+                Ok(blake3::Hasher::finalize(&hasher).to_hex().to_string())
+            }
+        }
+    }
+}
+
+impl Default for ChecksumAlgorithm {
+    fn default() -> Self {
+        ChecksumAlgorithm::Sha256
+    }
+}
+
+/// Parses the algorithm names in `ChecksumAlgorithm::NAMES`
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            #[cfg(feature = "checksums")]
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            #[cfg(feature = "checksums")]
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(format!(
+                "Expected one of {} but got \"{}\"",
+                ChecksumAlgorithm::NAMES.join(", "),
+                s
+            )),
+        }
+    }
+}
+
+impl FileMetadata {
+    /// Records the checksum in the field for its algorithm
+    pub(crate) fn set_checksum(&mut self, algorithm: ChecksumAlgorithm, checksum: String) {
+        let field = match algorithm {
+            ChecksumAlgorithm::Sha256 => &mut self.sha256,
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Md5 => &mut self.md5,
+            #[cfg(feature = "checksums")]
+            ChecksumAlgorithm::Blake3 => &mut self.blake3,
+        };
+        *field = Some(checksum);
+    }
+}
+
+/// The SHA-256 of a file's contents as lowercase hex
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    sha256_reader(File::open(path)?)
+}
+
+/// The checksum of a file's contents with the given algorithm as lowercase hex
+pub fn checksum_file<P: AsRef<Path>>(
+    path: P,
+    algorithm: ChecksumAlgorithm,
+) -> std::io::Result<String> {
+    algorithm.checksum_reader(File::open(path)?)
+}
+
+/// The SHA-256 of everything left in the reader as lowercase hex
+pub(crate) fn sha256_reader<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The outcome of checking an image against the checksum recorded in its json
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    /// The image still hashes to the recorded checksum
+    Unchanged(PathBuf),
+    /// The image's contents differ from when the json was written, ie from bit rot
+    Changed {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// The json doesn't have a checksum to check against
+    NoChecksum(PathBuf),
+    /// The image couldn't be read to check it, ie as it's been deleted, with the error
+    /// we got trying
+    Unreadable { path: PathBuf, error: String },
+}
+
+/// Rehashes the images referenced by previously written json, as written with the
+/// sha256 or checksum algorithm options, to check they haven't changed since. Whichever
+/// checksum was recorded is checked, the first of them in `ChecksumAlgorithm::NAMES`
+/// built in if there's more than one. The images are expected to be next to the json,
+/// as is the default. Images that can't be read don't stop the rest being checked
+pub fn verify_file<P: AsRef<Path>>(json_path: P) -> Result<Vec<Verification>, ImageError> {
+    let (values, _) = read_output_file(json_path.as_ref())?;
+    let dir = json_path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+
+    let mut verifications = vec![];
+    for value in values {
+        let filename = value
+            .get("filename")
+            .and_then(|filename| filename.as_str())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing the filename")
+            })?;
+        let path = dir.join(filename);
+        let recorded = ChecksumAlgorithm::NAMES.iter().find_map(|name| {
+            let expected = value.get(*name)?.as_str()?;
+            Some((name.parse::<ChecksumAlgorithm>().ok()?, expected))
+        });
+        let verification = match recorded {
+            Some((algorithm, expected)) => match checksum_file(&path, algorithm) {
+                Ok(actual) if actual == expected => Verification::Unchanged(path),
+                Ok(actual) => Verification::Changed {
+                    path,
+                    expected: expected.to_string(),
+                    actual,
+                },
+                Err(error) => Verification::Unreadable {
+                    path,
+                    error: error.to_string(),
+                },
+            },
+            None => Verification::NoChecksum(path),
+        };
+        verifications.push(verification);
+    }
+    Ok(verifications)
+}
+
+/// Images with identical contents, going by their SHA-256
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub paths: Vec<String>,
+}
+
+/// Collects up images by their SHA-256 to find the duplicates among them
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    by_hash: BTreeMap<String, Vec<String>>,
+}
+
+impl Duplicates {
+    /// Hashes an image, the path is kept as given
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let hash = sha256_file(&path)?;
+        self.by_hash
+            .entry(hash)
+            .or_default()
+            .push(path.as_ref().to_string_lossy().into_owned());
+        Ok(())
+    }
+
+    /// The groups of images that share their contents with at least one other, ordered
+    /// by hash
+    pub fn groups(&self) -> Vec<DuplicateGroup> {
+        self.by_hash
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(hash, paths)| DuplicateGroup {
+                hash: hash.clone(),
+                paths: paths.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_file() -> std::io::Result<()> {
+        assert_eq!(
+            sha256_file("tests/images/JAM19896.jpg")?,
+            "1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_file() -> std::io::Result<()> {
+        let path = "tests/images/JAM19896.jpg";
+        assert_eq!(
+            checksum_file(path, ChecksumAlgorithm::default())?,
+            sha256_file(path)?
+        );
+        assert_eq!("sha256".parse(), Ok(ChecksumAlgorithm::Sha256));
+        assert!("crc32".parse::<ChecksumAlgorithm>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "checksums")]
+    fn test_md5_and_blake3() -> std::io::Result<()> {
+        let path = "tests/images/JAM19896.jpg";
+        assert_eq!(
+            checksum_file(path, ChecksumAlgorithm::Md5)?,
+            "868580d3af7fce17a1d09f4d364d9dab"
+        );
+        assert_eq!(
+            checksum_file(path, ChecksumAlgorithm::Blake3)?,
+            "bb30743febd9ecc70ea0b3e0a66176d2fa134cbbb13bcebeaea5c9b854257969"
+        );
+        // The test vector for empty input
+        assert_eq!(
+            ChecksumAlgorithm::Blake3.checksum_reader(&b""[..])?,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!("blake3".parse(), Ok(ChecksumAlgorithm::Blake3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_file() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/verify_file");
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("JAM19896.jpg");
+        let json_path = dir.join("JAM19896.json");
+        std::fs::copy("tests/images/JAM19896.jpg", &image_path)?;
+        std::fs::write(
+            &json_path,
+            r#"{"filename":"JAM19896.jpg","size":953458,"sha256":"1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3"}"#,
+        )?;
+        assert_eq!(
+            verify_file(&json_path)?,
+            vec![Verification::Unchanged(image_path.clone())]
+        );
+
+        // Flip a bit in the middle of the image
+        let mut bytes = std::fs::read(&image_path)?;
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        std::fs::write(&image_path, bytes)?;
+        match verify_file(&json_path)?.as_slice() {
+            [Verification::Changed { path, .. }] => assert_eq!(path, &image_path),
+            other => panic!("Expected the image to have changed, got {:?}", other),
+        }
+
+        // A missing image gets reported along with the rest
+        let missing_path = dir.join("missing.jpg");
+        std::fs::write(
+            &json_path,
+            r#"[{"filename":"missing.jpg","sha256":"00"},{"filename":"JAM19896.jpg"}]"#,
+        )?;
+        match verify_file(&json_path)?.as_slice() {
+            [Verification::Unreadable { path, .. }, Verification::NoChecksum(_)] => {
+                assert_eq!(path, &missing_path)
+            }
+            other => panic!("Expected the image to be unreadable, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicates() -> std::io::Result<()> {
+        let dir = Path::new("target/test/duplicates");
+        std::fs::create_dir_all(dir)?;
+        let copy = dir.join("copy.jpg");
+        std::fs::copy("tests/images/JAM19896.jpg", &copy)?;
+
+        let mut duplicates = Duplicates::default();
+        duplicates.add("tests/images/JAM19896.jpg")?;
+        duplicates.add("tests/images/JAM26284.jpg")?;
+        duplicates.add(&copy)?;
+        assert_eq!(
+            duplicates.groups(),
+            vec![DuplicateGroup {
+                hash: "1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3"
+                    .to_string(),
+                paths: vec![
+                    "tests/images/JAM19896.jpg".to_string(),
+                    copy.to_string_lossy().into_owned()
+                ],
+            }]
+        );
+        Ok(())
+    }
+}
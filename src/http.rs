@@ -0,0 +1,122 @@
+use crate::native::{apply_image_options, extract_reader_with_options};
+use crate::{retry, CombinedMetadata, FileMetadata, ImageError, Options, DEFAULT_RETRIES};
+use std::io::{Cursor, Read};
+
+/// Whether a file argument is actually the URL of an image to download
+pub fn is_url(path: &str) -> bool {
+    let lowercase = path.to_ascii_lowercase();
+    lowercase.starts_with("http://") || lowercase.starts_with("https://")
+}
+
+/// Downloads the image at the URL and extracts its metadata following the given options,
+/// returns None if the image was skipped as it didn't match the filters. There's no
+/// filesystem to ask so only the size and filename, the last part of the URL's path, are
+/// filled in for the file metadata
+pub fn fetch_url_with_options(
+    url: &str,
+    options: &Options,
+) -> Result<Option<CombinedMetadata>, ImageError> {
+    let retries = options.retries.unwrap_or(DEFAULT_RETRIES);
+    let bytes = retry::with_retries(retries, || {
+        let response = ureq::get(url)
+            .call()
+            .map_err(|error| ImageError::HttpError(Box::new(error)))?;
+        let mut bytes = vec![];
+        response.into_reader().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })?;
+    let image_metadata = apply_image_options(
+        extract_reader_with_options(&mut Cursor::new(&bytes), options),
+        options,
+    )?;
+    let file_metadata = FileMetadata {
+        filename: url_filename(url).to_string(),
+        size: bytes.len() as u64,
+        ..Default::default()
+    };
+    let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+    if options.selects(&metadata) {
+        Ok(Some(metadata))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The last segment of the URL's path, ie photo.jpg for
+/// https://example.com/images/photo.jpg?width=100
+fn url_filename(url: &str) -> &str {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    let path = path.splitn(2, "://").nth(1).unwrap_or(path);
+    match path.find('/') {
+        Some(start) => path[start..]
+            .rsplit('/')
+            .find(|segment| !segment.is_empty())
+            .unwrap_or_default(),
+        None => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serves the given file for a single request
+    fn serve_once(path: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            // Read the request up to the blank line after the headers
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let body = std::fs::read(path).unwrap();
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        format!("http://{}/photos/JAM19896.jpg?download=1", address)
+    }
+
+    #[test]
+    fn test_fetch_url() -> Result<(), ImageError> {
+        let url = serve_once("tests/images/JAM19896.jpg");
+        let metadata = fetch_url_with_options(&url, &Options::default())?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "JAM19896.jpg");
+        assert_eq!(metadata.file_metadata.size, 953458);
+        assert_eq!(metadata.file_metadata.modified_time, None);
+        assert_eq!(
+            metadata.image_metadata.camera_model.as_deref(),
+            Some("Canon EOS 5D Mark IV")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/photo.jpg"));
+        assert!(is_url("HTTP://example.com/photo.jpg"));
+        assert!(!is_url("photos/http/photo.jpg"));
+        assert!(!is_url("ftp://example.com/photo.jpg"));
+    }
+
+    #[test]
+    fn test_url_filename() {
+        assert_eq!(url_filename("https://example.com/a/photo.jpg"), "photo.jpg");
+        assert_eq!(
+            url_filename("https://example.com/photo.jpg?w=1#top"),
+            "photo.jpg"
+        );
+        assert_eq!(url_filename("https://example.com/photos/"), "photos");
+        assert_eq!(url_filename("https://example.com"), "");
+    }
+}
@@ -0,0 +1,481 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Start of image, every jpeg starts with this marker
+const SOI: [u8; 2] = [0xFF, 0xD8];
+/// The application segment exif is stored in
+const APP1: u8 = 0xE1;
+/// The application segment ICC profiles are stored in
+const APP2: u8 = 0xE2;
+/// Start of scan, the compressed image data follows so there's no more metadata to find
+const SOS: u8 = 0xDA;
+/// End of image
+const EOI: u8 = 0xD9;
+/// The markers in 0xC0..=0xCF that aren't start of frame, ie the huffman and arithmetic
+/// coding tables and the JPG extension
+const DHT: u8 = 0xC4;
+const JPG: u8 = 0xC8;
+const DAC: u8 = 0xCC;
+/// The identifier at the start of an APP1 segment holding exif, as opposed to XMP
+const EXIF_ID: &[u8] = b"Exif\0\0";
+/// The identifier at the start of an APP2 segment holding (part of) an ICC profile
+const ICC_PROFILE_ID: &[u8] = b"ICC_PROFILE\0";
+/// The identifier at the start of an APP2 segment holding a multi-picture index
+const MPF_ID: &[u8] = b"MPF\0";
+/// The MP index tags for the number of images and the entries describing each of them
+const MPF_NUMBER_OF_IMAGES: u16 = 0xB001;
+const MPF_ENTRY: u16 = 0xB002;
+/// Each MP entry is 16 bytes, the image attributes, size, offset and two dependent
+/// image entry numbers
+const MPF_ENTRY_LEN: usize = 16;
+
+/// Whether the start of a file is a jpeg that definitely has no exif, going by the
+/// segments before the image data. Running the exif reader over these only to have it
+/// fail is comparatively slow. Anything we can't be sure of, ie the segments running
+/// past the bytes we were given, returns false so the exif reader gets a look at it
+pub fn lacks_exif(header: &[u8]) -> bool {
+    if !header.starts_with(&SOI) {
+        return false;
+    }
+    let mut pos = SOI.len();
+    loop {
+        let marker = match header.get(pos..pos + 2) {
+            Some(&[0xFF, marker]) => marker,
+            _ => return false,
+        };
+        match marker {
+            // Padding before the actual marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            SOS | EOI => return true,
+            // Restart and TEM markers have no length or data
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let length = match header.get(pos + 2..pos + 4) {
+            Some(&[high, low]) => u16::from_be_bytes([high, low]) as usize,
+            _ => return false,
+        };
+        if length < 2 {
+            return false;
+        }
+        if marker == APP1 {
+            match header.get(pos + 4..pos + 4 + EXIF_ID.len()) {
+                Some(id) if id != EXIF_ID => {}
+                _ => return false,
+            }
+        }
+        pos += 2 + length;
+    }
+}
+
+/// The APP1 segment holding the exif, from its marker to the end of its data, or None
+/// if the jpeg has no exif or is cut short before we find it
+#[cfg(feature = "image")]
+pub fn exif_segment(jpeg: &[u8]) -> Option<&[u8]> {
+    if !jpeg.starts_with(&SOI) {
+        return None;
+    }
+    let mut pos = SOI.len();
+    loop {
+        let marker = match jpeg.get(pos..pos + 2)? {
+            &[0xFF, marker] => marker,
+            _ => return None,
+        };
+        match marker {
+            // Padding before the actual marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            SOS | EOI => return None,
+            // Restart and TEM markers have no length or data
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let length = match jpeg.get(pos + 2..pos + 4)? {
+            &[high, low] => u16::from_be_bytes([high, low]) as usize,
+            _ => return None,
+        };
+        let segment = jpeg.get(pos..pos + 2 + length)?;
+        if marker == APP1 && segment.get(4..4 + EXIF_ID.len()) == Some(EXIF_ID) {
+            return Some(segment);
+        }
+        pos += 2 + length;
+    }
+}
+
+/// What we found in the segments of a jpeg beyond the exif
+#[derive(PartialEq, Debug, Default)]
+pub struct Segments {
+    /// Whether there's an embedded ICC profile
+    pub has_icc_profile: bool,
+    /// The images in a multi-picture file, ie the main image and its depth map
+    pub mpf: Option<Mpf>,
+    /// The number of color components from the start of frame, 1 for grayscale, 3 for
+    /// YCbCr or RGB and 4 for CMYK
+    pub num_components: Option<u8>,
+}
+
+/// The index of the images in a multi-picture (MPF) file
+#[derive(PartialEq, Debug)]
+pub struct Mpf {
+    pub image_count: u32,
+    pub images: Vec<MpfImage>,
+}
+
+/// Where one of the images in a multi-picture file is, for splitting them out
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct MpfImage {
+    /// The byte offset of the image from the start of the file
+    pub offset: u64,
+    /// The size of the image in bytes
+    pub size: u32,
+}
+
+/// Scans the segments of a jpeg for an embedded ICC profile, a multi-picture index and
+/// the number of components in the start of frame, returning None if it isn't a jpeg or
+/// is cut short before the image data. These tend to come after the exif so this seeks
+/// through the segments rather than needing them all in memory, leaving the reader
+/// where it started
+pub fn scan_segments<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<Segments>> {
+    let start = reader.seek(SeekFrom::Current(0))?;
+    // Running off the end of the file just means we can't tell
+    let segments = read_segments(reader).unwrap_or(None);
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(segments)
+}
+
+fn read_segments<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<Segments>> {
+    let read_u8 = |reader: &mut R| {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map(|_| byte[0])
+    };
+    if [read_u8(reader)?, read_u8(reader)?] != SOI {
+        return Ok(None);
+    }
+    let mut segments = Segments::default();
+    loop {
+        if read_u8(reader)? != 0xFF {
+            return Ok(None);
+        }
+        let mut marker = read_u8(reader)?;
+        // Padding before the actual marker
+        while marker == 0xFF {
+            marker = read_u8(reader)?;
+        }
+        match marker {
+            SOS | EOI => return Ok(Some(segments)),
+            // Restart and TEM markers have no length or data
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let length = u16::from_be_bytes([read_u8(reader)?, read_u8(reader)?]) as i64;
+        if length < 2 {
+            return Ok(None);
+        }
+        match marker {
+            // The precision, height and width come before the number of components
+            0xC0..=0xCF if marker != DHT && marker != JPG && marker != DAC && length >= 8 => {
+                let mut frame = [0; 6];
+                reader.read_exact(&mut frame)?;
+                segments.num_components = Some(frame[5]);
+                reader.seek(SeekFrom::Current(length - 8))?;
+                continue;
+            }
+            APP2 => {}
+            _ => {
+                reader.seek(SeekFrom::Current(length - 2))?;
+                continue;
+            }
+        }
+        let data_start = reader.seek(SeekFrom::Current(0))?;
+        let mut data = vec![0; length as usize - 2];
+        reader.read_exact(&mut data)?;
+        if data.starts_with(ICC_PROFILE_ID) {
+            segments.has_icc_profile = true;
+        } else if data.starts_with(MPF_ID) && segments.mpf.is_none() {
+            let header_offset = data_start + MPF_ID.len() as u64;
+            segments.mpf = parse_mpf(&data[MPF_ID.len()..], header_offset);
+        }
+    }
+}
+
+/// Parses the MP index, a little tiff of its own, out of an MPF segment. The image
+/// offsets in it are relative to the start of its tiff header, at `header_offset` in the
+/// file, apart from the first image's which is always 0 as it's the file itself
+fn parse_mpf(tiff: &[u8], header_offset: u64) -> Option<Mpf> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |pos: usize| {
+        let bytes = [
+            *tiff.get(pos)?,
+            *tiff.get(pos + 1)?,
+            *tiff.get(pos + 2)?,
+            *tiff.get(pos + 3)?,
+        ];
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let mut image_count = None;
+    let mut entries = (0, 0);
+    for i in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        match u16_at(entry)? {
+            MPF_NUMBER_OF_IMAGES => image_count = u32_at(entry + 8),
+            MPF_ENTRY => entries = (u32_at(entry + 8)? as usize, u32_at(entry + 4)? as usize),
+            _ => {}
+        }
+    }
+
+    let image_count = image_count?;
+    let (entries_offset, entries_len) = entries;
+    let images = (0..entries_len / MPF_ENTRY_LEN)
+        .map(|i| {
+            // Skipping the image attributes at the start of each entry
+            let entry = entries_offset + i * MPF_ENTRY_LEN;
+            let size = u32_at(entry + 4)?;
+            let offset = match i {
+                0 => 0,
+                _ => header_offset + u32_at(entry + 8)? as u64,
+            };
+            Some(MpfImage { offset, size })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Mpf {
+        image_count,
+        images,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A jpeg with a JFIF header and an XMP segment but no exif, with the image data
+    /// cut short as we never look at it
+    fn jpeg_without_exif() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0, 16]);
+        jpeg.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>";
+        jpeg.extend_from_slice(&[0xFF, APP1]);
+        jpeg.extend_from_slice(&(xmp.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(xmp);
+        jpeg.extend_from_slice(&[0xFF, SOS, 0, 2]);
+        jpeg
+    }
+
+    #[test]
+    fn test_lacks_exif() {
+        assert!(lacks_exif(&jpeg_without_exif()));
+
+        for image in &[
+            "JAM19896.jpg",
+            "JAM26284.jpg",
+            "JAM26496.jpg",
+            "rotated_CCW90.jpg",
+        ] {
+            let bytes = std::fs::read(format!("tests/images/{}", image)).unwrap();
+            assert!(!lacks_exif(&bytes), "{} has exif", image);
+            // Only being given the start of a file shouldn't change anything
+            assert!(!lacks_exif(&bytes[..1024]), "{} has exif", image);
+        }
+
+        // Segments running off the end of what we've got might be followed by exif
+        let jpeg = jpeg_without_exif();
+        assert!(!lacks_exif(&jpeg[..jpeg.len() - 4]));
+        assert!(!lacks_exif(b"not a jpeg"));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_exif_segment() {
+        assert_eq!(exif_segment(&jpeg_without_exif()), None);
+        assert_eq!(exif_segment(b"not a jpeg"), None);
+
+        let jpeg = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let segment = exif_segment(&jpeg).unwrap();
+        assert_eq!(&segment[..2], &[0xFF, APP1]);
+        assert_eq!(&segment[4..10], EXIF_ID);
+        assert_eq!(
+            segment.len(),
+            2 + u16::from_be_bytes([segment[2], segment[3]]) as usize
+        );
+    }
+
+    #[test]
+    fn test_extract_without_exif() {
+        let metadata = crate::extract_metadata_from_bytes(&jpeg_without_exif()).unwrap();
+        assert_eq!(
+            metadata,
+            crate::ImageMetadata {
+                has_icc_profile: Some(false),
+                ..Default::default()
+            }
+        );
+    }
+
+    /// A jpeg with an MPF segment indexing the given images, as (size, offset) pairs
+    fn jpeg_with_mpf(images: &[(u32, u32)]) -> Vec<u8> {
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        // Two entries and no next IFD, the MP entries following straight after
+        let entries_offset = 8 + 2 + 2 * 12 + 4;
+        tiff.extend_from_slice(&2u16.to_be_bytes());
+        tiff.extend_from_slice(&MPF_NUMBER_OF_IMAGES.to_be_bytes());
+        tiff.extend_from_slice(&4u16.to_be_bytes());
+        tiff.extend_from_slice(&1u32.to_be_bytes());
+        tiff.extend_from_slice(&(images.len() as u32).to_be_bytes());
+        tiff.extend_from_slice(&MPF_ENTRY.to_be_bytes());
+        tiff.extend_from_slice(&7u16.to_be_bytes());
+        tiff.extend_from_slice(&((images.len() * MPF_ENTRY_LEN) as u32).to_be_bytes());
+        tiff.extend_from_slice(&(entries_offset as u32).to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        for (size, offset) in images {
+            tiff.extend_from_slice(&0x0003_0000u32.to_be_bytes());
+            tiff.extend_from_slice(&size.to_be_bytes());
+            tiff.extend_from_slice(&offset.to_be_bytes());
+            tiff.extend_from_slice(&[0; 4]);
+        }
+
+        let mut jpeg = jpeg_without_exif();
+        // Slotting the MPF segment in before the image data
+        jpeg.truncate(jpeg.len() - 4);
+        jpeg.extend_from_slice(&[0xFF, APP2]);
+        jpeg.extend_from_slice(&((MPF_ID.len() + tiff.len()) as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(MPF_ID);
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, SOS, 0, 2]);
+        jpeg
+    }
+
+    #[test]
+    fn test_scan_segments() -> std::io::Result<()> {
+        let mut jpeg = Cursor::new(jpeg_without_exif());
+        assert_eq!(scan_segments(&mut jpeg)?, Some(Segments::default()));
+
+        // The profile comes after the 14KB or so of exif in the samples
+        let mut jpeg = Cursor::new(std::fs::read("tests/images/JAM19896.jpg")?);
+        let segments = scan_segments(&mut jpeg)?.unwrap();
+        assert!(segments.has_icc_profile);
+        assert_eq!(segments.mpf, None);
+        assert_eq!(jpeg.position(), 0);
+
+        let jpeg = std::fs::read("tests/images/JAM19896.jpg")?;
+        assert_eq!(scan_segments(&mut Cursor::new(&jpeg[..1024]))?, None);
+        assert_eq!(scan_segments(&mut Cursor::new(b"not a jpeg"))?, None);
+        Ok(())
+    }
+
+    /// A jpeg with just a start of frame for an image with the given number of components
+    fn jpeg_with_frame(num_components: u8) -> Vec<u8> {
+        let mut jpeg = jpeg_without_exif();
+        jpeg.truncate(jpeg.len() - 4);
+        jpeg.extend_from_slice(&[0xFF, 0xC0]);
+        jpeg.extend_from_slice(&(8 + 3 * num_components as u16).to_be_bytes());
+        jpeg.extend_from_slice(&[8, 0, 16, 0, 16, num_components]);
+        for id in 1..=num_components {
+            jpeg.extend_from_slice(&[id, 0x11, 0]);
+        }
+        jpeg.extend_from_slice(&[0xFF, SOS, 0, 2]);
+        jpeg
+    }
+
+    #[test]
+    fn test_num_components() -> std::io::Result<()> {
+        for num_components in &[1, 3, 4] {
+            let jpeg = jpeg_with_frame(*num_components);
+            let segments = scan_segments(&mut Cursor::new(&jpeg))?.unwrap();
+            assert_eq!(segments.num_components, Some(*num_components));
+        }
+        assert_eq!(
+            scan_segments(&mut Cursor::new(jpeg_without_exif()))?
+                .unwrap()
+                .num_components,
+            None
+        );
+
+        // The thumbnail's frame is tucked away in the exif so doesn't get in the way
+        let jpeg = std::fs::read("tests/images/JAM19896.jpg")?;
+        let metadata = crate::extract_metadata_from_bytes(&jpeg).unwrap();
+        assert_eq!(metadata.num_components, Some(3));
+        Ok(())
+    }
+
+    /// A real grayscale jpeg, as encoded by the image crate
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_grayscale() {
+        let mut jpeg = vec![];
+        let pixels = (0..64).map(|i| i * 4).collect::<Vec<u8>>();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode(&pixels, 8, 8, image::ColorType::L8)
+            .unwrap();
+        let metadata = crate::extract_metadata_from_bytes(&jpeg).unwrap();
+        assert_eq!(metadata.num_components, Some(1));
+    }
+
+    #[test]
+    fn test_mpf() -> std::io::Result<()> {
+        let jpeg = jpeg_with_mpf(&[(50_000, 0), (20_000, 49_000)]);
+        // The MPF tiff header comes after the JFIF and XMP segments, the APP2 marker,
+        // length and MPF identifier
+        let header_offset = jpeg_without_exif().len() as u64 - 4 + 4 + 4;
+        let segments = scan_segments(&mut Cursor::new(&jpeg))?.unwrap();
+        assert_eq!(
+            segments.mpf,
+            Some(Mpf {
+                image_count: 2,
+                images: vec![
+                    MpfImage {
+                        offset: 0,
+                        size: 50_000
+                    },
+                    MpfImage {
+                        offset: header_offset + 49_000,
+                        size: 20_000
+                    },
+                ],
+            })
+        );
+
+        let metadata = crate::extract_metadata_from_bytes(&jpeg).unwrap();
+        assert_eq!(metadata.mpf_image_count, Some(2));
+        assert_eq!(metadata.mpf_images.unwrap()[1].size, 20_000);
+
+        // An index claiming more entries than it holds is ignored rather than misread,
+        // the count of the MP entry tag being 8 bytes into the last IFD entry
+        let mut jpeg = jpeg_with_mpf(&[(50_000, 0), (20_000, 49_000)]);
+        let count = jpeg.len() - 4 - 2 * MPF_ENTRY_LEN - 4 - 8;
+        jpeg[count..count + 4].copy_from_slice(&256u32.to_be_bytes());
+        let segments = scan_segments(&mut Cursor::new(&jpeg))?.unwrap();
+        assert_eq!(segments.mpf, None);
+        Ok(())
+    }
+}
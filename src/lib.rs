@@ -1,82 +1,910 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
 use exif::{Exif, In, Tag};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::fmt::Formatter;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
-
-/// Process an image file, ie extract the metadata from it and write out
-/// a json file containing that metadata
-pub fn process_file<P: AsRef<Path>>(path: P) -> Result<(), ImageError> {
-    let metadata = CombinedMetadata {
-        file_metadata: file_metadata(&path)?,
-        image_metadata: image_metadata(&path)?,
-    };
-    let mut json_path = path.as_ref().to_path_buf();
-    json_path.set_extension("json");
-    write_metadata_to_file(json_path, &metadata)?;
-    Ok(())
+use std::io::{BufRead, Seek};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "native")]
+mod aliases;
+#[cfg(all(feature = "native", feature = "zip"))]
+mod archive;
+#[cfg(all(feature = "native", feature = "image"))]
+mod autorotate;
+mod dates;
+mod debug_tags;
+#[cfg(feature = "encoding_rs")]
+mod encoding;
+mod gps;
+mod group;
+#[cfg(all(feature = "native", feature = "sha2"))]
+mod hash;
+#[cfg(all(feature = "native", feature = "http"))]
+mod http;
+mod jpeg;
+mod makernote;
+#[cfg(feature = "native")]
+mod native;
+mod output;
+#[cfg(feature = "native")]
+mod recompute;
+#[cfg(feature = "native")]
+mod rename;
+#[cfg(feature = "native")]
+mod retry;
+mod size;
+#[cfg(all(feature = "native", feature = "rusqlite"))]
+mod sqlite;
+mod stats;
+mod stream;
+mod template;
+#[cfg(feature = "native")]
+mod timeout;
+#[cfg(feature = "native")]
+mod walk;
+mod xmp;
+
+#[cfg(feature = "native")]
+pub use aliases::load_camera_aliases;
+#[cfg(all(feature = "native", feature = "zip"))]
+pub use archive::{process_zip, ZipEntries};
+#[cfg(all(feature = "native", feature = "image"))]
+pub use autorotate::autorotate;
+pub use dates::parse_date_bound;
+pub use debug_tags::TagType;
+#[cfg(feature = "encoding_rs")]
+pub use encoding::StringEncoding;
+pub use gps::{geohash, haversine_km, Near, DEFAULT_GEOHASH_PRECISION};
+pub use group::{DayGroups, FilenameMap};
+#[cfg(all(feature = "native", feature = "sha2"))]
+pub use hash::{
+    checksum_file, sha256_file, verify_file, ChecksumAlgorithm, DuplicateGroup, Duplicates,
+    Verification,
+};
+#[cfg(all(feature = "native", feature = "http"))]
+pub use http::{fetch_url_with_options, is_url};
+pub use jpeg::MpfImage;
+#[cfg(feature = "native")]
+pub use native::{
+    error_stub, extract_file_with_options, process_file, process_file_with,
+    process_file_with_options, write_error_stub, OnError,
+};
+pub use output::{to_output_value, KeyCase};
+#[cfg(feature = "native")]
+pub use recompute::recompute_file;
+#[cfg(feature = "native")]
+pub use rename::rename_by_capture_time;
+#[cfg(feature = "native")]
+pub use retry::DEFAULT_RETRIES;
+pub use size::parse_file_size;
+#[cfg(all(feature = "native", feature = "rusqlite"))]
+pub use sqlite::SqliteWriter;
+pub use stats::{CameraModelCount, Stats};
+pub use stream::JsonArrayWriter;
+pub use template::OutputTemplate;
+#[cfg(feature = "native")]
+pub use timeout::run_with_timeout;
+#[cfg(feature = "native")]
+pub use walk::{find_images, has_extension, FoundImages};
+pub use xmp::{merge_xmp, to_xmp};
+#[cfg(feature = "native")]
+pub use xmp::{merge_xmp_sidecar, write_xmp_sidecar};
+
+/// Options controlling which images get processed and how
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Only images with GPS coordinates inside this area get processed
+    pub near: Option<Near>,
+    /// Only images captured at or after this time get processed
+    pub since: Option<NaiveDateTime>,
+    /// Only images captured at or before this time get processed
+    pub until: Option<NaiveDateTime>,
+    /// Process images without a capture time when filtering with since or until, rather
+    /// than skipping them
+    pub include_undated: bool,
+    /// Write the json keys out in a stable documented order rather than the order the
+    /// fields happen to be declared in, see `output::sort_keys`
+    pub sort_keys: bool,
+    /// Round the decimal GPS coordinates to this many decimal places, 6 places is
+    /// roughly 11cm
+    pub gps_precision: Option<u32>,
+    /// Work out the geohash to this many characters rather than
+    /// `DEFAULT_GEOHASH_PRECISION`, fewer characters giving a coarser cell
+    pub geohash_precision: Option<usize>,
+    /// Round all the decimal fields to this many significant digits, to tidy up values
+    /// like 2.8000000000000003 left over from converting the exif rationals
+    pub float_precision: Option<u32>,
+    /// The naming convention for the json keys, snake_case by default
+    pub key_case: KeyCase,
+    /// Output the keys prefixed with their exif group, ie tiff:Model, like ExifTool's
+    /// grouped output
+    pub grouped_keys: bool,
+    /// Output the file and image fields as separate `file` and `image` objects rather
+    /// than merged into one flat object
+    pub nested: bool,
+    /// Output each field as `{value, type, count}` with the raw exif type and component
+    /// count of the tag it came from, for tracking down malformed files
+    pub debug_tags: bool,
+    /// Output the rest of the GPS tags that don't have fields of their own, as the
+    /// gps_extra map
+    pub full_gps: bool,
+    /// Leave out fields that are empty strings, arrays or objects as well as the missing
+    /// ones, to keep the output for sparse images small
+    pub omit_empty: bool,
+    /// Add any warnings from extracting the image to the output as a `warnings` array,
+    /// so they stay with the data rather than only going to stderr
+    pub capture_warnings: bool,
+    /// Add the has_gps, has_capture_time and has_camera_info flags to the output, for
+    /// filtering on without checking each of the fields behind them
+    pub presence_flags: bool,
+    /// Camera models to output under another name, ie to settle on one name for models
+    /// that write their model string differently, see `load_camera_aliases`
+    pub camera_aliases: HashMap<String, String>,
+    /// Skip reading the exif entirely and only output the file metadata, for when that's
+    /// all that's wanted from a large batch
+    pub file_only: bool,
+    /// Skip files larger than this many bytes without reading them, so a stray huge file
+    /// can't stall a batch
+    pub max_file_size: Option<u64>,
+    /// How many times to retry reading a file after a transient IO error, defaults to
+    /// `DEFAULT_RETRIES`
+    pub retries: Option<u32>,
+    /// Only output these fields, when set
+    pub include: Option<Vec<String>>,
+    /// Never output these fields, this takes precedence over `include`
+    pub exclude: Vec<String>,
+    /// Skip extracting images whose json file is already newer than the image
+    pub skip_existing: bool,
+    /// Rather than failing on files whose exif can't be parsed, record just the file
+    /// metadata for them so every input gets an output
+    pub best_effort: bool,
+    /// Called before overwriting an existing json file, which is left alone if this
+    /// returns false
+    pub confirm_overwrite: Option<fn(PathBuf) -> bool>,
+    /// Called with the reason when a file is skipped without being read, ie for being over
+    /// `max_file_size`, so it can be warned about
+    pub on_skip: Option<fn(PathBuf, String)>,
+    /// Keep GPS coordinates of exactly (0, 0), by default these are treated as the
+    /// device not having a fix
+    pub allow_null_island: bool,
+    /// Merge in the rating, keywords, GPS location and capture time from any XMP sidecar
+    /// next to the image, taking them over the ones from the exif, see `merge_xmp`
+    pub merge_sidecar: bool,
+    /// What to do about files whose created time is later than their modified time, by
+    /// default they're reported as is
+    pub file_time_conflict: FileTimeConflict,
+    /// Percent encode filenames that aren't valid UTF-8 rather than replacing the
+    /// invalid bytes, so they can be mapped back to the original file
+    pub percent_encode_filenames: bool,
+    /// Memory map the files rather than reading them, to save on syscalls over a large
+    /// batch. Nothing else may change the files while they're mapped, another process
+    /// truncating one would kill us with a SIGBUS
+    #[cfg(feature = "mmap")]
+    pub mmap: bool,
+    /// Work out a checksum of each file with this algorithm, for spotting corruption or
+    /// duplicates later
+    #[cfg(all(feature = "native", feature = "sha2"))]
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Apply NFC unicode normalization to the extracted strings
+    #[cfg(feature = "unicode-normalization")]
+    pub normalize_unicode: bool,
+    /// How to decode the free text fields like the camera model
+    #[cfg(feature = "encoding_rs")]
+    pub string_encoding: StringEncoding,
+    /// Write the json files into this directory rather than next to the images
+    pub output_dir: Option<PathBuf>,
+    /// When writing to `output_dir`, mirror the layout of the images relative to this
+    /// directory rather than writing all the json files side by side
+    pub base_dir: Option<PathBuf>,
+    /// Write the json files to a path templated from the metadata, relative to
+    /// `output_dir` if set
+    pub output_template: Option<OutputTemplate>,
+    /// Record the path of each image relative to this directory as its filename, ie
+    /// "2020/01/JAM26284.jpg", rather than just its name. Images outside of it fail
+    pub relative_to: Option<PathBuf>,
+}
+
+impl Options {
+    /// Whether the image passes all the filters set in the options
+    pub fn selects(&self, metadata: &CombinedMetadata) -> bool {
+        if let Some(near) = &self.near {
+            let image_metadata = &metadata.image_metadata;
+            match (image_metadata.gps_latitude, image_metadata.gps_longitude) {
+                (Some(latitude), Some(longitude)) if near.contains(latitude, longitude) => {}
+                _ => return false,
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            match metadata.image_metadata.capture_time {
+                Some(capture_time) => {
+                    if self.since.map_or(false, |since| capture_time < since)
+                        || self.until.map_or(false, |until| capture_time > until)
+                    {
+                        return false;
+                    }
+                }
+                None if self.include_undated => {}
+                None => return false,
+            }
+        }
+        true
+    }
 }
 
 /// All the metadata about a file/image
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
-struct CombinedMetadata {
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
+pub struct CombinedMetadata {
     #[serde(flatten)]
-    file_metadata: FileMetadata,
+    pub file_metadata: FileMetadata,
     #[serde(flatten)]
-    image_metadata: ImageMetadata,
+    pub image_metadata: ImageMetadata,
+    /// Our single best guess at when the image was captured, see `best_capture_time`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_capture_time: Option<DateTime<Utc>>,
+    /// Any fields of their own added by library users, ie from `process_file_with`
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CombinedMetadata {
+    /// Combines the file and image metadata, working out the derived fields from them
+    pub fn new(file_metadata: FileMetadata, image_metadata: ImageMetadata) -> Self {
+        CombinedMetadata {
+            best_capture_time: best_capture_time(&file_metadata, &image_metadata),
+            file_metadata,
+            image_metadata,
+            extra_fields: serde_json::Map::new(),
+        }
+    }
+
+    /// A short human readable summary of the image, ie
+    /// "JAM19896.jpg: Canon EOS 5D Mark IV, 2019-07-26, ISO 1000"
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: {}, {}, ISO {}",
+            self.file_metadata.filename,
+            self.image_metadata
+                .camera_model
+                .as_deref()
+                .unwrap_or("unknown camera"),
+            self.image_metadata.capture_time.map_or_else(
+                || "unknown date".to_string(),
+                |time| time.format("%Y-%m-%d").to_string()
+            ),
+            self.image_metadata
+                .iso
+                .or(self.image_metadata.recommended_exposure_index)
+                .map_or_else(|| "unknown".to_string(), |iso| iso.to_string())
+        )
+    }
+
+    /// Whether the image has a GPS location
+    pub fn has_gps(&self) -> bool {
+        self.image_metadata.gps_latitude.is_some() && self.image_metadata.gps_longitude.is_some()
+    }
+
+    /// Whether the image records when it was captured, going by the exif alone
+    pub fn has_capture_time(&self) -> bool {
+        self.image_metadata.capture_time.is_some()
+    }
+
+    /// Whether the image records the camera it was taken with
+    pub fn has_camera_info(&self) -> bool {
+        self.image_metadata.camera_model.is_some() || self.image_metadata.camera_serial.is_some()
+    }
+}
+
+/// Resolves the various timestamps down to the one most likely to be when the image was
+/// actually captured, in order of preference:
+/// 1. The GPS datetime, as it comes from the satellites and is always in UTC
+/// 2. The exif capture time, using its offset if recorded, otherwise assuming UTC
+/// 3. The file created time
+fn best_capture_time(
+    file_metadata: &FileMetadata,
+    image_metadata: &ImageMetadata,
+) -> Option<DateTime<Utc>> {
+    let exif_capture_time = image_metadata.capture_time.map(|capture_time| {
+        image_metadata
+            .capture_time_offset
+            .as_ref()
+            .and_then(|offset| {
+                DateTime::parse_from_str(
+                    &format!("{}{}", capture_time.format("%Y-%m-%dT%H:%M:%S%.f"), offset),
+                    "%Y-%m-%dT%H:%M:%S%.f%:z",
+                )
+                .ok()
+            })
+            .map(|datetime| datetime.with_timezone(&Utc))
+            .unwrap_or_else(|| DateTime::from_utc(capture_time, Utc))
+    });
+
+    image_metadata
+        .gps_datetime
+        .or(exif_capture_time)
+        .or(file_metadata.created_time)
 }
 
 /// The file specific metadata
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
-struct FileMetadata {
-    filename: String,
-    size: u64,
+#[derive(Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub filename: String,
+    pub size: u64,
     /// Potentially missing if the underlying platform/filesystem doesn't capture the created time
     #[serde(skip_serializing_if = "Option::is_none")]
-    created_time: Option<DateTime<Utc>>,
+    pub created_time: Option<DateTime<Utc>>,
     /// Potentially missing if the underlying platform/filesystem doesn't capture the modified time
     #[serde(skip_serializing_if = "Option::is_none")]
-    modified_time: Option<DateTime<Utc>>,
+    pub modified_time: Option<DateTime<Utc>>,
+    /// The SHA-256 of the file's contents as hex, only worked out when asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The MD5 of the file's contents as hex, when asked for rather than the SHA-256
+    #[cfg(feature = "checksums")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// The BLAKE3 of the file's contents as hex, when asked for rather than the SHA-256
+    #[cfg(feature = "checksums")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl FileMetadata {
+    /// Whether the file claims to have been created after it was last modified, which
+    /// can't really happen but some filesystems reset the created time when copying
+    pub fn created_after_modified(&self) -> bool {
+        match (self.created_time, self.modified_time) {
+            (Some(created_time), Some(modified_time)) => created_time > modified_time,
+            _ => false,
+        }
+    }
+
+    /// Sorts out a created time later than the modified time as per the policy, returning
+    /// a warning saying what was done if anything changed
+    pub fn resolve_time_conflict(&mut self, policy: FileTimeConflict) -> Option<String> {
+        if policy == FileTimeConflict::Keep || !self.created_after_modified() {
+            return None;
+        }
+        let (created_time, modified_time) = (self.created_time?, self.modified_time?);
+        let action = match policy {
+            FileTimeConflict::Swap => {
+                self.created_time = Some(modified_time);
+                self.modified_time = Some(created_time);
+                "swapping them"
+            }
+            _ => {
+                self.created_time = None;
+                "dropping the created time"
+            }
+        };
+        Some(format!(
+            "Created time {} is after the modified time {}, {}",
+            created_time.to_rfc3339(),
+            modified_time.to_rfc3339(),
+            action
+        ))
+    }
+}
+
+/// What to do about a file whose created time is later than its modified time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileTimeConflict {
+    /// Report both times as they are
+    Keep,
+    /// Swap the times over, for when the copy kept the original created time as the
+    /// modified time
+    Swap,
+    /// Drop the created time as bogus
+    Clear,
+}
+
+impl Default for FileTimeConflict {
+    fn default() -> Self {
+        FileTimeConflict::Keep
+    }
+}
+
+/// Parses "keep", "swap" or "clear" as passed on the command line
+impl FromStr for FileTimeConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(FileTimeConflict::Keep),
+            "swap" => Ok(FileTimeConflict::Swap),
+            "clear" => Ok(FileTimeConflict::Clear),
+            _ => Err(format!("Expected keep, swap or clear but got \"{}\"", s)),
+        }
+    }
 }
 
 /// The metadata from the actual image itself
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
-struct ImageMetadata {
+#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+    /// How far the image needs rotating clockwise for display, after any mirroring,
+    /// one of 0, 90, 180 or 270
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation_degrees: Option<u16>,
+    /// Whether the image needs flipping horizontally for display, before rotating it by
+    /// `orientation_degrees`. Together these map onto a css transform of
+    /// `rotate(<degrees>deg) scaleX(-1)`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation_mirrored: Option<bool>,
+    /// The orientation recorded against the embedded thumbnail, this should match the
+    /// main orientation but some software only updates one of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_orientation: Option<u32>,
+    /// The stored dimensions of the embedded thumbnail, before any orientation is applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_height: Option<u32>,
+    /// The dimensions of the embedded thumbnail as displayed, going by the thumbnail's
+    /// own orientation or the main one when it doesn't record its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_display_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_display_height: Option<u32>,
+    /// The stored dimensions of the image, before any orientation is applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_height: Option<u32>,
+    /// The dimensions of the image as displayed, ie swapped from the pixel dimensions
+    /// when the orientation rotates the image by 90 degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_time: Option<NaiveDateTime>,
+    /// When the image was digitized, only really differs from the capture time for scans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digitized_time: Option<NaiveDateTime>,
+    /// When the image was last changed by the camera or editing software
     #[serde(skip_serializing_if = "Option::is_none")]
-    orientation: Option<u32>,
+    pub modify_datetime: Option<NaiveDateTime>,
+    /// The UTC offset of the capture time, ie "+12:00"
     #[serde(skip_serializing_if = "Option::is_none")]
-    capture_time: Option<NaiveDateTime>,
+    pub capture_time_offset: Option<String>,
+    /// The UTC offset of the capture time as set in the camera, read from the maker
+    /// notes for cameras that don't write the standard offset tag. This is best effort
+    /// and only Canon's maker notes are understood
     #[serde(skip_serializing_if = "Option::is_none")]
-    camera_model: Option<String>,
+    pub timezone_offset: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    camera_serial: Option<String>,
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_serial: Option<String>,
+    /// The owner name set in the camera, handy for telling whose shot is whose with
+    /// shared gear
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_owner: Option<String>,
+    /// The name of the lens, ie "EF35mm f/1.4L II USM". Without the standard LensModel
+    /// tag it's looked up from the lens ID in the maker notes, for Canon's more common
+    /// lenses only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens_serial: Option<String>,
+    /// The focal length and maximum aperture range of the lens, ie "24-70mm f/2.8"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens_spec: Option<String>,
+    /// An identifier the camera assigns each shot, as 32 hex characters, handy for
+    /// tracing edited copies back to their originals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_unique_id: Option<String>,
+    /// Whether the image was composed from multiple shots, ie HDR or a panorama
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_composite: Option<bool>,
+    /// The free text description of the image, as distinct from any XMP title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The star rating from 0 to 5, as set by Windows Explorer and some cameras
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    /// The keywords or tags for the image, as set by Windows Explorer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// The type of image sensor, ie "One-chip color area"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensing_method: Option<String>,
+    /// Where the image came from, ie "Digital still camera" or one of the scanner types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_source: Option<String>,
+    /// How the image data is stored, ie "Uncompressed" or "LZW". Mostly of interest for
+    /// tiffs and raws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// The color space of the stored image data, ie "RGB" or "Color Filter Array" for raws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photometric_interpretation: Option<String>,
+    /// How the embedded thumbnail is stored, "JPEG" for a complete jpeg or "Uncompressed"
+    /// for raw RGB pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_compression: Option<String>,
+    /// Whether the signal was boosted, ie "High gain up", a hint the image will be noisy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain_control: Option<String>,
+    /// The ISO speed from the PhotographicSensitivity tag, ie 1000. This is capped at
+    /// 65535, see `recommended_exposure_index` for the higher ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso: Option<u32>,
+    /// Which of the ISO standard's sensitivity measures the camera reported, ie
+    /// "Recommended exposure index"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitivity_type: Option<String>,
+    /// The recommended exposure index, the ISO most cameras report. Unlike the
+    /// PhotographicSensitivity tag this isn't capped at 65535
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recommended_exposure_index: Option<u32>,
+    /// The spectral sensitivity of each channel of the camera, as free text in the
+    /// format ASTM defines for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spectral_sensitivity: Option<String>,
+    /// The opto-electronic conversion function as set out in ISO 14524, the raw bytes of
+    /// the table base64 encoded as there's no standard text form for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oecf: Option<String>,
+    /// The white balance color temperature, ie 5200. Only read from Canon's maker notes
+    /// for now so None for other makes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub white_balance_kelvin: Option<u32>,
+    /// How much noise reduction was applied to the image data, "Off", "Partial" or "Full".
+    /// Only recorded by DNGs, there's no standard exif tag for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_reduction: Option<String>,
+    /// Whether a dark frame was taken to cancel out the noise of a long exposure. Only
+    /// read from Canon's maker notes for now, and only for the models that record it in
+    /// their FileInfo, so None for other makes and older Canons
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_exposure_noise_reduction: Option<bool>,
+    /// The temperature inside the camera in degrees Celsius, for following how hot it
+    /// ran over a long session. This is brand specific and often absent, it's only read
+    /// from the maker notes of the newer Canon EOS models for now
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_temperature_c: Option<f64>,
+    /// The indices of the autofocus points that were in focus, numbered as per the
+    /// camera model. Only read from Canon's maker notes for now
+    #[cfg(feature = "af-points")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub af_points_used: Vec<u32>,
+    /// Whether there's an embedded ICC color profile, only known for jpegs and tiffs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_icc_profile: Option<bool>,
+    /// How many images there are in a multi-picture (MPF) jpeg, ie 2 for a photo with a
+    /// depth map
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpf_image_count: Option<u32>,
+    /// Where each of the images in a multi-picture jpeg is, for splitting them out. The
+    /// first is the main image starting at the beginning of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpf_images: Option<Vec<MpfImage>>,
+    /// The number of color components in a jpeg, 1 for grayscale, 3 for YCbCr or RGB and
+    /// 4 for CMYK
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_components: Option<u8>,
+    /// DNG only, the white balance as the camera's neutral color in its own color space,
+    /// one value per color plane, ie [0.47, 1.0, 0.64]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_shot_neutral: Option<Vec<f64>>,
+    /// DNG only, the matrix taking XYZ colors to the camera's color space under the
+    /// first calibration illuminant, a row of 3 per color plane
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_matrix_1: Option<Vec<Vec<f64>>>,
+    /// DNG only, as per `color_matrix_1` but for the second calibration illuminant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_matrix_2: Option<Vec<Vec<f64>>>,
+    /// How long the shutter was open in seconds, ie 0.0015625 for 1/640s. Worked out
+    /// from the shutter speed when the camera only records that
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<f64>,
+    /// The shutter speed in APEX units, an exposure of 2^-APEX seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutter_speed_apex: Option<f64>,
+    /// The aperture as an f-number, ie 4.0 for f/4. Worked out from the aperture value
+    /// when the camera only records that
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub f_number: Option<f64>,
+    /// The aperture in APEX units, an f-number of sqrt(2)^APEX
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aperture_apex: Option<f64>,
+    /// Where the main subject is in the image, in pixels from the top left. The length
+    /// says how to read it, 2 being an x, y point, 3 a circle as x, y and diameter and
+    /// 4 a rectangle as center x, y, width and height
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_area: Option<Vec<u32>>,
+    /// Signed decimal degrees, positive being north
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_latitude: Option<f64>,
+    /// Signed decimal degrees, positive being east
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_longitude: Option<f64>,
+    /// The GPS coordinates as a geohash, `DEFAULT_GEOHASH_PRECISION` characters long
+    /// unless the output asks for another precision
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geohash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_datetime: Option<DateTime<Utc>>,
+    /// Whether differential correction was applied to the GPS fix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_differential: Option<bool>,
+    /// Whether the GPS receiver had a fix, "Measurement active" or "Measurement void"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_status: Option<String>,
+    /// Whether the GPS fix was "2-dimensional" or "3-dimensional", only a 3 dimensional
+    /// fix gives a trustworthy altitude
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_measure_mode: Option<String>,
+    /// The name of the GPS area, ie a place name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_area_information: Option<String>,
+    /// The version of the GPS tags, ie "2.3.0.0"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_version: Option<String>,
+    /// The estimated accuracy of the GPS location as a radius in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_h_positioning_error_m: Option<f64>,
+    /// Where the camera was headed, as recorded by some navigation cameras
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_dest_latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_dest_longitude: Option<f64>,
+    /// The bearing to the destination in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_dest_bearing: Option<f64>,
+    /// The direction the camera was moving in degrees, as opposed to the direction it was
+    /// pointing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_track: Option<f64>,
+    /// What the track is relative to, "True north" or "Magnetic north"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_track_ref: Option<String>,
+    /// The rest of the GPS tags that don't have fields of their own, ie the altitude,
+    /// speed and destination distance, keyed by tag name with their display values. Only
+    /// output with the full_gps option
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub gps_extra: BTreeMap<String, String>,
+    /// Any problems found in the image metadata that didn't stop us from extracting it
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+    /// The raw types of the tags the fields came from, only read with the debug_tags option
+    #[serde(skip)]
+    pub tag_types: Vec<TagType>,
 }
 
-/// Retrieves the filesystem metadata for a given file.
-fn file_metadata<P: AsRef<Path>>(path: P) -> std::io::Result<FileMetadata> {
-    let metadata = std::fs::metadata(&path)?;
-    Ok(FileMetadata {
-        filename: path
-            .as_ref()
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .to_string(),
-        size: metadata.len(),
-        created_time: metadata.created().map(DateTime::from).ok(),
-        modified_time: metadata.modified().map(DateTime::from).ok(),
+impl ImageMetadata {
+    /// Works out the fields derived from the other image fields
+    fn derive_fields(&mut self) {
+        // Orientations 5 to 8 all involve a 90 degree rotation
+        let (display_width, display_height) = match self.orientation {
+            Some(5..=8) => (self.pixel_height, self.pixel_width),
+            _ => (self.pixel_width, self.pixel_height),
+        };
+        self.display_width = display_width;
+        self.display_height = display_height;
+
+        let (thumbnail_width, thumbnail_height) =
+            match self.thumbnail_orientation.or(self.orientation) {
+                Some(5..=8) => (self.thumbnail_height, self.thumbnail_width),
+                _ => (self.thumbnail_width, self.thumbnail_height),
+            };
+        self.thumbnail_display_width = thumbnail_width;
+        self.thumbnail_display_height = thumbnail_height;
+
+        self.geohash = match (self.gps_latitude, self.gps_longitude) {
+            (Some(latitude), Some(longitude)) => {
+                Some(geohash(latitude, longitude, DEFAULT_GEOHASH_PRECISION))
+            }
+            _ => None,
+        };
+
+        let (degrees, mirrored) = match self.orientation {
+            Some(1) => (0, false),
+            Some(2) => (0, true),
+            Some(3) => (180, false),
+            Some(4) => (180, true),
+            Some(5) => (270, true),
+            Some(6) => (90, false),
+            Some(7) => (90, true),
+            Some(8) => (270, false),
+            _ => {
+                self.orientation_degrees = None;
+                self.orientation_mirrored = None;
+                return;
+            }
+        };
+        self.orientation_degrees = Some(degrees);
+        self.orientation_mirrored = Some(mirrored);
+    }
+
+    /// Drops a GPS location of exactly (0, 0) with a warning, devices without a fix
+    /// sometimes write zeros rather than leaving the coordinates out, placing the image
+    /// in the Gulf of Guinea
+    pub fn clear_null_island(&mut self) {
+        if self.gps_latitude == Some(0.0) && self.gps_longitude == Some(0.0) {
+            self.gps_latitude = None;
+            self.gps_longitude = None;
+            self.geohash = None;
+            self.warnings
+                .push("Ignoring GPS location of exactly 0, 0 (null island)".to_string());
+        }
+    }
+
+    /// Replaces the camera model with its alias, if it has one
+    pub fn apply_camera_aliases(&mut self, aliases: &HashMap<String, String>) {
+        if let Some(alias) = self.camera_model.as_ref().and_then(|m| aliases.get(m)) {
+            self.camera_model = Some(alias.clone());
+        }
+    }
+
+    /// Applies NFC normalization to the string fields, camera strings can mix composed
+    /// and decomposed accents which makes them awkward to group or dedupe on
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalize_unicode(&mut self) {
+        use unicode_normalization::UnicodeNormalization;
+
+        for field in vec![
+            &mut self.capture_time_offset,
+            &mut self.timezone_offset,
+            &mut self.camera_model,
+            &mut self.camera_serial,
+            &mut self.camera_owner,
+            &mut self.lens_model,
+            &mut self.description,
+            &mut self.sensing_method,
+            &mut self.file_source,
+            &mut self.compression,
+            &mut self.photometric_interpretation,
+            &mut self.thumbnail_compression,
+            &mut self.gain_control,
+            &mut self.sensitivity_type,
+            &mut self.spectral_sensitivity,
+            &mut self.noise_reduction,
+            &mut self.gps_area_information,
+        ] {
+            if let Some(value) = field {
+                *value = value.nfc().collect();
+            }
+        }
+        for keyword in &mut self.keywords {
+            *keyword = keyword.nfc().collect();
+        }
+    }
+}
+
+/// Extracts the image metadata from an image held in memory, the image format is
+/// worked out from the bytes themselves. This doesn't touch the filesystem so is
+/// available without the native feature, ie when built for wasm
+///
+/// ```
+/// # fn main() -> Result<(), image_metadata::ImageError> {
+/// let bytes = include_bytes!("../tests/images/JAM19896.jpg");
+/// let metadata = image_metadata::extract_metadata_from_bytes(bytes)?;
+/// assert_eq!(metadata.camera_model.as_deref(), Some("Canon EOS 5D Mark IV"));
+/// # Ok(())
+/// # }
+/// ```
+pub fn extract_metadata_from_bytes(bytes: &[u8]) -> Result<ImageMetadata, ImageError> {
+    extract_metadata_from_reader(&mut std::io::Cursor::new(bytes))
+}
+
+/// Extracts the image metadata from an image that's already been opened or is held
+/// in memory. Jpegs without any exif give empty metadata rather than an error
+pub fn extract_metadata_from_reader<R: BufRead + Seek>(
+    reader: &mut R,
+) -> Result<ImageMetadata, ImageError> {
+    read_metadata(reader, |_, _| {})
+}
+
+/// Extracts the image metadata as per `extract_metadata_from_reader` but decoding the
+/// free text fields like the camera model in the given encoding
+#[cfg(feature = "encoding_rs")]
+pub fn extract_metadata_from_reader_with_encoding<R: BufRead + Seek>(
+    reader: &mut R,
+    encoding: StringEncoding,
+) -> Result<ImageMetadata, ImageError> {
+    read_metadata(reader, |metadata, exif| {
+        if encoding != StringEncoding::Utf8 {
+            encoding::decode_strings(metadata, exif, encoding);
+        }
     })
 }
 
-/// Retrieves the filesystem metadata for a given file.
-fn image_metadata<P: AsRef<Path>>(path: P) -> Result<ImageMetadata, ImageError> {
-    let mut file = std::io::BufReader::new(File::open(path)?);
+/// Reads the exif and anything else we look for outside of it, letting the caller
+/// adjust the fields extracted from the exif
+pub(crate) fn read_metadata<R: BufRead + Seek, F: FnOnce(&mut ImageMetadata, &Exif)>(
+    reader: &mut R,
+    adjust: F,
+) -> Result<ImageMetadata, ImageError> {
+    let segments = jpeg::scan_segments(reader)?;
+    let has_icc_profile = segments.as_ref().map(|s| s.has_icc_profile);
+    let num_components = segments.as_ref().and_then(|s| s.num_components);
+    let mpf = segments.and_then(|s| s.mpf);
+    let mpf_image_count = mpf.as_ref().map(|mpf| mpf.image_count);
+    let mpf_images = mpf.map(|mpf| mpf.images);
+    if jpeg::lacks_exif(reader.fill_buf()?) {
+        return Ok(ImageMetadata {
+            has_icc_profile,
+            mpf_image_count,
+            mpf_images,
+            num_components,
+            ..Default::default()
+        });
+    }
+    let is_tiff =
+        reader.fill_buf()?.starts_with(b"II*\0") || reader.fill_buf()?.starts_with(b"MM\0*");
     let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut file)?;
+    let exif = exifreader.read_from_container(reader)?;
+    let mut metadata = exif_metadata(&exif);
+    // Tiffs keep their profile in a tag, for anything else we don't know how to look
+    metadata.has_icc_profile = match (has_icc_profile, is_tiff) {
+        (Some(has_icc_profile), _) => Some(has_icc_profile),
+        (None, true) => Some(exif.get_field(TAG_ICC_PROFILE, In::PRIMARY).is_some()),
+        (None, false) => None,
+    };
+    metadata.mpf_image_count = mpf_image_count;
+    metadata.mpf_images = mpf_images;
+    metadata.num_components = num_components;
+    adjust(&mut metadata, &exif);
+    Ok(metadata)
+}
+
+/// Cameras tend to pad the description out with NULs or spaces to reserve room for
+/// edits later
+fn trim_description(description: String) -> Option<String> {
+    Some(
+        description
+            .trim_end_matches(|c: char| c == '\0' || c == ' ')
+            .to_string(),
+    )
+    .filter(|s| !s.is_empty())
+}
+
+/// Microsoft's star rating tags, these aren't part of the exif standard so the exif
+/// lib doesn't have names for them
+const TAG_RATING: Tag = Tag(exif::Context::Tiff, 0x4746);
+const TAG_RATING_PERCENT: Tag = Tag(exif::Context::Tiff, 0x4749);
+/// Microsoft's keywords tag, also missing from the exif lib
+const TAG_XP_KEYWORDS: Tag = Tag(exif::Context::Tiff, 0x9C9E);
+
+/// Where tiffs embed their ICC profile
+const TAG_ICC_PROFILE: Tag = Tag(exif::Context::Tiff, 0x8773);
+
+/// DNG's color tags, which aren't in the exif lib either
+const TAG_COLOR_MATRIX_1: Tag = Tag(exif::Context::Tiff, 0xC621);
+const TAG_COLOR_MATRIX_2: Tag = Tag(exif::Context::Tiff, 0xC622);
+const TAG_AS_SHOT_NEUTRAL: Tag = Tag(exif::Context::Tiff, 0xC628);
+const TAG_NOISE_REDUCTION_APPLIED: Tag = Tag(exif::Context::Tiff, 0xC6F7);
+
+/// Standard base64 with padding, not worth pulling in a crate for the odd blob
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
 
+/// Extracts the fields we're interested in from the parsed exif data
+fn exif_metadata(exif: &Exif) -> ImageMetadata {
     /// Attempt to grab the raw bytes and use them as our string to avoid the exif lib
     /// mucking with the raw strings we want out
     fn field_str_unquoted(exif: &Exif, tag: Tag) -> Option<String> {
@@ -94,148 +922,1758 @@ fn image_metadata<P: AsRef<Path>>(path: P) -> Result<ImageMetadata, ImageError>
         })
     }
 
-    Ok(ImageMetadata {
-        orientation: exif
-            .get_field(Tag::Orientation, In::PRIMARY)
-            .and_then(|f| f.value.get_uint(0)),
-        capture_time: field_str_unquoted(&exif, Tag::DateTimeOriginal)
-            .and_then(|s| NaiveDateTime::parse_from_str(&s, "%Y:%m:%d %H:%M:%S").ok()),
-        camera_model: field_str_unquoted(&exif, Tag::Model),
-        camera_serial: field_str_unquoted(&exif, Tag::BodySerialNumber),
-    })
-}
+    /// The datetime tags only go down to the second, any fraction of a second is held in
+    /// a separate tag as the digits after the decimal point, ie "34" for 0.34 seconds
+    fn field_datetime(exif: &Exif, tag: Tag, subsec_tag: Tag) -> Option<NaiveDateTime> {
+        let datetime = field_str_unquoted(exif, tag)
+            .and_then(|s| NaiveDateTime::parse_from_str(&s, "%Y:%m:%d %H:%M:%S").ok())?;
+        let nanos = field_str_unquoted(exif, subsec_tag)
+            .map(|s| s.trim().trim_end_matches('\0').to_string())
+            .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| format!("{:0<9.9}", digits).parse::<i64>().ok());
+        match nanos {
+            Some(nanos) => Some(datetime + Duration::nanoseconds(nanos)),
+            None => Some(datetime),
+        }
+    }
 
-/// Write the metadata out to a file
-fn write_metadata_to_file<P: AsRef<Path>>(
-    path: P,
-    metadata: &CombinedMetadata,
-) -> std::io::Result<()> {
-    let mut file = BufWriter::new(File::create(path)?);
-    // All these json serde errors will actually be io errors anyway so the conversion
-    // back to io wont be lossy.
-    serde_json::to_writer_pretty(&mut file, metadata).map_err(std::io::Error::from)?;
-    file.flush()?;
-    Ok(())
-}
+    /// Converts a degrees/minutes/seconds coordinate and its N/S/E/W reference into
+    /// signed decimal degrees
+    fn field_gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag) -> Option<f64> {
+        let parts = match &exif.get_field(tag, In::PRIMARY)?.value {
+            exif::Value::Rational(parts) if !parts.is_empty() => parts,
+            _ => return None,
+        };
+        let degrees = parts
+            .iter()
+            .zip(&[1.0, 60.0, 3600.0])
+            .map(|(part, scale)| part.to_f64() / scale)
+            .sum::<f64>();
+        match field_str_unquoted(exif, ref_tag).as_deref() {
+            Some("S") | Some("W") => Some(-degrees),
+            _ => Some(degrees),
+        }
+    }
 
-/// Top level representation of any error that might happen during image processing
-#[derive(Debug)]
-pub enum ImageError {
-    /// An IO Error and the path that caused it
-    IoError(std::io::Error),
-    ExifError(exif::Error),
-}
+    /// The GPS date and time are stored separately, the time as hour/minute/second rationals
+    fn field_gps_datetime(exif: &Exif) -> Option<DateTime<Utc>> {
+        let date = field_str_unquoted(exif, Tag::GPSDateStamp)
+            .and_then(|s| NaiveDate::parse_from_str(&s, "%Y:%m:%d").ok())?;
+        let seconds = match &exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value {
+            exif::Value::Rational(parts) if parts.len() == 3 => parts
+                .iter()
+                .zip(&[3600.0, 60.0, 1.0])
+                .map(|(part, scale)| part.to_f64() * scale)
+                .sum::<f64>(),
+            _ => return None,
+        };
+        if !seconds.is_finite() {
+            return None;
+        }
+        let time = date.and_hms(0, 0, 0) + Duration::milliseconds((seconds * 1000.0) as i64);
+        Some(DateTime::from_utc(time, Utc))
+    }
 
-impl From<std::io::Error> for ImageError {
-    fn from(err: std::io::Error) -> Self {
-        ImageError::IoError(err)
+    /// 1 is a single shot, 2 and 3 are composites made after or while shooting,
+    /// anything else is unknown
+    fn field_is_composite(exif: &Exif) -> Option<bool> {
+        match exif
+            .get_field(Tag::CompositeImage, In::PRIMARY)?
+            .value
+            .get_uint(0)?
+        {
+            1 => Some(false),
+            2 | 3 => Some(true),
+            _ => None,
+        }
     }
-}
 
-impl From<exif::Error> for ImageError {
-    fn from(err: exif::Error) -> Self {
-        ImageError::ExifError(err)
+    fn field_sensing_method(exif: &Exif) -> Option<String> {
+        let code = exif
+            .get_field(Tag::SensingMethod, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        let method = match code {
+            1 => "Not defined",
+            2 => "One-chip color area",
+            3 => "Two-chip color area",
+            4 => "Three-chip color area",
+            5 => "Color sequential area",
+            7 => "Trilinear",
+            8 => "Color sequential linear",
+            _ => return Some(format!("Unknown ({})", code)),
+        };
+        Some(method.to_string())
     }
-}
 
-impl Display for ImageError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ImageError::IoError(err) => err.fmt(f),
-            ImageError::ExifError(err) => err.fmt(f),
-        }
+    fn field_compression(exif: &Exif, ifd: In) -> Option<String> {
+        let code = exif.get_field(Tag::Compression, ifd)?.value.get_uint(0)?;
+        let compression = match code {
+            // Exif reuses the old style JPEG code for thumbnails, which are plain jpegs
+            6 if ifd == In::THUMBNAIL => "JPEG",
+            1 => "Uncompressed",
+            2 => "CCITT 1D",
+            3 => "CCITT Group 3",
+            4 => "CCITT Group 4",
+            5 => "LZW",
+            6 => "JPEG (old style)",
+            7 => "JPEG",
+            8 => "Adobe Deflate",
+            32773 => "PackBits",
+            32946 => "Deflate",
+            34892 => "Lossy JPEG",
+            _ => return Some(format!("Unknown ({})", code)),
+        };
+        Some(compression.to_string())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use chrono::{Local, TimeZone};
-    use indoc::*;
+    fn field_photometric_interpretation(exif: &Exif) -> Option<String> {
+        let code = exif
+            .get_field(Tag::PhotometricInterpretation, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        let interpretation = match code {
+            0 => "WhiteIsZero",
+            1 => "BlackIsZero",
+            2 => "RGB",
+            3 => "RGB Palette",
+            4 => "Transparency Mask",
+            5 => "CMYK",
+            6 => "YCbCr",
+            8 => "CIELab",
+            9 => "ICCLab",
+            10 => "ITULab",
+            32803 => "Color Filter Array",
+            34892 => "Linear Raw",
+            _ => return Some(format!("Unknown ({})", code)),
+        };
+        Some(interpretation.to_string())
+    }
 
-    #[test]
-    fn test_file_metadata() {
-        let metadata = file_metadata("tests/images/JAM19896.jpg").unwrap();
-        assert_eq!(metadata.filename, "JAM19896.jpg".to_string());
-        assert_eq!(metadata.size, 953458);
-        // Git wont restore ctime/crtime so the best we can do is see if we get something sane
-        let created_time = metadata.created_time.expect("Created time expected");
-        let modified_time = metadata.created_time.expect("Modified time expected");
-        // A system time representing the start of 2020
-        let t_2020 = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
-        let now = Utc::now();
-        assert!(created_time > t_2020);
-        assert!(created_time <= now);
-        assert!(modified_time > t_2020);
-        assert!(modified_time <= now);
+    fn field_sensitivity_type(exif: &Exif) -> Option<String> {
+        let code = exif
+            .get_field(Tag::SensitivityType, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        let sensitivity_type = match code {
+            0 => "Unknown",
+            1 => "Standard output sensitivity",
+            2 => "Recommended exposure index",
+            3 => "ISO speed",
+            4 => "Standard output sensitivity and recommended exposure index",
+            5 => "Standard output sensitivity and ISO speed",
+            6 => "Recommended exposure index and ISO speed",
+            7 => "Standard output sensitivity, recommended exposure index and ISO speed",
+            _ => return Some(format!("Unknown ({})", code)),
+        };
+        Some(sensitivity_type.to_string())
     }
 
-    #[test]
-    fn test_image_metadata() {
-        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
-        assert_eq!(metadata.orientation, Some(1));
-        assert_eq!(
-            metadata.capture_time,
-            Some(Local.ymd(2019, 7, 26).and_hms(13, 25, 33).naive_local())
-        );
-        assert_eq!(
-            metadata.camera_model,
-            Some("Canon EOS 5D Mark IV".to_string())
-        );
-        assert_eq!(metadata.camera_serial, Some("025021000537".to_string()));
+    /// DNG records the noise reduction as a fraction, 0 for none through to 1 for full
+    fn field_noise_reduction(exif: &Exif) -> Option<String> {
+        let applied = field_rational(exif, TAG_NOISE_REDUCTION_APPLIED)?;
+        let noise_reduction = if applied <= 0.0 {
+            "Off"
+        } else if applied >= 1.0 {
+            "Full"
+        } else {
+            "Partial"
+        };
+        Some(noise_reduction.to_string())
     }
 
-    #[test]
-    fn test_write_metadata_to_file() -> Result<(), ImageError> {
-        let file_metadata = FileMetadata {
-            filename: "test.jpg".to_string(),
-            size: 123456,
-            created_time: Some(Utc.ymd(1986, 4, 27).and_hms(7, 30, 0)),
-            modified_time: None,
+    fn field_gain_control(exif: &Exif) -> Option<String> {
+        let code = exif
+            .get_field(Tag::GainControl, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        let gain = match code {
+            0 => "None",
+            1 => "Low gain up",
+            2 => "High gain up",
+            3 => "Low gain down",
+            4 => "High gain down",
+            _ => return Some(format!("Unknown ({})", code)),
         };
-        let image_metadata = ImageMetadata {
-            orientation: Some(1),
-            capture_time: Some(Local.ymd(1986, 4, 27).and_hms(7, 30, 0).naive_local()),
-            camera_model: None,
-            camera_serial: Some("baz".to_string()),
+        Some(gain.to_string())
+    }
+
+    /// Unlike most of the coded tags this is stored as a single UNDEFINED byte
+    fn field_file_source(exif: &Exif) -> Option<String> {
+        let code = match &exif.get_field(Tag::FileSource, In::PRIMARY)?.value {
+            exif::Value::Undefined(bytes, _) => *bytes.first()?,
+            value => value.get_uint(0)? as u8,
         };
-        let metadata = CombinedMetadata {
-            file_metadata,
-            image_metadata,
+        let source = match code {
+            0 => "Other",
+            1 => "Transparent scanner",
+            2 => "Reflex scanner",
+            3 => "Digital still camera",
+            _ => return Some(format!("Unknown ({})", code)),
+        };
+        Some(source.to_string())
+    }
+
+    fn field_gps_status(exif: &Exif) -> Option<String> {
+        let status = match field_str_unquoted(exif, Tag::GPSStatus)?.trim() {
+            "A" => "Measurement active".to_string(),
+            "V" => "Measurement void".to_string(),
+            other => format!("Unknown ({})", other),
+        };
+        Some(status)
+    }
+
+    fn field_gps_measure_mode(exif: &Exif) -> Option<String> {
+        let measure_mode = match field_str_unquoted(exif, Tag::GPSMeasureMode)?.trim() {
+            "2" => "2-dimensional".to_string(),
+            "3" => "3-dimensional".to_string(),
+            other => format!("Unknown ({})", other),
         };
+        Some(measure_mode)
+    }
 
-        let path = "target/test/metadata.json";
-        std::fs::create_dir_all("target/test")?;
+    /// Every GPS tag not already covered by one of the gps fields. The time stamp is
+    /// included as the gps_datetime is only worked out when there's a date stamp too
+    fn field_gps_extra(exif: &Exif) -> BTreeMap<String, String> {
+        const GPS_FIELD_TAGS: &[Tag] = &[
+            Tag::GPSVersionID,
+            Tag::GPSLatitudeRef,
+            Tag::GPSLatitude,
+            Tag::GPSLongitudeRef,
+            Tag::GPSLongitude,
+            Tag::GPSDateStamp,
+            Tag::GPSStatus,
+            Tag::GPSMeasureMode,
+            Tag::GPSTrackRef,
+            Tag::GPSTrack,
+            Tag::GPSDestLatitudeRef,
+            Tag::GPSDestLatitude,
+            Tag::GPSDestLongitudeRef,
+            Tag::GPSDestLongitude,
+            Tag::GPSDestBearing,
+            Tag::GPSAreaInformation,
+            Tag::GPSDifferential,
+            Tag::GPSHPositioningError,
+        ];
+        exif.fields()
+            .filter(|f| f.ifd_num == In::PRIMARY && f.tag.context() == exif::Context::Gps)
+            .filter(|f| !GPS_FIELD_TAGS.contains(&f.tag))
+            .map(|f| {
+                let value = f.display_value().with_unit(exif).to_string();
+                // Strings are displayed quoted
+                (f.tag.to_string(), value.trim_matches('"').to_string())
+            })
+            .collect()
+    }
 
-        write_metadata_to_file(path, &metadata)?;
+    fn field_gps_track_ref(exif: &Exif) -> Option<String> {
+        let track_ref = match field_str_unquoted(exif, Tag::GPSTrackRef)?.trim() {
+            "T" => "True north".to_string(),
+            "M" => "Magnetic north".to_string(),
+            other => format!("Unknown ({})", other),
+        };
+        Some(track_ref)
+    }
 
-        assert_eq!(
-            std::fs::read_to_string(path)?,
-            indoc! {r#"
+    /// Windows writes the star rating twice, as 0 to 5 and as a percentage, older tools
+    /// only wrote the percentage so that's the fallback. Windows maps 1 to 5 stars to
+    /// 1, 25, 50, 75 and 99 percent, the thresholds cover anything rounded to 0 or 100
+    fn field_rating(exif: &Exif) -> Option<u8> {
+        let rating = exif
+            .get_field(TAG_RATING, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            .filter(|rating| *rating <= 5);
+        if let Some(rating) = rating {
+            return Some(rating as u8);
+        }
+        let percent = exif
+            .get_field(TAG_RATING_PERCENT, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        match percent {
+            0 => Some(0),
+            1..=24 => Some(1),
+            25..=49 => Some(2),
+            50..=74 => Some(3),
+            75..=98 => Some(4),
+            99..=100 => Some(5),
+            _ => None,
+        }
+    }
+
+    /// Windows stores the keywords as bytes of null terminated UTF-16, always little
+    /// endian whatever the byte order of the file, separated by semicolons
+    fn field_keywords(exif: &Exif) -> Vec<String> {
+        let bytes = match exif
+            .get_field(TAG_XP_KEYWORDS, In::PRIMARY)
+            .map(|f| &f.value)
         {
-          "filename": "test.jpg",
-          "size": 123456,
-          "created_time": "1986-04-27T07:30:00Z",
-          "orientation": 1,
-          "capture_time": "1986-04-27T07:30:00",
-          "camera_serial": "baz"
-        }"#}
-        );
-        Ok(())
-    }
-
-    #[test]
-    fn test_process_file() -> Result<(), ImageError> {
-        // Lets copy our the file under target so we don't pollute the workspace
-        std::fs::create_dir_all("target/test")?;
-        let image_path = "target/test/JAM19896.jpg";
-        let expected_json_path = "target/test/JAM19896.json";
-        std::fs::copy("tests/images/JAM19896.jpg", image_path)?;
-
-        process_file(image_path)?;
-        let metadata: CombinedMetadata =
-            serde_json::from_slice(&std::fs::read(expected_json_path)?).unwrap();
-        assert_eq!(metadata.file_metadata.size, 953458);
-        Ok(())
+            Some(exif::Value::Byte(bytes)) => bytes,
+            _ => return vec![],
+        };
+        let units = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+            .split(';')
+            .map(str::trim)
+            .filter(|keyword| !keyword.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Free text tags like GPSAreaInformation are UNDEFINED bytes starting with an 8 byte
+    /// header naming the character set, unicode being UTF-16 in the file's byte order
+    fn field_encoded_text(exif: &Exif, tag: Tag) -> Option<String> {
+        let bytes = match &exif.get_field(tag, In::PRIMARY)?.value {
+            exif::Value::Undefined(bytes, _) if bytes.len() >= 8 => bytes,
+            _ => return None,
+        };
+        let (charset, text) = bytes.split_at(8);
+        let text = if charset == b"UNICODE\0" {
+            let units = text
+                .chunks_exact(2)
+                .map(|pair| {
+                    if exif.little_endian() {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        } else {
+            // ASCII or an undefined charset, in practice the latter is almost always
+            // ASCII or UTF-8. We don't decode JIS
+            String::from_utf8_lossy(text).into_owned()
+        };
+        Some(
+            text.trim_end_matches(|c: char| c == '\0' || c == ' ')
+                .to_string(),
+        )
+        .filter(|s| !s.is_empty())
+    }
+
+    /// The bytes of an UNDEFINED tag base64 encoded, for the blobs with no text form
+    fn field_base64(exif: &Exif, tag: Tag) -> Option<String> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            exif::Value::Undefined(bytes, _) if !bytes.is_empty() => Some(base64(bytes)),
+            _ => None,
+        }
+    }
+
+    /// The first value of a signed or unsigned rational tag as a float
+    fn field_rational(exif: &Exif, tag: Tag) -> Option<f64> {
+        let value = match &exif.get_field(tag, In::PRIMARY)?.value {
+            exif::Value::Rational(values) => values.first()?.to_f64(),
+            exif::Value::SRational(values) => values.first()?.to_f64(),
+            _ => return None,
+        };
+        Some(value).filter(|value| value.is_finite())
+    }
+
+    /// All the values of a numeric tag, or None if any of them are garbage, ie 0/0
+    fn field_f64s(exif: &Exif, tag: Tag) -> Option<Vec<f64>> {
+        let values = match &exif.get_field(tag, In::PRIMARY)?.value {
+            exif::Value::Rational(values) => values.iter().map(|v| v.to_f64()).collect(),
+            exif::Value::SRational(values) => values.iter().map(|v| v.to_f64()).collect(),
+            value => value.iter_uint()?.map(f64::from).collect::<Vec<_>>(),
+        };
+        Some(values).filter(|values| !values.is_empty() && values.iter().all(|v| v.is_finite()))
+    }
+
+    /// DNG color matrices are stored flattened, with 3 columns for X, Y and Z
+    fn field_color_matrix(exif: &Exif, tag: Tag) -> Option<Vec<Vec<f64>>> {
+        let values = field_f64s(exif, tag).filter(|values| values.len() % 3 == 0)?;
+        Some(values.chunks(3).map(|row| row.to_vec()).collect())
+    }
+
+    /// The lens specification is four rationals, the min and max focal lengths then the
+    /// max apertures at each. Unknown values are recorded as 0/0 and left out
+    fn field_lens_spec(exif: &Exif) -> Option<String> {
+        let spec = match &exif.get_field(Tag::LensSpecification, In::PRIMARY)?.value {
+            exif::Value::Rational(spec) if spec.len() == 4 => spec
+                .iter()
+                .map(|value| Some(value.to_f64()).filter(|v| v.is_finite() && *v > 0.0))
+                .collect::<Vec<_>>(),
+            _ => return None,
+        };
+        let range = |min: Option<f64>, max: Option<f64>| {
+            let round = |value: f64| (value * 10.0).round() / 10.0;
+            match (min.map(round), max.map(round)) {
+                (Some(min), Some(max)) if min < max => Some(format!("{}-{}", min, max)),
+                (Some(value), _) | (None, Some(value)) => Some(value.to_string()),
+                (None, None) => None,
+            }
+        };
+        let focal_length = range(spec[0], spec[1]).map(|range| format!("{}mm", range));
+        let aperture = range(spec[2], spec[3]).map(|range| format!("f/{}", range));
+        match (focal_length, aperture) {
+            (Some(focal_length), Some(aperture)) => Some(format!("{} {}", focal_length, aperture)),
+            (focal_length, aperture) => focal_length.or(aperture),
+        }
+    }
+
+    /// Falls back to the older SubjectLocation tag, which only ever holds a point
+    fn field_subject_area(exif: &Exif) -> Option<Vec<u32>> {
+        [Tag::SubjectArea, Tag::SubjectLocation]
+            .iter()
+            .filter_map(|tag| exif.get_field(*tag, In::PRIMARY))
+            .map(|field| {
+                field
+                    .value
+                    .iter_uint()
+                    .map(|values| values.collect::<Vec<_>>())
+            })
+            .next()
+            .flatten()
+            .filter(|values| (2..=4).contains(&values.len()))
+    }
+
+    /// Jpegs record their dimensions in the exif sub IFD while tiffs use the main IFD
+    fn field_dimension(exif: &Exif, tag: Tag, tiff_tag: Tag) -> Option<u32> {
+        exif.get_field(tag, In::PRIMARY)
+            .or_else(|| exif.get_field(tiff_tag, In::PRIMARY))
+            .and_then(|f| f.value.get_uint(0))
+    }
+
+    let mut warnings = vec![];
+
+    let orientation = exif
+        .get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0));
+    let thumbnail_orientation = exif
+        .get_field(Tag::Orientation, In::THUMBNAIL)
+        .and_then(|f| f.value.get_uint(0));
+    if let (Some(primary), Some(thumbnail)) = (orientation, thumbnail_orientation) {
+        if primary != thumbnail {
+            warnings.push(format!(
+                "Orientation {} doesn't match the thumbnail orientation {}",
+                primary, thumbnail
+            ));
+        }
+    }
+
+    if let Some(orientation) = orientation.filter(|o| !(1..=8).contains(o)) {
+        warnings.push(format!("Ignoring out of range orientation {}", orientation));
+    }
+    let orientation = orientation.filter(|o| (1..=8).contains(o));
+    // Unknown dates are meant to be written as blanks, anything else that doesn't
+    // parse is malformed
+    for (name, tag) in &[
+        ("capture time", Tag::DateTimeOriginal),
+        ("digitized time", Tag::DateTimeDigitized),
+        ("modify datetime", Tag::DateTime),
+    ] {
+        if let Some(s) = field_str_unquoted(exif, *tag) {
+            let blank = s.chars().all(|c| c == ' ' || c == ':' || c == '\0');
+            if !blank && NaiveDateTime::parse_from_str(&s, "%Y:%m:%d %H:%M:%S").is_err() {
+                warnings.push(format!("Ignoring malformed {} \"{}\"", name, s));
+            }
+        }
+    }
+
+    let pixel_width = field_dimension(exif, Tag::PixelXDimension, Tag::ImageWidth);
+    let pixel_height = field_dimension(exif, Tag::PixelYDimension, Tag::ImageLength);
+    // Buggy cameras occasionally write coordinates that can't exist, better to have
+    // no location than one that breaks whatever tries to map it
+    let mut validate_coordinate = |value: Option<f64>, limit: f64, name: &str| {
+        value.filter(|value| {
+            let valid = (-limit..=limit).contains(value);
+            if !valid {
+                warnings.push(format!("Ignoring out of range GPS {} {}", name, value));
+            }
+            valid
+        })
+    };
+    let gps_latitude = validate_coordinate(
+        field_gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef),
+        90.0,
+        "latitude",
+    );
+    let gps_longitude = validate_coordinate(
+        field_gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef),
+        180.0,
+        "longitude",
+    );
+    let gps_dest_latitude = validate_coordinate(
+        field_gps_coordinate(exif, Tag::GPSDestLatitude, Tag::GPSDestLatitudeRef),
+        90.0,
+        "destination latitude",
+    );
+    let gps_dest_longitude = validate_coordinate(
+        field_gps_coordinate(exif, Tag::GPSDestLongitude, Tag::GPSDestLongitudeRef),
+        180.0,
+        "destination longitude",
+    );
+
+    let shutter_speed_apex = field_rational(exif, Tag::ShutterSpeedValue);
+    let exposure_time = field_rational(exif, Tag::ExposureTime)
+        .or_else(|| shutter_speed_apex.map(|apex| 2f64.powf(-apex)));
+    let aperture_apex = field_rational(exif, Tag::ApertureValue);
+    // sqrt(2)^APEX, written as a power of 2 so whole stops come out exact
+    let f_number = field_rational(exif, Tag::FNumber)
+        .or_else(|| aperture_apex.map(|apex| 2f64.powf(apex / 2.0)));
+
+    let mut metadata = ImageMetadata {
+        orientation,
+        orientation_degrees: None,
+        orientation_mirrored: None,
+        thumbnail_orientation,
+        thumbnail_width: exif
+            .get_field(Tag::ImageWidth, In::THUMBNAIL)
+            .and_then(|f| f.value.get_uint(0)),
+        thumbnail_height: exif
+            .get_field(Tag::ImageLength, In::THUMBNAIL)
+            .and_then(|f| f.value.get_uint(0)),
+        thumbnail_display_width: None,
+        thumbnail_display_height: None,
+        pixel_width,
+        pixel_height,
+        display_width: None,
+        display_height: None,
+        capture_time: field_datetime(exif, Tag::DateTimeOriginal, Tag::SubSecTimeOriginal),
+        digitized_time: field_datetime(exif, Tag::DateTimeDigitized, Tag::SubSecTimeDigitized),
+        modify_datetime: field_datetime(exif, Tag::DateTime, Tag::SubSecTime),
+        capture_time_offset: field_str_unquoted(exif, Tag::OffsetTimeOriginal),
+        timezone_offset: makernote::timezone_offset(exif),
+        camera_model: field_str_unquoted(exif, Tag::Model),
+        camera_serial: field_str_unquoted(exif, Tag::BodySerialNumber),
+        camera_owner: field_str_unquoted(exif, Tag::CameraOwnerName),
+        lens_model: field_str_unquoted(exif, Tag::LensModel)
+            .filter(|s| !s.trim().is_empty())
+            .or_else(|| makernote::lens_model(exif)),
+        lens_serial: field_str_unquoted(exif, Tag::LensSerialNumber),
+        lens_spec: field_lens_spec(exif),
+        image_unique_id: field_str_unquoted(exif, Tag::ImageUniqueID),
+        is_composite: field_is_composite(exif),
+        description: field_str_unquoted(exif, Tag::ImageDescription).and_then(trim_description),
+        rating: field_rating(exif),
+        keywords: field_keywords(exif),
+        sensing_method: field_sensing_method(exif),
+        file_source: field_file_source(exif),
+        compression: field_compression(exif, In::PRIMARY),
+        photometric_interpretation: field_photometric_interpretation(exif),
+        thumbnail_compression: field_compression(exif, In::THUMBNAIL),
+        gain_control: field_gain_control(exif),
+        iso: exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0)),
+        sensitivity_type: field_sensitivity_type(exif),
+        recommended_exposure_index: exif
+            .get_field(Tag::RecommendedExposureIndex, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0)),
+        spectral_sensitivity: field_str_unquoted(exif, Tag::SpectralSensitivity)
+            .map(|s| s.trim_end_matches('\0').to_string())
+            .filter(|s| !s.is_empty()),
+        oecf: field_base64(exif, Tag::OECF),
+        white_balance_kelvin: makernote::white_balance_kelvin(exif),
+        noise_reduction: field_noise_reduction(exif),
+        long_exposure_noise_reduction: makernote::long_exposure_noise_reduction(exif),
+        camera_temperature_c: makernote::camera_temperature_c(exif),
+        #[cfg(feature = "af-points")]
+        af_points_used: makernote::af_points_used(exif),
+        has_icc_profile: None,
+        mpf_image_count: None,
+        mpf_images: None,
+        num_components: None,
+        as_shot_neutral: field_f64s(exif, TAG_AS_SHOT_NEUTRAL),
+        color_matrix_1: field_color_matrix(exif, TAG_COLOR_MATRIX_1),
+        color_matrix_2: field_color_matrix(exif, TAG_COLOR_MATRIX_2),
+        exposure_time,
+        shutter_speed_apex,
+        f_number,
+        aperture_apex,
+        subject_area: field_subject_area(exif),
+        gps_latitude,
+        gps_longitude,
+        geohash: None,
+        gps_datetime: field_gps_datetime(exif),
+        gps_differential: exif
+            .get_field(Tag::GPSDifferential, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            .and_then(|differential| match differential {
+                0 => Some(false),
+                1 => Some(true),
+                _ => None,
+            }),
+        gps_status: field_gps_status(exif),
+        gps_measure_mode: field_gps_measure_mode(exif),
+        gps_area_information: field_encoded_text(exif, Tag::GPSAreaInformation),
+        gps_version: exif
+            .get_field(Tag::GPSVersionID, In::PRIMARY)
+            .and_then(|f| match &f.value {
+                exif::Value::Byte(version) if !version.is_empty() => Some(
+                    version
+                        .iter()
+                        .map(|part| part.to_string())
+                        .collect::<Vec<_>>()
+                        .join("."),
+                ),
+                _ => None,
+            }),
+        gps_h_positioning_error_m: field_rational(exif, Tag::GPSHPositioningError),
+        gps_dest_latitude,
+        gps_dest_longitude,
+        gps_dest_bearing: field_rational(exif, Tag::GPSDestBearing),
+        gps_track: field_rational(exif, Tag::GPSTrack),
+        gps_track_ref: field_gps_track_ref(exif),
+        gps_extra: field_gps_extra(exif),
+        warnings,
+        // Only worked out when they're wanted, see native::extract_reader_with_options
+        tag_types: vec![],
+    };
+    metadata.derive_fields();
+    metadata
+}
+
+/// Top level representation of any error that might happen during image processing
+#[derive(Debug)]
+pub enum ImageError {
+    /// An IO Error and the path that caused it
+    IoError(std::io::Error),
+    ExifError(exif::Error),
+    #[cfg(feature = "zip")]
+    ZipError(zip::result::ZipError),
+    #[cfg(feature = "rusqlite")]
+    SqliteError(rusqlite::Error),
+    /// Boxed as ureq's errors can hold a whole response
+    #[cfg(feature = "http")]
+    HttpError(Box<ureq::Error>),
+    /// Decoding or re-encoding an image to rotate it failed
+    #[cfg(feature = "image")]
+    RotateError(image::ImageError),
+    /// Processing took longer than the allowed time
+    Timeout(std::time::Duration),
+    /// Processing panicked on a worker thread, with the panic's message
+    WorkerPanicked(String),
+}
+
+impl From<std::io::Error> for ImageError {
+    fn from(err: std::io::Error) -> Self {
+        ImageError::IoError(err)
+    }
+}
+
+impl From<exif::Error> for ImageError {
+    fn from(err: exif::Error) -> Self {
+        ImageError::ExifError(err)
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<zip::result::ZipError> for ImageError {
+    fn from(err: zip::result::ZipError) -> Self {
+        ImageError::ZipError(err)
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl From<rusqlite::Error> for ImageError {
+    fn from(err: rusqlite::Error) -> Self {
+        ImageError::SqliteError(err)
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<image::ImageError> for ImageError {
+    fn from(err: image::ImageError) -> Self {
+        ImageError::RotateError(err)
+    }
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::IoError(err) => err.fmt(f),
+            ImageError::ExifError(err) => err.fmt(f),
+            #[cfg(feature = "zip")]
+            ImageError::ZipError(err) => err.fmt(f),
+            #[cfg(feature = "rusqlite")]
+            ImageError::SqliteError(err) => err.fmt(f),
+            #[cfg(feature = "http")]
+            ImageError::HttpError(err) => err.fmt(f),
+            #[cfg(feature = "image")]
+            ImageError::RotateError(err) => err.fmt(f),
+            ImageError::Timeout(timeout) => {
+                write!(f, "Timed out after {} seconds", timeout.as_secs_f64())
+            }
+            ImageError::WorkerPanicked(message) => write!(f, "Panicked: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use exif::experimental::Writer;
+    use exif::{Field, Value};
+    use std::io::Cursor;
+
+    /// Reads the metadata from one of the sample images, this goes via the bytes so the
+    /// tests don't need the native file handling
+    fn image_metadata(path: &str) -> Result<ImageMetadata, ImageError> {
+        extract_metadata_from_bytes(&std::fs::read(path)?)
+    }
+
+    #[test]
+    fn test_image_metadata() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.orientation, Some(1));
+        assert_eq!(
+            metadata.capture_time,
+            Some(Local.ymd(2019, 7, 26).and_hms(13, 25, 33).naive_local())
+        );
+        assert_eq!(
+            metadata.camera_model,
+            Some("Canon EOS 5D Mark IV".to_string())
+        );
+        assert_eq!(metadata.camera_serial, Some("025021000537".to_string()));
+    }
+
+    /// Builds up an in memory exif structure from the given fields, this lets us test
+    /// fields that our sample images don't carry
+    fn synthetic_exif(fields: &[Field]) -> Exif {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        exif::Reader::new().read_raw(buf.into_inner()).unwrap()
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        }
+    }
+
+    fn dms(degrees: u32, minutes: u32, seconds: u32) -> Value {
+        Value::Rational(vec![
+            (degrees, 1).into(),
+            (minutes, 1).into(),
+            (seconds, 1).into(),
+        ])
+    }
+
+    #[test]
+    fn test_extract_metadata_from_bytes() {
+        let bytes = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let metadata = extract_metadata_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            metadata,
+            image_metadata("tests/images/JAM19896.jpg").unwrap()
+        );
+        assert_eq!(
+            metadata.camera_model,
+            Some("Canon EOS 5D Mark IV".to_string())
+        );
+
+        assert!(extract_metadata_from_bytes(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_malformed_warnings() {
+        let exif = synthetic_exif(&[
+            field(
+                Tag::DateTimeOriginal,
+                Value::Ascii(vec![b"2019-07-26 13:25:33".to_vec()]),
+            ),
+            field(
+                Tag::DateTime,
+                Value::Ascii(vec![b"    :  :     :  :  ".to_vec()]),
+            ),
+            field(Tag::Orientation, Value::Short(vec![9])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.capture_time, None);
+        assert_eq!(metadata.orientation, None);
+        assert_eq!(
+            metadata.warnings,
+            vec![
+                "Ignoring out of range orientation 9".to_string(),
+                "Ignoring malformed capture time \"2019-07-26 13:25:33\"".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thumbnail_orientation() {
+        let exif = synthetic_exif(&[
+            field(Tag::Orientation, Value::Short(vec![1])),
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::THUMBNAIL,
+                value: Value::Short(vec![6]),
+            },
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.orientation, Some(1));
+        assert_eq!(metadata.thumbnail_orientation, Some(6));
+        assert_eq!(
+            metadata.warnings,
+            vec!["Orientation 1 doesn't match the thumbnail orientation 6".to_string()]
+        );
+
+        let exif = synthetic_exif(&[
+            field(Tag::Orientation, Value::Short(vec![6])),
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::THUMBNAIL,
+                value: Value::Short(vec![6]),
+            },
+        ]);
+        assert!(exif_metadata(&exif).warnings.is_empty());
+    }
+
+    #[test]
+    fn test_is_composite() {
+        let is_composite = |value: u16| {
+            let exif = synthetic_exif(&[field(Tag::CompositeImage, Value::Short(vec![value]))]);
+            exif_metadata(&exif).is_composite
+        };
+        assert_eq!(is_composite(0), None);
+        assert_eq!(is_composite(1), Some(false));
+        assert_eq!(is_composite(2), Some(true));
+        assert_eq!(is_composite(3), Some(true));
+        let exif = synthetic_exif(&[field(Tag::Orientation, Value::Short(vec![1]))]);
+        assert_eq!(exif_metadata(&exif).is_composite, None);
+    }
+
+    #[test]
+    fn test_camera_owner() {
+        // None of our samples have the owner set
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.camera_owner, None);
+
+        let exif = synthetic_exif(&[field(
+            Tag::CameraOwnerName,
+            Value::Ascii(vec![b"Wanaka Photo Studio".to_vec()]),
+        )]);
+        assert_eq!(
+            exif_metadata(&exif).camera_owner,
+            Some("Wanaka Photo Studio".to_string())
+        );
+    }
+
+    #[test]
+    fn test_description() {
+        let description = |raw: &[u8]| {
+            let exif = synthetic_exif(&[field(
+                Tag::ImageDescription,
+                Value::Ascii(vec![raw.to_vec()]),
+            )]);
+            exif_metadata(&exif).description
+        };
+        assert_eq!(
+            description(b"Sunrise over Lake Wanaka"),
+            Some("Sunrise over Lake Wanaka".to_string())
+        );
+        assert_eq!(
+            description(b"Sunrise over Lake Wanaka\0\0\0\0"),
+            Some("Sunrise over Lake Wanaka".to_string())
+        );
+        assert_eq!(description(b"                "), None);
+    }
+
+    #[test]
+    fn test_sensing_method() {
+        let sensing_method = |code: u16| {
+            let exif = synthetic_exif(&[field(Tag::SensingMethod, Value::Short(vec![code]))]);
+            exif_metadata(&exif).sensing_method
+        };
+        assert_eq!(sensing_method(2), Some("One-chip color area".to_string()));
+        assert_eq!(sensing_method(7), Some("Trilinear".to_string()));
+        assert_eq!(sensing_method(6), Some("Unknown (6)".to_string()));
+    }
+
+    #[test]
+    fn test_file_source() {
+        let file_source = |code: u8| {
+            let exif = synthetic_exif(&[field(Tag::FileSource, Value::Undefined(vec![code], 0))]);
+            exif_metadata(&exif).file_source
+        };
+        assert_eq!(file_source(3), Some("Digital still camera".to_string()));
+        assert_eq!(file_source(2), Some("Reflex scanner".to_string()));
+        assert_eq!(file_source(9), Some("Unknown (9)".to_string()));
+    }
+
+    #[test]
+    fn test_compression_and_photometric_interpretation() {
+        let exif = synthetic_exif(&[
+            field(Tag::Compression, Value::Short(vec![5])),
+            field(Tag::PhotometricInterpretation, Value::Short(vec![2])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.compression, Some("LZW".to_string()));
+        assert_eq!(metadata.photometric_interpretation, Some("RGB".to_string()));
+
+        let exif = synthetic_exif(&[
+            field(Tag::Compression, Value::Short(vec![7])),
+            field(Tag::PhotometricInterpretation, Value::Short(vec![32803])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.compression, Some("JPEG".to_string()));
+        assert_eq!(
+            metadata.photometric_interpretation,
+            Some("Color Filter Array".to_string())
+        );
+
+        let exif = synthetic_exif(&[
+            field(Tag::Compression, Value::Short(vec![99])),
+            field(Tag::PhotometricInterpretation, Value::Short(vec![7])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.compression, Some("Unknown (99)".to_string()));
+        assert_eq!(
+            metadata.photometric_interpretation,
+            Some("Unknown (7)".to_string())
+        );
+
+        // Jpegs only record the compression of their thumbnail
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.compression, None);
+        assert_eq!(metadata.thumbnail_compression, Some("JPEG".to_string()));
+
+        let exif = synthetic_exif(&[
+            field(Tag::Orientation, Value::Short(vec![1])),
+            Field {
+                tag: Tag::Compression,
+                ifd_num: In::THUMBNAIL,
+                value: Value::Short(vec![1]),
+            },
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.compression, None);
+        assert_eq!(
+            metadata.thumbnail_compression,
+            Some("Uncompressed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_gain_control() {
+        let gain_control = |code: u16| {
+            let exif = synthetic_exif(&[field(Tag::GainControl, Value::Short(vec![code]))]);
+            exif_metadata(&exif).gain_control
+        };
+        assert_eq!(gain_control(0), Some("None".to_string()));
+        assert_eq!(gain_control(1), Some("Low gain up".to_string()));
+        assert_eq!(gain_control(2), Some("High gain up".to_string()));
+        assert_eq!(gain_control(3), Some("Low gain down".to_string()));
+        assert_eq!(gain_control(4), Some("High gain down".to_string()));
+        assert_eq!(gain_control(5), Some("Unknown (5)".to_string()));
+    }
+
+    #[test]
+    fn test_sensitivity() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(
+            metadata.sensitivity_type,
+            Some("Recommended exposure index".to_string())
+        );
+        assert_eq!(metadata.iso, Some(1000));
+        assert_eq!(metadata.recommended_exposure_index, Some(1000));
+
+        let exif = synthetic_exif(&[
+            field(Tag::SensitivityType, Value::Short(vec![9])),
+            field(Tag::RecommendedExposureIndex, Value::Long(vec![102_400])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.sensitivity_type, Some("Unknown (9)".to_string()));
+        assert_eq!(metadata.recommended_exposure_index, Some(102_400));
+    }
+
+    #[test]
+    fn test_noise_reduction() {
+        let noise_reduction = |applied: (u32, u32)| {
+            let exif = synthetic_exif(&[field(
+                TAG_NOISE_REDUCTION_APPLIED,
+                Value::Rational(vec![applied.into()]),
+            )]);
+            exif_metadata(&exif).noise_reduction
+        };
+        assert_eq!(noise_reduction((0, 1)), Some("Off".to_string()));
+        assert_eq!(noise_reduction((1, 2)), Some("Partial".to_string()));
+        assert_eq!(noise_reduction((1, 1)), Some("Full".to_string()));
+        assert_eq!(noise_reduction((1, 0)), None);
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.noise_reduction, None);
+        assert_eq!(metadata.long_exposure_noise_reduction, None);
+        assert_eq!(metadata.camera_temperature_c, None);
+    }
+
+    #[test]
+    fn test_dng_color() {
+        let srationals =
+            |values: &[i32]| Value::SRational(values.iter().map(|v| (*v, 10000).into()).collect());
+        let exif = synthetic_exif(&[
+            field(Tag::Make, Value::Ascii(vec![b"Canon".to_vec()])),
+            field(
+                TAG_AS_SHOT_NEUTRAL,
+                Value::Rational(vec![
+                    (4700, 10000).into(),
+                    (1, 1).into(),
+                    (6400, 10000).into(),
+                ]),
+            ),
+            field(
+                TAG_COLOR_MATRIX_1,
+                srationals(&[6875, -970, -932, -4691, 12459, 2501, -874, 1953, 5809]),
+            ),
+            field(TAG_COLOR_MATRIX_2, srationals(&[6875, -970])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.as_shot_neutral, Some(vec![0.47, 1.0, 0.64]));
+        assert_eq!(
+            metadata.color_matrix_1,
+            Some(vec![
+                vec![0.6875, -0.097, -0.0932],
+                vec![-0.4691, 1.2459, 0.2501],
+                vec![-0.0874, 0.1953, 0.5809],
+            ])
+        );
+        // Not a whole number of rows
+        assert_eq!(metadata.color_matrix_2, None);
+
+        let exif = synthetic_exif(&[field(
+            TAG_AS_SHOT_NEUTRAL,
+            Value::Rational(vec![(1, 0).into(), (1, 1).into(), (1, 1).into()]),
+        )]);
+        assert_eq!(exif_metadata(&exif).as_shot_neutral, None);
+    }
+
+    #[test]
+    fn test_display_dimensions() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.orientation, Some(1));
+        assert_eq!(
+            (metadata.pixel_width, metadata.pixel_height),
+            (Some(5040), Some(3360))
+        );
+        assert_eq!(
+            (metadata.display_width, metadata.display_height),
+            (Some(5040), Some(3360))
+        );
+
+        // Our rotated sample doesn't record its dimensions
+        let exif = synthetic_exif(&[
+            field(Tag::Orientation, Value::Short(vec![6])),
+            field(Tag::ImageWidth, Value::Long(vec![5040])),
+            field(Tag::ImageLength, Value::Long(vec![3360])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(
+            (metadata.pixel_width, metadata.pixel_height),
+            (Some(5040), Some(3360))
+        );
+        assert_eq!(
+            (metadata.display_width, metadata.display_height),
+            (Some(3360), Some(5040))
+        );
+    }
+
+    #[test]
+    fn test_thumbnail_display_dimensions() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(
+            (metadata.thumbnail_width, metadata.thumbnail_height),
+            (Some(256), Some(170))
+        );
+        assert_eq!(
+            (
+                metadata.thumbnail_display_width,
+                metadata.thumbnail_display_height
+            ),
+            (Some(256), Some(170))
+        );
+
+        // The thumbnail doesn't record an orientation of its own so it takes the main one
+        let metadata = image_metadata("tests/images/rotated_CCW90.jpg").unwrap();
+        assert_eq!(metadata.orientation, Some(6));
+        assert_eq!(
+            (metadata.thumbnail_width, metadata.thumbnail_height),
+            (Some(170), Some(256))
+        );
+        assert_eq!(
+            (
+                metadata.thumbnail_display_width,
+                metadata.thumbnail_display_height
+            ),
+            (Some(256), Some(170))
+        );
+
+        // When it does its own orientation wins
+        let thumbnail = |tag: Tag, value: Value| Field {
+            tag,
+            ifd_num: In::THUMBNAIL,
+            value,
+        };
+        let exif = synthetic_exif(&[
+            field(Tag::Orientation, Value::Short(vec![1])),
+            thumbnail(Tag::Orientation, Value::Short(vec![8])),
+            thumbnail(Tag::ImageWidth, Value::Long(vec![160])),
+            thumbnail(Tag::ImageLength, Value::Long(vec![120])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(
+            (
+                metadata.thumbnail_display_width,
+                metadata.thumbnail_display_height
+            ),
+            (Some(120), Some(160))
+        );
+    }
+
+    #[test]
+    fn test_orientation_degrees() {
+        let expected = [
+            (1, 0, false),
+            (2, 0, true),
+            (3, 180, false),
+            (4, 180, true),
+            (5, 270, true),
+            (6, 90, false),
+            (7, 90, true),
+            (8, 270, false),
+        ];
+        for (orientation, degrees, mirrored) in &expected {
+            let exif = synthetic_exif(&[field(Tag::Orientation, Value::Short(vec![*orientation]))]);
+            let metadata = exif_metadata(&exif);
+            assert_eq!(metadata.orientation_degrees, Some(*degrees));
+            assert_eq!(metadata.orientation_mirrored, Some(*mirrored));
+        }
+
+        let exif = synthetic_exif(&[field(Tag::Orientation, Value::Short(vec![9]))]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.orientation_degrees, None);
+        assert_eq!(metadata.orientation_mirrored, None);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_normalize_unicode() {
+        let mut metadata = ImageMetadata {
+            camera_model: Some("Cafe\u{301} Cam".to_string()),
+            description: Some("Caf\u{e9}".to_string()),
+            ..Default::default()
+        };
+        metadata.normalize_unicode();
+        assert_eq!(metadata.camera_model, Some("Caf\u{e9} Cam".to_string()));
+        assert_eq!(metadata.description, Some("Caf\u{e9}".to_string()));
+    }
+
+    #[test]
+    fn test_image_datetimes() {
+        let exif = synthetic_exif(&[
+            field(
+                Tag::DateTimeOriginal,
+                Value::Ascii(vec![b"1986:04:27 07:30:00".to_vec()]),
+            ),
+            field(
+                Tag::DateTimeDigitized,
+                Value::Ascii(vec![b"2005:11:02 16:45:10".to_vec()]),
+            ),
+            field(
+                Tag::DateTime,
+                Value::Ascii(vec![b"2020:08:14 12:04:00".to_vec()]),
+            ),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(
+            metadata.capture_time,
+            Some(NaiveDate::from_ymd(1986, 4, 27).and_hms(7, 30, 0))
+        );
+        assert_eq!(
+            metadata.digitized_time,
+            Some(NaiveDate::from_ymd(2005, 11, 2).and_hms(16, 45, 10))
+        );
+        assert_eq!(
+            metadata.modify_datetime,
+            Some(NaiveDate::from_ymd(2020, 8, 14).and_hms(12, 4, 0))
+        );
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.digitized_time, metadata.capture_time);
+        assert_eq!(
+            metadata.modify_datetime,
+            Some(Local.ymd(2020, 8, 14).and_hms(12, 4, 0).naive_local())
+        );
+    }
+
+    #[test]
+    fn test_image_datetimes_subsec() {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let exif = synthetic_exif(&[
+            field(Tag::DateTimeOriginal, ascii("1986:04:27 07:30:00")),
+            field(Tag::SubSecTimeOriginal, ascii("5")),
+            field(Tag::DateTimeDigitized, ascii("2005:11:02 16:45:10")),
+            field(Tag::SubSecTimeDigitized, ascii("25")),
+            field(Tag::DateTime, ascii("2020:08:14 12:04:00")),
+            field(Tag::SubSecTime, ascii("125")),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(
+            metadata.capture_time,
+            Some(NaiveDate::from_ymd(1986, 4, 27).and_hms_milli(7, 30, 0, 500))
+        );
+        assert_eq!(
+            metadata.digitized_time,
+            Some(NaiveDate::from_ymd(2005, 11, 2).and_hms_milli(16, 45, 10, 250))
+        );
+        assert_eq!(
+            metadata.modify_datetime,
+            Some(NaiveDate::from_ymd(2020, 8, 14).and_hms_milli(12, 4, 0, 125))
+        );
+
+        // Our samples have the subsecond tags
+        let metadata = image_metadata("tests/images/JAM26496.jpg").unwrap();
+        assert_eq!(
+            metadata.capture_time,
+            Some(NaiveDate::from_ymd(2020, 1, 30).and_hms_milli(9, 44, 56, 340))
+        );
+    }
+
+    #[test]
+    fn test_gps_coordinates() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSLatitude, dms(44, 42, 0)),
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"S".to_vec()])),
+            field(Tag::GPSLongitude, dms(169, 9, 36)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![b"E".to_vec()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_latitude, Some(-44.7));
+        assert_eq!(metadata.gps_longitude, Some(169.16));
+        assert_eq!(metadata.geohash.as_deref(), Some("rb039qde9"));
+        assert!(metadata.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_exposure_time() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.exposure_time, Some(1.0 / 640.0));
+        assert_eq!(metadata.shutter_speed_apex, Some(9.375));
+    }
+
+    #[test]
+    fn test_exposure_time_from_shutter_speed() {
+        let exif = synthetic_exif(&[field(
+            Tag::ShutterSpeedValue,
+            Value::SRational(vec![(8, 1).into()]),
+        )]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.shutter_speed_apex, Some(8.0));
+        assert_eq!(metadata.exposure_time, Some(1.0 / 256.0));
+
+        // Negative APEX values are exposures longer than a second
+        let exif = synthetic_exif(&[field(
+            Tag::ShutterSpeedValue,
+            Value::SRational(vec![(-2, 1).into()]),
+        )]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.shutter_speed_apex, Some(-2.0));
+        assert_eq!(metadata.exposure_time, Some(4.0));
+    }
+
+    #[test]
+    fn test_f_number() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.f_number, Some(4.0));
+        assert_eq!(metadata.aperture_apex, Some(4.0));
+    }
+
+    #[test]
+    fn test_f_number_from_aperture_value() {
+        let exif = synthetic_exif(&[field(
+            Tag::ApertureValue,
+            Value::Rational(vec![(6, 1).into()]),
+        )]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.aperture_apex, Some(6.0));
+        assert_eq!(metadata.f_number, Some(8.0));
+
+        // APEX 5 is the f/5.6 stop, more precisely 4 * sqrt(2)
+        let exif = synthetic_exif(&[field(
+            Tag::ApertureValue,
+            Value::Rational(vec![(5, 1).into()]),
+        )]);
+        let f_number = exif_metadata(&exif).f_number.unwrap();
+        assert!((f_number - 5.656_854).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lens() {
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(
+            metadata.lens_model,
+            Some("EF35mm f/1.4L II USM".to_string())
+        );
+        assert_eq!(metadata.lens_serial, Some("4200000392".to_string()));
+        // The apertures are recorded as unknown
+        assert_eq!(metadata.lens_spec, Some("35mm".to_string()));
+
+        let lens_spec = |spec: &[(u32, u32)]| {
+            let spec = spec.iter().map(|&value| value.into()).collect();
+            let exif = synthetic_exif(&[field(Tag::LensSpecification, Value::Rational(spec))]);
+            exif_metadata(&exif).lens_spec
+        };
+        assert_eq!(
+            lens_spec(&[(24, 1), (70, 1), (28, 10), (28, 10)]),
+            Some("24-70mm f/2.8".to_string())
+        );
+        assert_eq!(
+            lens_spec(&[(18, 1), (55, 1), (35, 10), (56, 10)]),
+            Some("18-55mm f/3.5-5.6".to_string())
+        );
+        assert_eq!(lens_spec(&[(0, 0), (0, 0), (0, 0), (0, 0)]), None);
+    }
+
+    #[test]
+    fn test_image_unique_id() {
+        let exif = synthetic_exif(&[field(
+            Tag::ImageUniqueID,
+            Value::Ascii(vec![b"0123456789abcdef0123456789abcdef".to_vec()]),
+        )]);
+        assert_eq!(
+            exif_metadata(&exif).image_unique_id,
+            Some("0123456789abcdef0123456789abcdef".to_string())
+        );
+    }
+
+    #[test]
+    fn test_spectral_sensitivity_and_oecf() {
+        let exif = synthetic_exif(&[
+            field(
+                Tag::SpectralSensitivity,
+                Value::Ascii(vec![b"R: 610nm, G: 540nm, B: 465nm".to_vec()]),
+            ),
+            field(Tag::OECF, Value::Undefined(vec![0, 2, 0, 1, b'E', b'V'], 0)),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(
+            metadata.spectral_sensitivity,
+            Some("R: 610nm, G: 540nm, B: 465nm".to_string())
+        );
+        assert_eq!(metadata.oecf, Some("AAIAAUVW".to_string()));
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.spectral_sensitivity, None);
+        assert_eq!(metadata.oecf, None);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(&[0xFF, 0xEF, 0x00, 0x3E]), "/+8APg==");
+    }
+
+    #[test]
+    fn test_subject_area() {
+        let exif = synthetic_exif(&[field(
+            Tag::SubjectArea,
+            Value::Short(vec![2520, 1680, 400, 300]),
+        )]);
+        assert_eq!(
+            exif_metadata(&exif).subject_area,
+            Some(vec![2520, 1680, 400, 300])
+        );
+
+        let exif = synthetic_exif(&[field(Tag::SubjectLocation, Value::Short(vec![2520, 1680]))]);
+        assert_eq!(exif_metadata(&exif).subject_area, Some(vec![2520, 1680]));
+
+        let exif = synthetic_exif(&[field(Tag::SubjectArea, Value::Short(vec![2520]))]);
+        assert_eq!(exif_metadata(&exif).subject_area, None);
+    }
+
+    #[test]
+    fn test_rating() {
+        let exif = synthetic_exif(&[
+            field(TAG_RATING, Value::Short(vec![4])),
+            field(TAG_RATING_PERCENT, Value::Short(vec![75])),
+        ]);
+        assert_eq!(exif_metadata(&exif).rating, Some(4));
+    }
+
+    #[test]
+    fn test_rating_from_percent() {
+        for (percent, rating) in &[(0, 0), (1, 1), (25, 2), (50, 3), (75, 4), (99, 5), (100, 5)] {
+            let exif = synthetic_exif(&[field(TAG_RATING_PERCENT, Value::Short(vec![*percent]))]);
+            assert_eq!(exif_metadata(&exif).rating, Some(*rating));
+        }
+        let exif = synthetic_exif(&[field(TAG_RATING_PERCENT, Value::Short(vec![101]))]);
+        assert_eq!(exif_metadata(&exif).rating, None);
+    }
+
+    #[test]
+    fn test_keywords() {
+        let mut bytes = vec![];
+        for unit in "Wānaka; lake;;sunset\0".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        let exif = synthetic_exif(&[field(TAG_XP_KEYWORDS, Value::Byte(bytes))]);
+        assert_eq!(
+            exif_metadata(&exif).keywords,
+            vec![
+                "Wānaka".to_string(),
+                "lake".to_string(),
+                "sunset".to_string()
+            ]
+        );
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert!(metadata.keywords.is_empty());
+    }
+
+    #[test]
+    fn test_gps_differential_and_status() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSDifferential, Value::Short(vec![1])),
+            field(Tag::GPSStatus, Value::Ascii(vec![b"A".to_vec()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_differential, Some(true));
+        assert_eq!(metadata.gps_status, Some("Measurement active".to_string()));
+
+        let exif = synthetic_exif(&[
+            field(Tag::GPSDifferential, Value::Short(vec![0])),
+            field(Tag::GPSStatus, Value::Ascii(vec![b"V".to_vec()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_differential, Some(false));
+        assert_eq!(metadata.gps_status, Some("Measurement void".to_string()));
+    }
+
+    #[test]
+    fn test_gps_measure_mode() {
+        let measure_mode = |mode: &[u8]| {
+            let exif = synthetic_exif(&[field(
+                Tag::GPSMeasureMode,
+                Value::Ascii(vec![mode.to_vec()]),
+            )]);
+            exif_metadata(&exif).gps_measure_mode
+        };
+        assert_eq!(measure_mode(b"2"), Some("2-dimensional".to_string()));
+        assert_eq!(measure_mode(b"3"), Some("3-dimensional".to_string()));
+        assert_eq!(measure_mode(b"4"), Some("Unknown (4)".to_string()));
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.gps_measure_mode, None);
+    }
+
+    #[test]
+    fn test_gps_extra() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSLatitude, dms(44, 42, 0)),
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"S".to_vec()])),
+            field(
+                Tag::GPSDestDistance,
+                Value::Rational(vec![(125, 10).into()]),
+            ),
+            field(Tag::GPSDestDistanceRef, Value::Ascii(vec![b"K".to_vec()])),
+            field(Tag::GPSMapDatum, Value::Ascii(vec![b"WGS-84".to_vec()])),
+            field(
+                Tag::GPSTimeStamp,
+                Value::Rational(vec![(1, 1).into(), (2, 1).into(), (3, 1).into()]),
+            ),
+        ]);
+        let metadata = exif_metadata(&exif);
+        let extra = |tag: &str| metadata.gps_extra.get(tag).map(|value| value.as_str());
+        assert_eq!(extra("GPSDestDistance"), Some("12.5 km"));
+        assert_eq!(extra("GPSDestDistanceRef"), Some("km"));
+        assert_eq!(extra("GPSMapDatum"), Some("WGS-84"));
+        assert_eq!(extra("GPSTimeStamp"), Some("01:02:03"));
+        // Tags with fields of their own are left out
+        assert_eq!(extra("GPSLatitude"), None);
+        assert_eq!(metadata.gps_extra.len(), 4);
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert!(metadata.gps_extra.is_empty());
+    }
+
+    #[test]
+    fn test_gps_area_information_and_version() {
+        let exif = synthetic_exif(&[
+            field(
+                Tag::GPSAreaInformation,
+                Value::Undefined(b"ASCII\0\0\0Wanaka\0".to_vec(), 0),
+            ),
+            field(Tag::GPSVersionID, Value::Byte(vec![2, 3, 0, 0])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_area_information, Some("Wanaka".to_string()));
+        assert_eq!(metadata.gps_version, Some("2.3.0.0".to_string()));
+
+        // The synthetic exif is written big endian
+        let mut area = b"UNICODE\0".to_vec();
+        for unit in "Wānaka".encode_utf16() {
+            area.extend_from_slice(&unit.to_be_bytes());
+        }
+        let exif = synthetic_exif(&[field(Tag::GPSAreaInformation, Value::Undefined(area, 0))]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_area_information, Some("Wānaka".to_string()));
+        assert_eq!(metadata.gps_version, None);
+    }
+
+    #[test]
+    fn test_gps_h_positioning_error() {
+        let exif = synthetic_exif(&[field(
+            Tag::GPSHPositioningError,
+            Value::Rational(vec![(35, 10).into()]),
+        )]);
+        assert_eq!(exif_metadata(&exif).gps_h_positioning_error_m, Some(3.5));
+
+        // A zero denominator shouldn't give us an infinite error
+        let exif = synthetic_exif(&[field(
+            Tag::GPSHPositioningError,
+            Value::Rational(vec![(35, 0).into()]),
+        )]);
+        assert_eq!(exif_metadata(&exif).gps_h_positioning_error_m, None);
+    }
+
+    #[test]
+    fn test_gps_destination() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSDestLatitude, dms(45, 1, 48)),
+            field(Tag::GPSDestLatitudeRef, Value::Ascii(vec![b"S".to_vec()])),
+            field(Tag::GPSDestLongitude, dms(168, 39, 36)),
+            field(Tag::GPSDestLongitudeRef, Value::Ascii(vec![b"E".to_vec()])),
+            field(
+                Tag::GPSDestBearing,
+                Value::Rational(vec![(4505, 20).into()]),
+            ),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_dest_latitude, Some(-45.03));
+        assert_eq!(metadata.gps_dest_longitude, Some(168.66));
+        assert_eq!(metadata.gps_dest_bearing, Some(225.25));
+        assert_eq!(metadata.gps_latitude, None);
+        assert!(metadata.warnings.is_empty());
+
+        let exif = synthetic_exif(&[field(
+            Tag::GPSDestBearing,
+            Value::Rational(vec![(0, 0).into()]),
+        )]);
+        assert_eq!(exif_metadata(&exif).gps_dest_bearing, None);
+    }
+
+    #[test]
+    fn test_gps_track() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSTrack, Value::Rational(vec![(1351, 10).into()])),
+            field(Tag::GPSTrackRef, Value::Ascii(vec![b"T".to_vec()])),
+            field(Tag::GPSImgDirection, Value::Rational(vec![(90, 1).into()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_track, Some(135.1));
+        assert_eq!(metadata.gps_track_ref, Some("True north".to_string()));
+
+        let exif = synthetic_exif(&[
+            field(Tag::GPSTrack, Value::Rational(vec![(20, 1).into()])),
+            field(Tag::GPSTrackRef, Value::Ascii(vec![b"M".to_vec()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_track, Some(20.0));
+        assert_eq!(metadata.gps_track_ref, Some("Magnetic north".to_string()));
+
+        let metadata = image_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.gps_track, None);
+        assert_eq!(metadata.gps_track_ref, None);
+    }
+
+    #[test]
+    fn test_gps_coordinates_out_of_range() {
+        let exif = synthetic_exif(&[
+            field(Tag::GPSLatitude, dms(95, 0, 0)),
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"N".to_vec()])),
+            field(Tag::GPSLongitude, dms(200, 0, 0)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![b"W".to_vec()])),
+        ]);
+        let metadata = exif_metadata(&exif);
+        assert_eq!(metadata.gps_latitude, None);
+        assert_eq!(metadata.gps_longitude, None);
+        assert_eq!(
+            metadata.warnings,
+            vec![
+                "Ignoring out of range GPS latitude 95".to_string(),
+                "Ignoring out of range GPS longitude -200".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gps_datetime() {
+        let exif = synthetic_exif(&[
+            field(
+                Tag::GPSDateStamp,
+                Value::Ascii(vec![b"2019:07:26".to_vec()]),
+            ),
+            field(
+                Tag::GPSTimeStamp,
+                Value::Rational(vec![(1, 1).into(), (25, 1).into(), (335, 10).into()]),
+            ),
+        ]);
+        assert_eq!(
+            exif_metadata(&exif).gps_datetime,
+            Some(Utc.ymd(2019, 7, 26).and_hms_milli(1, 25, 33, 500))
+        );
+    }
+
+    #[test]
+    fn test_resolve_time_conflict() {
+        let created = Utc.ymd(2021, 3, 1).and_hms(9, 0, 0);
+        let modified = Utc.ymd(2019, 7, 26).and_hms(13, 25, 33);
+        let file_metadata = || FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            created_time: Some(created),
+            modified_time: Some(modified),
+            ..Default::default()
+        };
+        assert!(file_metadata().created_after_modified());
+
+        let mut kept = file_metadata();
+        assert_eq!(kept.resolve_time_conflict(FileTimeConflict::Keep), None);
+        assert_eq!(kept, file_metadata());
+
+        let mut swapped = file_metadata();
+        assert_eq!(
+            swapped.resolve_time_conflict(FileTimeConflict::Swap),
+            Some(
+                "Created time 2021-03-01T09:00:00+00:00 is after the modified time \
+                 2019-07-26T13:25:33+00:00, swapping them"
+                    .to_string()
+            )
+        );
+        assert_eq!(swapped.created_time, Some(modified));
+        assert_eq!(swapped.modified_time, Some(created));
+
+        let mut cleared = file_metadata();
+        assert!(cleared
+            .resolve_time_conflict(FileTimeConflict::Clear)
+            .is_some());
+        assert_eq!(cleared.created_time, None);
+        assert_eq!(cleared.modified_time, Some(modified));
+
+        // Nothing to resolve when they're the right way around
+        let mut consistent = FileMetadata {
+            created_time: Some(modified),
+            modified_time: Some(created),
+            ..file_metadata()
+        };
+        assert!(!consistent.created_after_modified());
+        assert_eq!(
+            consistent.resolve_time_conflict(FileTimeConflict::Swap),
+            None
+        );
+        assert_eq!(consistent.created_time, Some(modified));
+
+        assert_eq!("clear".parse(), Ok(FileTimeConflict::Clear));
+        assert!("fix".parse::<FileTimeConflict>().is_err());
+    }
+
+    #[test]
+    fn test_best_capture_time() {
+        let mut file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            created_time: Some(Utc.ymd(2020, 8, 14).and_hms(12, 4, 0)),
+            ..Default::default()
+        };
+        let mut image_metadata = ImageMetadata {
+            capture_time: Some(NaiveDate::from_ymd(2019, 7, 26).and_hms(13, 25, 33)),
+            capture_time_offset: Some("+12:00".to_string()),
+            gps_datetime: Some(Utc.ymd(2019, 7, 26).and_hms(1, 25, 34)),
+            ..Default::default()
+        };
+
+        // GPS datetime wins when present
+        assert_eq!(
+            best_capture_time(&file_metadata, &image_metadata),
+            Some(Utc.ymd(2019, 7, 26).and_hms(1, 25, 34))
+        );
+
+        // Then the exif capture time adjusted by its offset
+        image_metadata.gps_datetime = None;
+        assert_eq!(
+            best_capture_time(&file_metadata, &image_metadata),
+            Some(Utc.ymd(2019, 7, 26).and_hms(1, 25, 33))
+        );
+
+        // Without an offset the capture time is taken to be UTC
+        image_metadata.capture_time_offset = None;
+        assert_eq!(
+            best_capture_time(&file_metadata, &image_metadata),
+            Some(Utc.ymd(2019, 7, 26).and_hms(13, 25, 33))
+        );
+
+        // Then falling back to the file created time
+        image_metadata.capture_time = None;
+        assert_eq!(
+            best_capture_time(&file_metadata, &image_metadata),
+            Some(Utc.ymd(2020, 8, 14).and_hms(12, 4, 0))
+        );
+
+        file_metadata.created_time = None;
+        assert_eq!(best_capture_time(&file_metadata, &image_metadata), None);
+    }
+
+    #[test]
+    fn test_summary() {
+        let sample = |filename: &str| {
+            let file_metadata = FileMetadata {
+                filename: filename.to_string(),
+                size: 1,
+                ..Default::default()
+            };
+            let image_metadata = image_metadata(&format!("tests/images/{}", filename)).unwrap();
+            CombinedMetadata::new(file_metadata, image_metadata)
+        };
+        assert_eq!(
+            sample("JAM19896.jpg").summary(),
+            "JAM19896.jpg: Canon EOS 5D Mark IV, 2019-07-26, ISO 1000"
+        );
+
+        let metadata = sample("rotated_CCW90.jpg");
+        assert_eq!(
+            metadata.summary(),
+            "rotated_CCW90.jpg: unknown camera, unknown date, ISO unknown"
+        );
+    }
+
+    #[test]
+    fn test_options_near() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let mut metadata = CombinedMetadata::new(file_metadata, ImageMetadata::default());
+        let options = Options {
+            near: Some("-44.7,169.16,10".parse().unwrap()),
+            ..Default::default()
+        };
+        // No GPS means we can't tell so it's excluded
+        assert!(!options.selects(&metadata));
+
+        metadata.image_metadata.gps_latitude = Some(-44.69);
+        metadata.image_metadata.gps_longitude = Some(169.13);
+        assert!(options.selects(&metadata));
+
+        metadata.image_metadata.gps_latitude = Some(-45.03);
+        metadata.image_metadata.gps_longitude = Some(168.66);
+        assert!(!options.selects(&metadata));
+
+        assert!(Options::default().selects(&metadata));
     }
 }
@@ -1,21 +1,963 @@
 use clap::{App, Arg};
-use image_metadata::process_file;
+#[cfg(feature = "sha2")]
+use image_metadata::Verification;
+use image_metadata::{
+    error_stub, extract_file_with_options, find_images, has_extension, load_camera_aliases,
+    parse_date_bound, parse_file_size, process_file_with_options, recompute_file,
+    rename_by_capture_time, run_with_timeout, to_output_value, write_error_stub, write_xmp_sidecar,
+    CombinedMetadata, DayGroups, FilenameMap, ImageError, JsonArrayWriter, Near, OnError, Options,
+    OutputTemplate, Stats,
+};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 
 fn main() {
-    let matches = App::new("Image Metadata Extractor")
+    let files = Arg::with_name("FILES").multiple(true);
+    #[cfg(not(feature = "zip"))]
+    let files = files.required(true);
+    #[cfg(feature = "zip")]
+    let files = files.required_unless("zip");
+
+    let app = App::new("Image Metadata Extractor")
         .about("Extracts metadata from image files into json")
-        .arg(Arg::with_name("FILES").required(true).multiple(true))
-        .get_matches();
+        .after_help("--rename, --dedupe, --verify and --autorotate are modes given as options rather than subcommands, as they work through the same FILES with the same options for finding and filtering images, ie image-metadata -r --dedupe ~/Pictures. Only one of them can be used at a time")
+        .arg(files)
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Prints a one line summary of each image once it's been processed"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Only prints errors, overriding --verbose"),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .short("r")
+                .long("recursive")
+                .help("Processes all the images found under any directories given"),
+        )
+        .arg(
+            Arg::with_name("skip-hidden")
+                .long("skip-hidden")
+                .requires("recursive")
+                .help("Ignores hidden files and directories, ie .thumbnails or macOS ._ files, when recursing. Off by default so nothing is silently missed"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .requires("recursive")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Only descends N levels of directories when recursing, 0 being just the files directly in the directories given"),
+        )
+        .arg(
+            Arg::with_name("include-ext")
+                .long("include-ext")
+                .value_name("EXTENSIONS")
+                .use_delimiter(true)
+                .requires("recursive")
+                .help("Only processes the images found when recursing with the given comma separated extensions, ie jpg,jpeg. Case insensitive"),
+        )
+        .arg(
+            Arg::with_name("exclude-ext")
+                .long("exclude-ext")
+                .value_name("EXTENSIONS")
+                .use_delimiter(true)
+                .requires("recursive")
+                .help("Skips the images found when recursing with the given comma separated extensions, ie png. Case insensitive and takes precedence over --include-ext"),
+        )
+        .arg(
+            Arg::with_name("near")
+                .long("near")
+                .value_name("LAT,LON,RADIUS_KM")
+                .allow_hyphen_values(true)
+                .validator(|s| s.parse::<Near>().map(|_| ()))
+                .help("Only processes images taken within RADIUS_KM of the given coordinates, images without GPS data are skipped"),
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .value_name("DATE")
+                .validator(|s| parse_date_bound(&s, false).map(|_| ()))
+                .help("Only processes images captured on or after DATE, either a date like 2020-01-30 or a datetime like 2020-01-30T09:30:00. Images without a capture time are skipped"),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .value_name("DATE")
+                .validator(|s| parse_date_bound(&s, true).map(|_| ()))
+                .help("Only processes images captured on or before DATE, either a date like 2020-01-30, which includes the whole day, or a datetime like 2020-01-30T09:30:00. Images without a capture time are skipped"),
+        )
+        .arg(
+            Arg::with_name("max-file-size")
+                .long("max-file-size")
+                .value_name("SIZE")
+                .validator(|s| parse_file_size(&s).map(|_| ()))
+                .help("Skips files larger than SIZE with a warning, without reading them. SIZE is in bytes or has a K, M or G suffix, ie 200MB"),
+        )
+        .arg(
+            Arg::with_name("include-undated")
+                .long("include-undated")
+                .help("Processes images without a capture time when filtering with --since or --until, rather than skipping them"),
+        )
+        .arg(
+            Arg::with_name("allow-null-island")
+                .long("allow-null-island")
+                .help("Keeps GPS coordinates of exactly 0,0, by default these are dropped with a warning as devices without a fix tend to write them"),
+        )
+        .arg(
+            Arg::with_name("file-time-conflict")
+                .long("file-time-conflict")
+                .value_name("POLICY")
+                .possible_values(&["keep", "swap", "clear"])
+                .help("What to do about files whose created time is later than their modified time, as copying can leave them: keep reports both as they are, swap swaps them over and clear drops the created time, the last two with a warning. Defaults to keep"),
+        )
+        .arg(
+            Arg::with_name("file-only")
+                .long("file-only")
+                .conflicts_with_all(&["rename", "since", "until", "near"])
+                .help("Skips reading the exif entirely and only outputs the file metadata, ie the size, times and sha256, which is much quicker for large batches"),
+        )
+        .arg(
+            Arg::with_name("aliases")
+                .long("aliases")
+                .value_name("FILE")
+                .help("Outputs camera models under the names given in FILE, a json object mapping model strings to the names to use instead, ie {\"EOS 5D Mark IV\": \"Canon EOS 5D Mark IV\"}. Models not in it are left as they are"),
+        )
+        .arg(
+            Arg::with_name("nested")
+                .long("nested")
+                .help("Outputs the file and image metadata as separate file and image objects rather than merged into one flat object"),
+        )
+        .arg(
+            Arg::with_name("sort-keys")
+                .long("sort-keys")
+                .help("Writes the json keys in a stable order, the file fields, then the image fields, then derived fields, each sorted alphabetically"),
+        )
+        .arg(
+            Arg::with_name("gps-precision")
+                .long("gps-precision")
+                .value_name("N")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Rounds the GPS coordinates to N decimal places, 6 places is roughly 11cm. Defaults to full precision"),
+        )
+        .arg(
+            Arg::with_name("geohash-precision")
+                .long("geohash-precision")
+                .value_name("N")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(1..=12) => Ok(()),
+                    _ => Err("Expected a number of characters from 1 to 12".to_string()),
+                })
+                .help("Works out the geohash to N characters, from 1 to 12. Defaults to 9 characters, a cell of roughly 5m by 5m"),
+        )
+        .arg(
+            Arg::with_name("float-precision")
+                .long("float-precision")
+                .value_name("DIGITS")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(digits) if (1..=17).contains(&digits) => Ok(()),
+                    _ => Err("Expected a number of digits from 1 to 17".to_string()),
+                })
+                .help("Rounds all the decimal fields to DIGITS significant digits, tidying up values like 2.8000000000000003 from converting the exif fractions. Applied before --gps-precision"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("FIELDS")
+                .use_delimiter(true)
+                .help("Only outputs the given comma separated fields, ie filename,capture_time"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("FIELDS")
+                .use_delimiter(true)
+                .help("Leaves the given comma separated fields out of the output, ie camera_serial. Takes precedence over --include"),
+        )
+        .arg(
+            Arg::with_name("key-case")
+                .long("key-case")
+                .value_name("CASE")
+                .possible_values(&["snake", "camel"])
+                .help("The naming convention for the json keys, ie capture_time or captureTime. Defaults to snake"),
+        )
+        .arg(
+            Arg::with_name("grouped-keys")
+                .long("grouped-keys")
+                .conflicts_with("key-case")
+                .help("Names the json keys after the exif tags, prefixed with their group, ie tiff:Model or gps:Latitude, like ExifTool's grouped output. --include and --exclude still take the usual field names"),
+        )
+        .arg(
+            Arg::with_name("omit-empty")
+                .long("omit-empty")
+                .help("Leaves out fields that are empty strings or lists, as well as the missing ones"),
+        )
+        .arg(
+            Arg::with_name("capture-warnings")
+                .long("capture-warnings")
+                .help("Adds any warnings from extracting each image, ie malformed dates, to its output as a warnings array as well as printing them"),
+        )
+        .arg(
+            Arg::with_name("presence-flags")
+                .long("presence-flags")
+                .help("Adds has_gps, has_capture_time and has_camera_info flags to the output, for filtering on without checking each of the fields behind them"),
+        )
+        .arg(
+            Arg::with_name("debug-tags")
+                .long("debug-tags")
+                .help("Outputs each field as {value, type, count} with the exif type and component count of the tag it came from, for tracking down malformed files"),
+        )
+        .arg(
+            Arg::with_name("full-gps")
+                .long("full-gps")
+                .help("Adds the rest of the GPS tags that don't have fields of their own, ie the altitude, speed and destination distance, to the output as a gps_extra object of their display values"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .validator(|s| match s.parse::<f64>() {
+                    Ok(secs) if secs.is_finite() && secs > 0.0 => Ok(()),
+                    _ => Err(format!("Expected a positive number of seconds but got \"{}\"", s)),
+                })
+                .help("Gives up on a file if it takes longer than SECS seconds to process, protects against corrupt files that stall the exif parser"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("N")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("How many times to retry reading a file after a transient IO error, ie on a flaky network mount. Defaults to 2"),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Writes the json files into DIR rather than next to the images"),
+        )
+        .arg(
+            Arg::with_name("base-dir")
+                .long("base-dir")
+                .visible_alias("strip-prefix")
+                .value_name("DIR")
+                .requires("output-dir")
+                .help("Mirrors the directory layout of the images under DIR into the --output-dir, avoiding collisions between images with the same name"),
+        )
+        .arg(
+            Arg::with_name("relative-to")
+                .long("relative-to")
+                .value_name("DIR")
+                .help("Records the path of each image relative to DIR as its filename, ie \"2020/01/JAM26284.jpg\", rather than just its name, so the directory layout can be rebuilt from the json. Images outside of DIR fail"),
+        )
+        .arg(
+            Arg::with_name("output-template")
+                .long("output-template")
+                .value_name("TEMPLATE")
+                .conflicts_with("base-dir")
+                .validator(|s| s.parse::<OutputTemplate>().map(|_| ()))
+                .help("Writes the json files to a path built from the metadata, ie \"{year}/{camera_model}/{filename}.json\", relative to --output-dir if given. The placeholders are {year}, {month}, {day}, {camera_model} and {filename}, any the image doesn't have a value for become \"unknown\""),
+        )
+        .arg(
+            Arg::with_name("merge-sidecar")
+                .long("merge-sidecar")
+                .help("Merges in any XMP sidecar next to each image, ie JAM19896.jpg.xmp or JAM19896.xmp. The rating, keywords, GPS location and capture time in the sidecar are taken over the ones embedded in the image, as photo managers keep later edits there"),
+        )
+        .arg(
+            Arg::with_name("skip-existing")
+                .long("skip-existing")
+                .help("Skips images whose json file is newer than the image, for incremental runs over the same files"),
+        )
+        .arg(
+            Arg::with_name("interactive")
+                .short("i")
+                .long("interactive")
+                .help("Asks before overwriting an existing json file. Without a terminal to ask on the file is left alone"),
+        )
+        .arg(
+            Arg::with_name("best-effort")
+                .long("best-effort")
+                .help("Writes out the file metadata alone for images whose exif can't be parsed rather than failing them, with a warning, so every input gets a json file. Files that can't be read at all still fail"),
+        )
+        .arg(
+            Arg::with_name("on-error")
+                .long("on-error")
+                .value_name("POLICY")
+                .possible_values(&["stop", "skip", "record"])
+                .help("What to do about images that fail to be extracted: stop aborts the run, skip carries on without mentioning them, record writes a json file holding just the filename and error in place of the metadata and carries on. By default a failure stops the run, unless --recursive where it's reported and the run carries on before exiting with an error"),
+        )
+        .arg(
+            Arg::with_name("percent-encode-filenames")
+                .long("percent-encode-filenames")
+                .help("Percent encodes the bytes of filenames that aren't valid UTF-8, along with any %, so they can be decoded back to the original name. By default invalid bytes are replaced with \u{FFFD}"),
+        )
+        .arg(
+            Arg::with_name("recompute")
+                .long("recompute")
+                .conflicts_with_all(&["recursive", "stats"])
+                .help("Treats FILES as previously written json (or .jsonl) output and rewrites them with the computed fields, ie best_capture_time, worked out again without rereading the images"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Prints aggregate statistics over all the images instead of writing out json files per image"),
+        )
+        .arg(
+            Arg::with_name("group-by-day")
+                .long("group-by-day")
+                .conflicts_with_all(&["stats", "recompute"])
+                .help("Prints a single json object of the images keyed by the day they were captured, ie \"2019-07-26\", instead of writing out json files per image. Images without a capture time go under \"unknown\""),
+        )
+        .arg(
+            Arg::with_name("combined")
+                .long("combined")
+                .conflicts_with_all(&["stats", "group-by-day", "format", "recompute"])
+                .help("Prints a single json array of all the images instead of writing out json files per image. The images are written out as they're processed so any number of them can be handled"),
+        )
+        .arg(
+            Arg::with_name("rename")
+                .long("rename")
+                .conflicts_with_all(&["recompute", "stats", "group-by-day", "format", "combined"])
+                .help("Renames each image after its capture time, ie 20190726_132533.jpg, instead of writing out json files. Another image already having that name gets a numeric suffix, ie 20190726_132533_1.jpg. Images without a capture time are left as is with a warning"),
+        )
+        .arg(
+            Arg::with_name("copy")
+                .long("copy")
+                .requires("rename")
+                .help("With --rename, makes a renamed copy of each image leaving the original in place"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["text", "json", "map", "xmp"])
+                .help("The format to print --stats in, text or json, defaults to text. Without --stats, map prints a single json object of the images keyed by filename instead of writing out json files per image, images that share a filename are keyed by their path as given, and xmp writes an XMP sidecar next to each image holding its rating, keywords, GPS location and capture time, ie JAM19896.jpg.xmp, for photo managers like digiKam and Darktable"),
+        );
+    #[cfg(feature = "rusqlite")]
+    let app = app.arg(
+        Arg::with_name("sqlite")
+            .long("sqlite")
+            .value_name("DB")
+            .help("Inserts the metadata for each image as a row in the images table of an sqlite database instead of writing json files, the database and table are created if absent"),
+    );
+    #[cfg(feature = "sha2")]
+    let app = app
+        .arg(
+            Arg::with_name("sha256")
+                .long("sha256")
+                .help("Adds the SHA-256 of each file to the output, for checking on later with --verify"),
+        )
+        .arg(
+            Arg::with_name("checksum-algorithm")
+                .long("checksum-algorithm")
+                .value_name("ALGORITHM")
+                .possible_values(image_metadata::ChecksumAlgorithm::NAMES)
+                .conflicts_with("sha256")
+                .help("Adds a checksum of each file to the output like --sha256 but with the given algorithm, output as the field of the same name"),
+        )
+        .arg(
+            Arg::with_name("dedupe")
+                .long("dedupe")
+                .conflicts_with_all(&["stats", "group-by-day", "recompute", "format", "combined", "rename"])
+                .help("Hashes the images and prints a json array of the groups of duplicates, as {hash, paths}, instead of writing out json files per image"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .conflicts_with_all(&["recursive", "stats", "recompute", "group-by-day", "rename", "dedupe"])
+                .help("Treats FILES as previously written json (or .jsonl) output and checks the images next to them still match their recorded checksum, reporting any that have changed or can't be read"),
+        );
+    #[cfg(feature = "unicode-normalization")]
+    let app = app.arg(
+        Arg::with_name("normalize-unicode")
+            .long("normalize-unicode")
+            .help("Applies NFC unicode normalization to the extracted strings so composed and decomposed accents compare equal"),
+    );
+    #[cfg(feature = "encoding_rs")]
+    let app = app.arg(
+        Arg::with_name("string-encoding")
+            .long("string-encoding")
+            .value_name("ENCODING")
+            .possible_values(&["utf8", "latin1", "shiftjis"])
+            .help("How to decode free text like the camera model, for older cameras that write Latin-1 or Shift-JIS rather than ASCII. Defaults to utf8"),
+    );
+    #[cfg(feature = "image")]
+    let app = app.arg(
+        Arg::with_name("autorotate")
+            .long("autorotate")
+            .conflicts_with_all(&["recompute", "stats", "group-by-day", "format", "combined", "rename", "dedupe", "verify"])
+            .help("Writes a copy of each jpeg rotated to match its orientation, ie photo_rotated.jpg, with the orientation reset to 1, instead of writing out json files. The copies are re-encoded so lose a little quality. Images that are already upright are left alone"),
+    );
+    #[cfg(feature = "mmap")]
+    let app = app.arg(
+        Arg::with_name("mmap")
+            .long("mmap")
+            .help("Memory maps the images rather than reading them, which can be quicker over a large batch. The images mustn't be changed by anything else while they're processed, as one being truncated while mapped crashes the process"),
+    );
+    #[cfg(feature = "glob")]
+    let app = app.arg(
+        Arg::with_name("glob")
+            .long("glob")
+            .help("Expands glob patterns in FILES, ie \"photos/*.jpg\", for shells like cmd.exe that pass them through as is"),
+    );
+    #[cfg(feature = "zip")]
+    let app = app.arg(
+        Arg::with_name("zip")
+            .long("zip")
+            .value_name("ARCHIVE")
+            .multiple(true)
+            .number_of_values(1)
+            .conflicts_with_all(&["combined", "stats", "group-by-day", "format"])
+            .help("Extracts metadata from the images inside a zip archive into a single json file next to it, or with --output-dir a json file per image laid out as in the archive"),
+    );
+    let matches = app.get_matches();
+
+    let reporter = Reporter {
+        verbose: matches.is_present("verbose"),
+        quiet: matches.is_present("quiet"),
+    };
+
+    let options = Options {
+        near: matches.value_of("near").map(|s| s.parse().unwrap()),
+        since: matches
+            .value_of("since")
+            .map(|s| parse_date_bound(s, false).unwrap()),
+        until: matches
+            .value_of("until")
+            .map(|s| parse_date_bound(s, true).unwrap()),
+        include_undated: matches.is_present("include-undated"),
+        camera_aliases: match matches.value_of_os("aliases") {
+            Some(path) => load_camera_aliases(path)
+                .unwrap_or_else(|error| exit_with_error(path, error.into())),
+            None => HashMap::new(),
+        },
+        file_only: matches.is_present("file-only"),
+        max_file_size: matches
+            .value_of("max-file-size")
+            .map(|s| parse_file_size(s).unwrap()),
+        sort_keys: matches.is_present("sort-keys"),
+        allow_null_island: matches.is_present("allow-null-island"),
+        file_time_conflict: matches
+            .value_of("file-time-conflict")
+            .map_or_else(Default::default, |s| s.parse().unwrap()),
+        gps_precision: matches
+            .value_of("gps-precision")
+            .map(|s| s.parse().unwrap()),
+        geohash_precision: matches
+            .value_of("geohash-precision")
+            .map(|s| s.parse().unwrap()),
+        float_precision: matches
+            .value_of("float-precision")
+            .map(|s| s.parse().unwrap()),
+        key_case: matches
+            .value_of("key-case")
+            .map_or_else(Default::default, |s| s.parse().unwrap()),
+        grouped_keys: matches.is_present("grouped-keys"),
+        nested: matches.is_present("nested"),
+        debug_tags: matches.is_present("debug-tags"),
+        full_gps: matches.is_present("full-gps"),
+        omit_empty: matches.is_present("omit-empty"),
+        capture_warnings: matches.is_present("capture-warnings"),
+        presence_flags: matches.is_present("presence-flags"),
+        retries: matches.value_of("retries").map(|s| s.parse().unwrap()),
+        include: matches
+            .values_of("include")
+            .map(|fields| fields.map(str::to_string).collect()),
+        exclude: matches
+            .values_of("exclude")
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect(),
+        merge_sidecar: matches.is_present("merge-sidecar"),
+        skip_existing: matches.is_present("skip-existing"),
+        best_effort: matches.is_present("best-effort"),
+        percent_encode_filenames: matches.is_present("percent-encode-filenames"),
+        confirm_overwrite: if matches.is_present("interactive") {
+            Some(confirm_overwrite)
+        } else {
+            None
+        },
+        on_skip: if reporter.quiet {
+            None
+        } else {
+            Some(print_skip)
+        },
+        output_dir: matches.value_of_os("output-dir").map(PathBuf::from),
+        base_dir: matches.value_of_os("base-dir").map(PathBuf::from),
+        relative_to: matches.value_of_os("relative-to").map(PathBuf::from),
+        output_template: matches
+            .value_of("output-template")
+            .map(|s| s.parse().unwrap()),
+        #[cfg(feature = "mmap")]
+        mmap: matches.is_present("mmap"),
+        #[cfg(feature = "sha2")]
+        checksum: match matches.value_of("checksum-algorithm") {
+            Some(algorithm) => Some(algorithm.parse().unwrap()),
+            None if matches.is_present("sha256") => Some(Default::default()),
+            None => None,
+        },
+        #[cfg(feature = "unicode-normalization")]
+        normalize_unicode: matches.is_present("normalize-unicode"),
+        #[cfg(feature = "encoding_rs")]
+        string_encoding: matches
+            .value_of("string-encoding")
+            .map_or_else(Default::default, |s| s.parse().unwrap()),
+    };
+
+    let timeout = matches
+        .value_of("timeout")
+        .map(|s| Duration::from_secs_f64(s.parse().unwrap()));
+    let stats_mode = matches.is_present("stats");
+    let mut stats = Stats::default();
+    let group_by_day = matches.is_present("group-by-day");
+    let mut day_groups = DayGroups::default();
+    let map_mode = matches.value_of("format") == Some("map");
+    let mut filename_map = FilenameMap::default();
+    let xmp_mode = matches.value_of("format") == Some("xmp");
+    if (map_mode || xmp_mode) && (stats_mode || group_by_day) {
+        clap::Error::with_description(
+            "--format map or xmp can't be used with --stats or --group-by-day",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    let mut combined = if matches.is_present("combined") {
+        let writer = JsonArrayWriter::new(std::io::stdout())
+            .unwrap_or_else(|error| exit_with_error(OsStr::new("stdout"), error.into()));
+        Some(writer)
+    } else {
+        None
+    };
+    let on_error = matches
+        .value_of("on-error")
+        .map(|s| s.parse::<OnError>().unwrap());
+    // When the metadata is going somewhere else there's no need for per file json
+    let extract_only = stats_mode
+        || group_by_day
+        || map_mode
+        || xmp_mode
+        || combined.is_some()
+        || matches.is_present("sqlite");
+
+    #[cfg(feature = "rusqlite")]
+    let mut sqlite = matches.value_of_os("sqlite").map(|path| {
+        image_metadata::SqliteWriter::open(path)
+            .unwrap_or_else(|error| exit_with_error(path, error))
+    });
+
+    if matches.is_present("recompute") {
+        for path in matches.values_of_os("FILES").into_iter().flatten() {
+            match recompute_file(path, &options) {
+                Ok(entries) => {
+                    for metadata in &entries {
+                        reporter.report(path.to_string_lossy(), metadata);
+                    }
+                }
+                Err(error) => exit_with_error(path, error),
+            }
+        }
+        return;
+    }
+
+    #[cfg(feature = "sha2")]
+    if matches.is_present("verify") {
+        let mut failed = 0;
+        for path in matches.values_of_os("FILES").into_iter().flatten() {
+            let verifications = image_metadata::verify_file(path)
+                .unwrap_or_else(|error| exit_with_error(path, error));
+            for verification in verifications {
+                match verification {
+                    Verification::Unchanged(image_path) => {
+                        if reporter.verbose && !reporter.quiet {
+                            eprintln!("{} is unchanged", image_path.display());
+                        }
+                    }
+                    Verification::Changed {
+                        path: image_path,
+                        expected,
+                        actual,
+                    } => {
+                        println!(
+                            "{} has changed, expected checksum {} but got {}",
+                            image_path.display(),
+                            expected,
+                            actual
+                        );
+                        failed += 1;
+                    }
+                    Verification::Unreadable {
+                        path: image_path,
+                        error,
+                    } => {
+                        println!("{} couldn't be read: {}", image_path.display(), error);
+                        failed += 1;
+                    }
+                    Verification::NoChecksum(image_path) => {
+                        if !reporter.quiet {
+                            eprintln!(
+                                "Warning while processing {}: No checksum recorded to verify against",
+                                image_path.display()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        if failed > 0 {
+            exit(1);
+        }
+        return;
+    }
+
+    // When recursing we carry on past anything we can't read or process, counting them
+    // up to report at the end
+    let recursive = matches.is_present("recursive");
+    let mut skipped = 0;
+    let mut failed = 0;
+
+    let max_depth = matches.value_of("max-depth").map(|s| s.parse().unwrap());
+    let include_ext = matches
+        .values_of("include-ext")
+        .map(|exts| exts.collect::<Vec<_>>());
+    let exclude_ext = matches
+        .values_of("exclude-ext")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    #[allow(unused_mut)]
+    let mut files = matches
+        .values_of_os("FILES")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    #[cfg(feature = "glob")]
+    if matches.is_present("glob") {
+        files = expand_globs(files);
+    }
+
+    let mut paths = vec![];
+    for path in &files {
+        if recursive && path.is_dir() {
+            let found = find_images(path, matches.is_present("skip-hidden"), max_depth);
+            for (path, error) in found.skipped {
+                print_error(path.as_os_str(), &error.into());
+                skipped += 1;
+            }
+            paths.extend(found.images.into_iter().filter(|path| {
+                include_ext
+                    .as_ref()
+                    .map_or(true, |exts| has_extension(path, exts))
+                    && !has_extension(path, &exclude_ext)
+            }));
+        } else {
+            paths.push(path.clone());
+        }
+    }
+
+    let rename_mode = matches.is_present("rename");
+    let copy = matches.is_present("copy");
+
+    #[cfg(feature = "sha2")]
+    let mut duplicates = if matches.is_present("dedupe") {
+        Some(image_metadata::Duplicates::default())
+    } else {
+        None
+    };
+
+    for path in &paths {
+        #[cfg(feature = "sha2")]
+        if let Some(duplicates) = &mut duplicates {
+            match duplicates.add(path) {
+                Ok(()) => {}
+                Err(error) if recursive => {
+                    print_error(path.as_os_str(), &error.into());
+                    failed += 1;
+                }
+                Err(error) => exit_with_error(path.as_os_str(), error.into()),
+            }
+            continue;
+        }
+        #[cfg(feature = "image")]
+        if matches.is_present("autorotate") {
+            match image_metadata::autorotate(path) {
+                Ok(Some(new_path)) => {
+                    if reporter.verbose && !reporter.quiet {
+                        eprintln!("{} -> {}", path.display(), new_path.display());
+                    }
+                }
+                Ok(None) => {
+                    if reporter.verbose && !reporter.quiet {
+                        eprintln!("{} is already upright", path.display());
+                    }
+                }
+                Err(error) if recursive => {
+                    print_error(path.as_os_str(), &error);
+                    failed += 1;
+                }
+                Err(error) => exit_with_error(path.as_os_str(), error),
+            }
+            continue;
+        }
+        if rename_mode {
+            match rename_by_capture_time(path, &options, copy) {
+                Ok(Some(new_path)) => {
+                    if reporter.verbose && !reporter.quiet {
+                        eprintln!("{} -> {}", path.display(), new_path.display());
+                    }
+                }
+                Ok(None) => {
+                    if !reporter.quiet {
+                        eprintln!(
+                            "Warning while processing {}: No capture time to rename by",
+                            path.display()
+                        );
+                    }
+                }
+                Err(error) if recursive => {
+                    print_error(path.as_os_str(), &error);
+                    failed += 1;
+                }
+                Err(error) => exit_with_error(path.as_os_str(), error),
+            }
+            continue;
+        }
+        let process = {
+            let (path, options) = (path.clone(), options.clone());
+            move || {
+                #[cfg(feature = "http")]
+                if let Some(url) = path.to_str().filter(|path| image_metadata::is_url(path)) {
+                    let metadata = image_metadata::fetch_url_with_options(url, &options)?;
+                    // There's nowhere to write the json next to so it goes to stdout
+                    if let (Some(metadata), false) = (&metadata, extract_only) {
+                        let output =
+                            to_output_value(metadata, &options).map_err(std::io::Error::from)?;
+                        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+                    }
+                    return Ok(metadata);
+                }
+                if extract_only {
+                    extract_file_with_options(path, &options)
+                } else {
+                    process_file_with_options(path, &options)
+                }
+            }
+        };
+        let result = match timeout {
+            Some(timeout) => run_with_timeout(timeout, process),
+            None => process(),
+        };
+        let path = path.as_os_str();
+        match result {
+            Ok(Some(metadata)) => {
+                reporter.report(path.to_string_lossy(), &metadata);
+                stats.add(&metadata);
+                if group_by_day {
+                    if let Err(error) = day_groups.add(&metadata, &options) {
+                        exit_with_error(path, std::io::Error::from(error).into());
+                    }
+                }
+                if map_mode {
+                    if let Err(error) = filename_map.add(path, &metadata, &options) {
+                        exit_with_error(path, std::io::Error::from(error).into());
+                    }
+                }
+                if xmp_mode {
+                    if let Err(error) = write_xmp_sidecar(path, &metadata.image_metadata) {
+                        exit_with_error(path, error.into());
+                    }
+                }
+                if let Some(combined) = &mut combined {
+                    let written = to_output_value(&metadata, &options)
+                        .map_err(std::io::Error::from)
+                        .and_then(|value| combined.write(&value));
+                    if let Err(error) = written {
+                        exit_with_error(path, error.into());
+                    }
+                }
+                #[cfg(feature = "rusqlite")]
+                if let Some(sqlite) = &mut sqlite {
+                    if let Err(error) = sqlite.insert(path, &metadata, &options) {
+                        exit_with_error(path, error);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(error) => match on_error {
+                Some(OnError::Stop) => exit_with_error(path, error),
+                Some(OnError::Skip) => {}
+                Some(OnError::Record) => {
+                    let recorded = match &mut combined {
+                        Some(combined) => combined
+                            .write(&error_stub(path, &error, &options))
+                            .map_err(ImageError::from),
+                        None if extract_only => Ok(()),
+                        None => write_error_stub(path, &error, &options).map(|_| ()),
+                    };
+                    if let Err(error) = recorded {
+                        exit_with_error(path, error);
+                    }
+                }
+                None if recursive => {
+                    print_error(path, &error);
+                    failed += 1;
+                }
+                None => exit_with_error(path, error),
+            },
+        }
+    }
+
+    if stats_mode {
+        if matches.value_of("format") == Some("json") {
+            println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        } else {
+            println!("{}", stats);
+        }
+    }
 
-    for path in matches.values_of_os("FILES").unwrap() {
-        if let Err(error) = process_file(path) {
-            eprintln!(
-                "While processing {}, we hit an error:\n  {}",
-                path.to_string_lossy(),
-                error
-            );
-            exit(1)
+    if group_by_day {
+        println!("{}", serde_json::to_string_pretty(&day_groups).unwrap());
+    }
+    if map_mode {
+        println!("{}", serde_json::to_string_pretty(&filename_map).unwrap());
+    }
+    #[cfg(feature = "sha2")]
+    if let Some(duplicates) = duplicates {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&duplicates.groups()).unwrap()
+        );
+    }
+    if let Some(combined) = combined {
+        if let Err(error) = combined.finish() {
+            exit_with_error(OsStr::new("stdout"), error.into());
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    for path in matches.values_of_os("zip").into_iter().flatten() {
+        match image_metadata::process_zip(path, &options, on_error.unwrap_or(OnError::Skip)) {
+            Ok(entries) => {
+                for metadata in &entries.metadata {
+                    let source = format!(
+                        "{}/{}",
+                        path.to_string_lossy(),
+                        metadata.file_metadata.filename
+                    );
+                    reporter.report(source, metadata);
+                }
+                for (name, error) in entries.failures {
+                    let source = format!("{}/{}", path.to_string_lossy(), name);
+                    match on_error {
+                        Some(OnError::Stop) => exit_with_error(OsStr::new(&source), error),
+                        Some(OnError::Skip) | Some(OnError::Record) => {}
+                        None => {
+                            print_error(OsStr::new(&source), &error);
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            Err(error) => exit_with_error(path, error),
+        }
+    }
+
+    if skipped > 0 || failed > 0 {
+        eprintln!(
+            "Skipped {} unreadable directories or files and {} files that failed to process",
+            skipped, failed
+        );
+        exit(1);
+    }
+}
+
+/// Handles printing out any warnings and summaries for processed images
+struct Reporter {
+    verbose: bool,
+    quiet: bool,
+}
+
+impl Reporter {
+    fn report<D: Display>(&self, source: D, metadata: &CombinedMetadata) {
+        if self.quiet {
+            return;
+        }
+        for warning in &metadata.image_metadata.warnings {
+            eprintln!("Warning while processing {}: {}", source, warning);
+        }
+        if self.verbose {
+            eprintln!("{}", metadata.summary());
         }
     }
 }
+
+/// Warns about a file being skipped without being read
+fn print_skip(path: PathBuf, reason: String) {
+    eprintln!("Warning while processing {}: {}", path.display(), reason);
+}
+
+/// Asks on stderr whether to overwrite the given json file, anything but a yes is a no.
+/// When stdin isn't a terminal there's nobody to answer so we don't wait around
+fn confirm_overwrite(path: PathBuf) -> bool {
+    if !atty::is(atty::Stream::Stdin) {
+        eprintln!("Not overwriting {}", path.display());
+        return false;
+    }
+    eprint!("Overwrite {}? [y/N] ", path.display());
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Expands any glob patterns in the file arguments, a pattern that doesn't match anything
+/// is passed along as is so it gets reported as missing
+#[cfg(feature = "glob")]
+fn expand_globs(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut expanded = vec![];
+    for file in files {
+        let paths = glob::glob(&file.to_string_lossy()).unwrap_or_else(|error| {
+            let error = std::io::Error::new(std::io::ErrorKind::InvalidInput, error.to_string());
+            exit_with_error(file.as_os_str(), error.into())
+        });
+        let count = expanded.len();
+        for path in paths {
+            match path {
+                Ok(path) => expanded.push(path),
+                Err(error) => {
+                    let io_error =
+                        std::io::Error::new(error.error().kind(), error.error().to_string());
+                    print_error(error.path().as_os_str(), &io_error.into());
+                }
+            }
+        }
+        if expanded.len() == count {
+            expanded.push(file);
+        }
+    }
+    expanded
+}
+
+fn print_error(path: &OsStr, error: &ImageError) {
+    eprintln!(
+        "While processing {}, we hit an error:\n  {}",
+        path.to_string_lossy(),
+        error
+    );
+}
+
+fn exit_with_error(path: &OsStr, error: ImageError) -> ! {
+    print_error(path, &error);
+    exit(1)
+}
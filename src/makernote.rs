@@ -0,0 +1,433 @@
+use exif::{Exif, In, Tag};
+
+/// Canon's TimeInfo maker note tag, an array of signed longs holding its own size, the
+/// time zone in minutes, the time zone city and the daylight savings adjustment in minutes
+const CANON_TIME_INFO: u16 = 0x0035;
+/// Canon's ColorTemperature maker note tag, a single unsigned short in Kelvin
+const CANON_COLOR_TEMPERATURE: u16 = 0x00AE;
+/// Canon's CameraSettings maker note tag, an array of signed shorts holding the shooting
+/// settings, including the lens type
+const CANON_CAMERA_SETTINGS: u16 = 0x0001;
+/// Canon's ShotInfo maker note tag, an array of signed shorts describing the exposure
+const CANON_SHOT_INFO: u16 = 0x0004;
+/// Canon's FileInfo maker note tag, an array of signed shorts holding all sorts
+const CANON_FILE_INFO: u16 = 0x0093;
+/// Canon's AFInfo2 maker note tag, an array of unsigned shorts holding the number of AF
+/// points, their sizes and positions then bitmasks of which of them were in focus
+#[cfg(feature = "af-points")]
+const CANON_AF_INFO2: u16 = 0x0026;
+
+/// The UTC offset of the capture time as recorded in the maker notes, ie "+12:00".
+/// Maker notes are undocumented and differ per brand so this is best effort and only
+/// understands Canon's for now
+pub fn timezone_offset(exif: &Exif) -> Option<String> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let time_info = ifd_entry_offset(exif, maker_note, CANON_TIME_INFO)?;
+    let timezone = read_i32(exif, time_info + 4)?;
+    let daylight_savings = read_i32(exif, time_info + 12)?;
+    format_offset(timezone.checked_add(daylight_savings)?)
+}
+
+/// The white balance color temperature in Kelvin as recorded in the maker notes. Like
+/// the time zone this only understands Canon's, other makes give None
+pub fn white_balance_kelvin(exif: &Exif) -> Option<u32> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let entry = ifd_entry(exif, maker_note, CANON_COLOR_TEMPERATURE)?;
+    // Short enough to be stored inline in the entry itself
+    let kelvin = u32::from(read_u16(exif, entry + 8)?);
+    // Zero when the camera didn't record one, anything outside of what cameras let you
+    // dial in is garbage
+    Some(kelvin).filter(|kelvin| (1000..=50_000).contains(kelvin))
+}
+
+/// Whether long exposure noise reduction was on, as recorded in the maker notes. Only
+/// Canon's is understood, and only the newer models that keep it in their FileInfo rather
+/// than their custom functions. When it was left on auto there's no telling whether it
+/// kicked in so that's None too
+pub fn long_exposure_noise_reduction(exif: &Exif) -> Option<bool> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let file_info = ifd_entry_offset(exif, maker_note, CANON_FILE_INFO)?;
+    match read_u16(exif, file_info + 8 * 2)? {
+        0 => Some(false),
+        // 1 is the 1D series' on
+        1 | 3 => Some(true),
+        _ => None,
+    }
+}
+
+/// The temperature inside the camera in degrees Celsius at the time of capture, as
+/// recorded in the maker notes. This is brand specific and often absent, only Canon's is
+/// understood and only the newer EOS models record it in their ShotInfo, so other makes,
+/// compacts and older Canons give None
+pub fn camera_temperature_c(exif: &Exif) -> Option<f64> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let shot_info = ifd_entry_offset(exif, maker_note, CANON_SHOT_INFO)?;
+    // Stored offset by 128 so it fits an unsigned byte, with 0 for not recorded
+    let raw = read_u16(exif, shot_info + 12 * 2)? as i16;
+    if raw == 0 {
+        return None;
+    }
+    let celsius = raw.checked_sub(128)?;
+    // Anything a camera wouldn't survive is garbage
+    Some(f64::from(celsius)).filter(|celsius| (-50.0..=100.0).contains(celsius))
+}
+
+/// Names for the lens type IDs Canon records in its CameraSettings, as ExifTool has them.
+/// This only covers a selection of the more common EF and EF-S lenses, and leaves out
+/// IDs that third party lenses reuse as there's no telling those apart from the ID alone
+const CANON_LENS_TYPES: &[(u16, &str)] = &[
+    (229, "Canon EF 16-35mm f/2.8L USM"),
+    (230, "Canon EF 24-70mm f/2.8L USM"),
+    (235, "Canon EF-S 10-22mm f/3.5-4.5 USM"),
+    (237, "Canon EF 24-105mm f/4L IS USM"),
+    (241, "Canon EF 50mm f/1.2L USM"),
+    (246, "Canon EF 16-35mm f/2.8L II USM"),
+    (247, "Canon EF 14mm f/2.8L II USM"),
+    (251, "Canon EF 70-200mm f/2.8L IS II USM"),
+    (254, "Canon EF 100mm f/2.8L Macro IS USM"),
+    (495, "Canon EF 24-70mm f/2.8L II USM"),
+    (504, "Canon EF 24-70mm f/4L IS USM"),
+    (508, "Canon EF 11-24mm f/4L USM"),
+    (747, "Canon EF 100-400mm f/4.5-5.6L IS II USM"),
+    (750, "Canon EF 35mm f/1.4L II USM"),
+    (751, "Canon EF 16-35mm f/2.8L III USM"),
+    (752, "Canon EF 24-105mm f/4L IS II USM"),
+    (753, "Canon EF 85mm f/1.4L IS USM"),
+    (4144, "Canon EF 40mm f/2.8 STM"),
+    (4146, "Canon EF-S 18-55mm f/3.5-5.6 IS STM"),
+    (4148, "Canon EF-S 55-250mm f/4-5.6 IS STM"),
+    (4156, "Canon EF 50mm f/1.8 STM"),
+];
+
+/// The name of the lens going by the lens type ID in the maker notes, for images without
+/// the standard LensModel tag. Only Canon's are understood, and only the lenses in
+/// `CANON_LENS_TYPES`, so anything else is None
+pub fn lens_model(exif: &Exif) -> Option<String> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let camera_settings = ifd_entry_offset(exif, maker_note, CANON_CAMERA_SETTINGS)?;
+    let lens_type = read_u16(exif, camera_settings + 22 * 2)?;
+    CANON_LENS_TYPES
+        .iter()
+        .find(|(id, _)| *id == lens_type)
+        .map(|(_, name)| name.to_string())
+}
+
+/// The indices of the autofocus points that were in focus, as recorded in the maker
+/// notes. The numbering of the points is specific to each camera model. Only Canon's
+/// AFInfo2 is understood so other makes, and older Canons, give an empty list
+#[cfg(feature = "af-points")]
+pub fn af_points_used(exif: &Exif) -> Vec<u32> {
+    af_points_in_focus(exif).unwrap_or_default()
+}
+
+#[cfg(feature = "af-points")]
+fn af_points_in_focus(exif: &Exif) -> Option<Vec<u32>> {
+    let maker_note = canon_maker_note_offset(exif)?;
+    let af_info = ifd_entry_offset(exif, maker_note, CANON_AF_INFO2)?;
+    let short = |index: usize| read_u16(exif, af_info + index * 2);
+    let points = usize::from(short(2)?);
+    // The size, area mode, point counts and image sizes come first, then the widths,
+    // heights and x and y positions of each point before the in focus bitmask
+    let in_focus = 8 + 4 * points;
+    let mut used = vec![];
+    for word in 0..(points + 15) / 16 {
+        let bits = short(in_focus + word)?;
+        for bit in 0..16 {
+            let point = word * 16 + bit;
+            if point < points && bits & (1 << bit) != 0 {
+                used.push(point as u32);
+            }
+        }
+    }
+    Some(used)
+}
+
+/// The offset of the maker note IFD, if the image is from a Canon
+fn canon_maker_note_offset(exif: &Exif) -> Option<usize> {
+    let make = match &exif.get_field(Tag::Make, In::PRIMARY)?.value {
+        exif::Value::Ascii(make) => make.first().map(|make| String::from_utf8_lossy(make))?,
+        _ => return None,
+    };
+    if !make.starts_with("Canon") {
+        return None;
+    }
+    match exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        exif::Value::Undefined(_, offset) => Some(offset as usize),
+        _ => None,
+    }
+}
+
+/// Canon's maker note is a bare IFD, with offsets relative to the start of the exif
+/// rather than the maker note itself. Returns the offset of the entry for the given tag
+fn ifd_entry(exif: &Exif, ifd: usize, tag: u16) -> Option<usize> {
+    let entries = read_u16(exif, ifd)? as usize;
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(exif, entry) == Some(tag))
+}
+
+/// Returns the offset of the value for the given tag, which needs to be too big to fit
+/// inline in the entry
+fn ifd_entry_offset(exif: &Exif, ifd: usize, tag: u16) -> Option<usize> {
+    ifd_entry(exif, ifd, tag)
+        .and_then(|entry| read_u32(exif, entry + 8))
+        .map(|offset| offset as usize)
+}
+
+fn read_u16(exif: &Exif, offset: usize) -> Option<u16> {
+    let bytes = exif.buf().get(offset..offset + 2)?;
+    let bytes = [bytes[0], bytes[1]];
+    if exif.little_endian() {
+        Some(u16::from_le_bytes(bytes))
+    } else {
+        Some(u16::from_be_bytes(bytes))
+    }
+}
+
+fn read_u32(exif: &Exif, offset: usize) -> Option<u32> {
+    let bytes = exif.buf().get(offset..offset + 4)?;
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if exif.little_endian() {
+        Some(u32::from_le_bytes(bytes))
+    } else {
+        Some(u32::from_be_bytes(bytes))
+    }
+}
+
+fn read_i32(exif: &Exif, offset: usize) -> Option<i32> {
+    read_u32(exif, offset).map(|value| value as i32)
+}
+
+/// Formats an offset in minutes the same way as the exif OffsetTime tags, ie "-03:30"
+fn format_offset(minutes: i32) -> Option<String> {
+    // Anything past the furthest real time zones is garbage
+    if !(-14 * 60..=14 * 60).contains(&minutes) {
+        return None;
+    }
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let minutes = minutes.abs();
+    Some(format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::{Field, Value};
+    use std::io::Cursor;
+
+    /// A Canon maker note holding just the TimeInfo tag, positioned at the given offset
+    /// in the exif
+    fn canon_maker_note(offset: u32, timezone: i32, daylight_savings: i32) -> Vec<u8> {
+        let mut note = vec![];
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&CANON_TIME_INFO.to_be_bytes());
+        // Signed longs
+        note.extend_from_slice(&9u16.to_be_bytes());
+        note.extend_from_slice(&4u32.to_be_bytes());
+        // The values follow on from the entry and the next IFD pointer
+        note.extend_from_slice(&(offset + 18).to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        for value in &[16, timezone, 0, daylight_savings] {
+            note.extend_from_slice(&value.to_be_bytes());
+        }
+        note
+    }
+
+    /// A Canon maker note holding just the AFInfo2 tag for 9 AF points, positioned at the
+    /// given offset in the exif
+    #[cfg(feature = "af-points")]
+    fn canon_af_info(offset: u32, in_focus: u16) -> Vec<u8> {
+        let points = 9u16;
+        let mut values = vec![0, 0, points, points, 5472, 3648, 5472, 3648];
+        values.extend(std::iter::repeat(0).take(4 * points as usize));
+        values.push(in_focus);
+        values[0] = values.len() as u16 * 2;
+
+        let mut note = vec![];
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&CANON_AF_INFO2.to_be_bytes());
+        // Unsigned shorts
+        note.extend_from_slice(&3u16.to_be_bytes());
+        note.extend_from_slice(&(values.len() as u32).to_be_bytes());
+        note.extend_from_slice(&(offset + 18).to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        for value in values {
+            note.extend_from_slice(&value.to_be_bytes());
+        }
+        note
+    }
+
+    /// Writes out a Canon maker note that refers to its own position in the exif
+    fn canon_exif_with<F: Fn(u32) -> Vec<u8>>(maker_note: F) -> Exif {
+        // We don't know where the maker note lands until it's been written, it'll be in
+        // the same place the second time around as the size doesn't change
+        let exif = write_exif(b"Canon", maker_note(0));
+        let offset = match exif.get_field(Tag::MakerNote, In::PRIMARY).unwrap().value {
+            Value::Undefined(_, offset) => offset,
+            _ => unreachable!(),
+        };
+        write_exif(b"Canon", maker_note(offset))
+    }
+
+    /// A Canon maker note holding just the ColorTemperature tag
+    fn canon_color_temperature(kelvin: u16) -> Vec<u8> {
+        let mut note = vec![];
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&CANON_COLOR_TEMPERATURE.to_be_bytes());
+        // A single unsigned short, padded out to fill the value field
+        note.extend_from_slice(&3u16.to_be_bytes());
+        note.extend_from_slice(&1u32.to_be_bytes());
+        note.extend_from_slice(&kelvin.to_be_bytes());
+        note.extend_from_slice(&[0, 0]);
+        note.extend_from_slice(&0u32.to_be_bytes());
+        note
+    }
+
+    /// A Canon maker note holding just the given tag as signed shorts, positioned at the
+    /// given offset in the exif. Canon starts these arrays with their length in bytes,
+    /// so the first value is filled in with it
+    fn canon_shorts(offset: u32, tag: u16, mut values: Vec<u16>) -> Vec<u8> {
+        values[0] = values.len() as u16 * 2;
+
+        let mut note = vec![];
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&tag.to_be_bytes());
+        // Signed shorts
+        note.extend_from_slice(&8u16.to_be_bytes());
+        note.extend_from_slice(&(values.len() as u32).to_be_bytes());
+        note.extend_from_slice(&(offset + 18).to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        for value in values {
+            note.extend_from_slice(&value.to_be_bytes());
+        }
+        note
+    }
+
+    fn write_exif(make: &[u8], maker_note: Vec<u8>) -> Exif {
+        let make = Field {
+            tag: Tag::Make,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![make.to_vec()]),
+        };
+        let maker_note = Field {
+            tag: Tag::MakerNote,
+            ifd_num: In::PRIMARY,
+            value: Value::Undefined(maker_note, 0),
+        };
+        let mut writer = Writer::new();
+        writer.push_field(&make);
+        writer.push_field(&maker_note);
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        exif::Reader::new().read_raw(buf.into_inner()).unwrap()
+    }
+
+    fn canon_exif(timezone: i32, daylight_savings: i32) -> Exif {
+        canon_exif_with(|offset| canon_maker_note(offset, timezone, daylight_savings))
+    }
+
+    #[test]
+    fn test_canon_timezone_offset() {
+        assert_eq!(
+            timezone_offset(&canon_exif(720, 60)),
+            Some("+13:00".to_string())
+        );
+        assert_eq!(
+            timezone_offset(&canon_exif(-210, 0)),
+            Some("-03:30".to_string())
+        );
+        assert_eq!(timezone_offset(&canon_exif(100_000, 0)), None);
+        assert_eq!(timezone_offset(&canon_exif(i32::MAX, 60)), None);
+    }
+
+    #[test]
+    fn test_canon_white_balance_kelvin() {
+        let exif = write_exif(b"Canon", canon_color_temperature(5200));
+        assert_eq!(white_balance_kelvin(&exif), Some(5200));
+        let exif = write_exif(b"Canon", canon_color_temperature(0));
+        assert_eq!(white_balance_kelvin(&exif), None);
+        // Other makes lay out their maker notes differently
+        let exif = write_exif(b"NIKON CORPORATION", canon_color_temperature(5200));
+        assert_eq!(white_balance_kelvin(&exif), None);
+    }
+
+    #[test]
+    fn test_canon_long_exposure_noise_reduction() {
+        let noise_reduction = |setting: u16| {
+            let exif = canon_exif_with(|offset| {
+                let mut values = vec![0; 10];
+                values[8] = setting;
+                canon_shorts(offset, CANON_FILE_INFO, values)
+            });
+            long_exposure_noise_reduction(&exif)
+        };
+        assert_eq!(noise_reduction(0), Some(false));
+        assert_eq!(noise_reduction(1), Some(true));
+        assert_eq!(noise_reduction(3), Some(true));
+        // Auto
+        assert_eq!(noise_reduction(4), None);
+        let exif = write_exif(b"Canon", canon_color_temperature(5200));
+        assert_eq!(long_exposure_noise_reduction(&exif), None);
+    }
+
+    #[test]
+    fn test_canon_camera_temperature_c() {
+        let temperature = |raw: u16| {
+            let exif = canon_exif_with(|offset| {
+                let mut values = vec![0; 34];
+                values[12] = raw;
+                canon_shorts(offset, CANON_SHOT_INFO, values)
+            });
+            camera_temperature_c(&exif)
+        };
+        assert_eq!(temperature(128 + 31), Some(31.0));
+        assert_eq!(temperature(128 - 5), Some(-5.0));
+        // Not recorded
+        assert_eq!(temperature(0), None);
+        assert_eq!(temperature(1000), None);
+        // Negative once read as signed, too far down to take the offset off
+        assert_eq!(temperature(0x8000), None);
+        assert_eq!(temperature(0x807F), None);
+        let exif = write_exif(b"Canon", canon_color_temperature(5200));
+        assert_eq!(camera_temperature_c(&exif), None);
+    }
+
+    #[test]
+    fn test_canon_lens_model() {
+        let lens_model = |lens_type: u16| {
+            let exif = canon_exif_with(|offset| {
+                let mut values = vec![0; 49];
+                values[22] = lens_type;
+                canon_shorts(offset, CANON_CAMERA_SETTINGS, values)
+            });
+            lens_model(&exif)
+        };
+        assert_eq!(
+            lens_model(750),
+            Some("Canon EF 35mm f/1.4L II USM".to_string())
+        );
+        assert_eq!(
+            lens_model(4156),
+            Some("Canon EF 50mm f/1.8 STM".to_string())
+        );
+        // Shared with Sigma and Tamron lenses so not in the table
+        assert_eq!(lens_model(137), None);
+        // Not recorded
+        assert_eq!(lens_model(0xFFFF), None);
+        let exif = write_exif(b"Canon", canon_color_temperature(5200));
+        assert_eq!(super::lens_model(&exif), None);
+    }
+
+    #[test]
+    #[cfg(feature = "af-points")]
+    fn test_canon_af_points_used() {
+        // The center point and the one to the right of it
+        let exif = canon_exif_with(|offset| canon_af_info(offset, 0b1_0001_0000));
+        assert_eq!(af_points_used(&exif), vec![4, 8]);
+        let exif = canon_exif_with(|offset| canon_af_info(offset, 0));
+        assert_eq!(af_points_used(&exif), Vec::<u32>::new());
+        let exif = write_exif(b"Canon", canon_color_temperature(5200));
+        assert_eq!(af_points_used(&exif), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,1001 @@
+use crate::{
+    extract_metadata_from_reader, output, retry, CombinedMetadata, FileMetadata, ImageError,
+    ImageMetadata, Options, DEFAULT_RETRIES,
+};
+use chrono::DateTime;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+impl Options {
+    /// The path of the json file we write the metadata for the given file to, taking
+    /// into account any output directory
+    fn json_path<P: AsRef<Path>>(&self, path: P) -> std::io::Result<PathBuf> {
+        let output_dir = match &self.output_dir {
+            Some(output_dir) => output_dir,
+            None => return Ok(json_path(path)),
+        };
+        let relative_path = match &self.base_dir {
+            Some(base_dir) => path.as_ref().strip_prefix(base_dir).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is not under the base directory {}",
+                        path.as_ref().display(),
+                        base_dir.display()
+                    ),
+                )
+            })?,
+            None => Path::new(path.as_ref().file_name().unwrap_or_default()),
+        };
+        Ok(json_path(output_dir.join(relative_path)))
+    }
+}
+
+/// Process an image file, ie extract the metadata from it and write out
+/// a json file containing that metadata
+pub fn process_file<P: AsRef<Path>>(path: P) -> Result<CombinedMetadata, ImageError> {
+    let metadata = CombinedMetadata::new(file_metadata(&path)?, image_metadata(&path)?);
+    write_metadata_to_file(json_path(path), &metadata)?;
+    Ok(metadata)
+}
+
+/// Process an image file as per `process_file` but handing the metadata to the given
+/// hook before it's written out, letting it change fields or add its own to
+/// `extra_fields`
+pub fn process_file_with<P: AsRef<Path>, F: FnMut(&mut CombinedMetadata)>(
+    path: P,
+    mut hook: F,
+) -> Result<CombinedMetadata, ImageError> {
+    let mut metadata = CombinedMetadata::new(file_metadata(&path)?, image_metadata(&path)?);
+    hook(&mut metadata);
+    write_metadata_to_file(json_path(path), &metadata)?;
+    Ok(metadata)
+}
+
+/// Process an image file as per `process_file` but following the given options,
+/// returns None if the image was skipped as it didn't match the filters or its json
+/// was already up to date
+pub fn process_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+) -> Result<Option<CombinedMetadata>, ImageError> {
+    // Templated paths depend on the metadata so for those we can't check whether the json
+    // is up to date until after extracting it
+    if options.output_template.is_none()
+        && options.skip_existing
+        && is_up_to_date(&path, &options.json_path(&path)?)
+    {
+        return Ok(None);
+    }
+    let metadata = match extract_file_with_options(&path, options)? {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    let json_path = match &options.output_template {
+        Some(template) => {
+            let json_path = options
+                .output_dir
+                .clone()
+                .unwrap_or_default()
+                .join(template.render(&metadata));
+            if options.skip_existing && is_up_to_date(&path, &json_path) {
+                return Ok(None);
+            }
+            json_path
+        }
+        None => options.json_path(&path)?,
+    };
+    if let Some(confirm_overwrite) = options.confirm_overwrite {
+        if json_path.exists() && !confirm_overwrite(json_path.clone()) {
+            return Ok(None);
+        }
+    }
+    let output = output::to_output_value(&metadata, options).map_err(std::io::Error::from)?;
+    if options.output_dir.is_some() || options.output_template.is_some() {
+        if let Some(parent) = json_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    write_metadata_to_file(json_path, &output)?;
+    Ok(Some(metadata))
+}
+
+/// Extract the metadata from an image file following the given options but without
+/// writing anything out, returns None if the image was skipped as it didn't match the
+/// filters
+pub fn extract_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+) -> Result<Option<CombinedMetadata>, ImageError> {
+    let retries = options.retries.unwrap_or(DEFAULT_RETRIES);
+    let mut file_metadata = retry::with_retries(retries, || Ok(file_metadata(&path)?))?;
+    if let Some(max_file_size) = options
+        .max_file_size
+        .filter(|max_file_size| file_metadata.size > *max_file_size)
+    {
+        if let Some(on_skip) = options.on_skip {
+            let reason = format!(
+                "Skipping as it's {} bytes, over the maximum file size of {} bytes",
+                file_metadata.size, max_file_size
+            );
+            on_skip(path.as_ref().to_path_buf(), reason);
+        }
+        return Ok(None);
+    }
+    if let Some(filename) = output_filename(path.as_ref(), options)? {
+        file_metadata.filename = filename;
+    }
+    #[cfg(feature = "mmap")]
+    let (mut image_metadata, checksum) = if options.mmap {
+        read_mapped(&path, options)?
+    } else {
+        read_image(|| File::open(&path), options)?
+    };
+    #[cfg(not(feature = "mmap"))]
+    let (mut image_metadata, checksum) = read_image(|| File::open(&path), options)?;
+    #[cfg(feature = "sha2")]
+    if let (Some(algorithm), Some(checksum)) = (options.checksum, checksum) {
+        file_metadata.set_checksum(algorithm, checksum);
+    }
+    #[cfg(not(feature = "sha2"))]
+    let _ = checksum;
+    if let Some(warning) = file_metadata.resolve_time_conflict(options.file_time_conflict) {
+        image_metadata.warnings.push(warning);
+    }
+    if options.merge_sidecar && !options.file_only {
+        crate::merge_xmp_sidecar(&path, &mut image_metadata)?;
+    }
+    let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+    if options.selects(&metadata) {
+        Ok(Some(metadata))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads the image metadata from the file that `open` opens, along with its checksum if
+/// the options ask for one. When hashing, the whole file is read into memory and both are
+/// worked out from that one read, rather than reading the file for the exif and then
+/// all over again to hash it
+fn read_image<R: Read + Seek, F: FnMut() -> std::io::Result<R>>(
+    mut open: F,
+    options: &Options,
+) -> Result<(ImageMetadata, Option<String>), ImageError> {
+    let retries = options.retries.unwrap_or(DEFAULT_RETRIES);
+    #[cfg(feature = "sha2")]
+    if let Some(algorithm) = options.checksum {
+        if options.file_only {
+            let checksum =
+                retry::with_retries(retries, || Ok(algorithm.checksum_reader(open()?)?))?;
+            return Ok((ImageMetadata::default(), Some(checksum)));
+        }
+        let bytes = retry::with_retries(retries, || {
+            let mut bytes = vec![];
+            open()?.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })?;
+        let checksum = algorithm.checksum_reader(&bytes[..])?;
+        let image_metadata = apply_image_options(
+            extract_reader_with_options(&mut std::io::Cursor::new(&bytes), options),
+            options,
+        )?;
+        return Ok((image_metadata, Some(checksum)));
+    }
+    if options.file_only {
+        return Ok((ImageMetadata::default(), None));
+    }
+    let image_metadata = apply_image_options(
+        retry::with_retries(retries, || {
+            let mut file = BufReader::new(open()?);
+            extract_reader_with_options(&mut file, options)
+        }),
+        options,
+    )?;
+    Ok((image_metadata, None))
+}
+
+/// Reads the image metadata and any checksum as per `read_image`, but from the file memory
+/// mapped rather than read, saving on the copying and syscalls over a large batch. The
+/// mapping is only sound while nothing else changes the file, were another process to
+/// truncate it while it's mapped we'd be killed by a SIGBUS, or if it's rewritten the
+/// bytes could change mid parse. So this is only for batches that are known to be left
+/// alone while they're processed
+#[cfg(feature = "mmap")]
+fn read_mapped<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+) -> Result<(ImageMetadata, Option<String>), ImageError> {
+    let retries = options.retries.unwrap_or(DEFAULT_RETRIES);
+    let file = retry::with_retries(retries, || Ok(File::open(&path)?))?;
+    // Safety: as above, we rely on the file not being changed while it's mapped
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    #[cfg(feature = "sha2")]
+    let checksum = match options.checksum {
+        Some(algorithm) => Some(algorithm.checksum_reader(&map[..])?),
+        None => None,
+    };
+    #[cfg(not(feature = "sha2"))]
+    let checksum = None;
+    if options.file_only {
+        return Ok((ImageMetadata::default(), checksum));
+    }
+    let image_metadata = apply_image_options(
+        extract_reader_with_options(&mut std::io::Cursor::new(&map[..]), options),
+        options,
+    )?;
+    Ok((image_metadata, checksum))
+}
+
+/// What to do about an image that fails to be extracted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnError {
+    /// Give up on the whole run
+    Stop,
+    /// Carry on with the next image without mentioning it
+    Skip,
+    /// Write out an `error_stub` in place of the metadata and carry on
+    Record,
+}
+
+/// Parses "stop", "skip" or "record" as passed on the command line
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(OnError::Stop),
+            "skip" => Ok(OnError::Skip),
+            "record" => Ok(OnError::Record),
+            _ => Err(format!("Expected stop, skip or record but got \"{}\"", s)),
+        }
+    }
+}
+
+/// The filename recorded for an image, relative to `options.relative_to` if given and
+/// percent encoded if asked for. None for paths without a filename, ie ".."
+fn output_filename(path: &Path, options: &Options) -> Result<Option<String>, ImageError> {
+    let encode = |name: &OsStr| encode_filename(name, options);
+    if let Some(root) = &options.relative_to {
+        Ok(Some(
+            relative_path(path, root)?
+                .iter()
+                .map(encode)
+                .collect::<Vec<_>>()
+                .join("/"),
+        ))
+    } else {
+        Ok(path.file_name().map(encode))
+    }
+}
+
+/// The json recorded in place of the metadata for an image that failed to be extracted,
+/// just its filename and the error, so the failures can be found amongst the rest. The
+/// filename is as it would have been in the metadata, or just the file's name if the
+/// image isn't under `options.relative_to`
+pub fn error_stub<P: AsRef<Path>>(
+    path: P,
+    error: &ImageError,
+    options: &Options,
+) -> serde_json::Value {
+    let path = path.as_ref();
+    let filename = output_filename(path, options)
+        .ok()
+        .flatten()
+        .or_else(|| path.file_name().map(|name| encode_filename(name, options)))
+        .unwrap_or_default();
+    serde_json::json!({
+        "filename": filename,
+        "error": error.to_string(),
+    })
+}
+
+/// Writes the `error_stub` for an image to where its json would have gone, returning
+/// that path. The output template needs the metadata to render so it's ignored here
+pub fn write_error_stub<P: AsRef<Path>>(
+    path: P,
+    error: &ImageError,
+    options: &Options,
+) -> Result<PathBuf, ImageError> {
+    let json_path = options.json_path(&path)?;
+    if let Some(parent) = json_path.parent().filter(|_| options.output_dir.is_some()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_metadata_to_file(&json_path, &error_stub(path, error, options))?;
+    Ok(json_path)
+}
+
+/// Applies the options that only concern the metadata from the image itself, given the
+/// result of extracting it
+pub(crate) fn apply_image_options(
+    result: Result<ImageMetadata, ImageError>,
+    options: &Options,
+) -> Result<ImageMetadata, ImageError> {
+    let mut image_metadata = match result {
+        // Only failures to parse the exif are let through, not being able to read the
+        // file at all is still an error
+        Err(ImageError::ExifError(error))
+            if options.best_effort && !matches!(error, exif::Error::Io(_)) =>
+        {
+            ImageMetadata {
+                warnings: vec![format!("No image metadata extracted: {}", error)],
+                ..Default::default()
+            }
+        }
+        result => result?,
+    };
+    if !options.allow_null_island {
+        image_metadata.clear_null_island();
+    }
+    if !options.full_gps {
+        image_metadata.gps_extra.clear();
+    }
+    #[cfg(feature = "unicode-normalization")]
+    if options.normalize_unicode {
+        image_metadata.normalize_unicode();
+    }
+    image_metadata.apply_camera_aliases(&options.camera_aliases);
+    Ok(image_metadata)
+}
+
+/// Whether the json file for an image exists and was written after the image was last
+/// modified, if we can't tell either way we assume it needs redoing
+fn is_up_to_date<P: AsRef<Path>>(path: P, json_path: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(path.as_ref()), modified(json_path)) {
+        (Some(image_modified), Some(json_modified)) => json_modified >= image_modified,
+        _ => false,
+    }
+}
+
+/// The path of the json file we write the metadata for the given file to
+pub(crate) fn json_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut json_path = path.as_ref().to_path_buf();
+    json_path.set_extension("json");
+    json_path
+}
+
+/// Retrieves the filesystem metadata for a given file.
+fn file_metadata<P: AsRef<Path>>(path: P) -> std::io::Result<FileMetadata> {
+    let metadata = std::fs::metadata(&path)?;
+    Ok(FileMetadata {
+        filename: path
+            .as_ref()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string(),
+        size: metadata.len(),
+        created_time: metadata.created().map(DateTime::from).ok(),
+        modified_time: metadata.modified().map(DateTime::from).ok(),
+        ..Default::default()
+    })
+}
+
+/// The path of a file relative to the given directory. Either path could have been given
+/// relative to the current directory or with a ./ in front, so if the path isn't under
+/// the directory as given both are resolved before giving up
+fn relative_path(path: &Path, root: &Path) -> std::io::Result<PathBuf> {
+    if let Ok(relative) = path.strip_prefix(root) {
+        return Ok(relative.to_path_buf());
+    }
+    let canonical_path = path.canonicalize()?;
+    let canonical_root = root.canonicalize()?;
+    canonical_path
+        .strip_prefix(&canonical_root)
+        .map(Path::to_path_buf)
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not under {}", path.display(), root.display()),
+            )
+        })
+}
+
+/// A filename as a string, percent encoded if `options.percent_encode_filenames` is set
+fn encode_filename(name: &OsStr, options: &Options) -> String {
+    if options.percent_encode_filenames {
+        percent_encode_filename(name)
+    } else {
+        name.to_string_lossy().to_string()
+    }
+}
+
+/// Percent encodes the bytes of a filename that aren't valid UTF-8, along with any '%'
+/// so the result can always be decoded back to the original bytes. Only unix filenames
+/// can be arbitrary bytes, elsewhere we fall back to the lossy conversion
+fn percent_encode_filename(name: &OsStr) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(name);
+    #[cfg(not(unix))]
+    let lossy = name.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+
+    let mut encoded = String::new();
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                encoded.push_str(&valid.replace('%', "%25"));
+                return encoded;
+            }
+            Err(error) => {
+                let (valid, after) = rest.split_at(error.valid_up_to());
+                let valid = std::str::from_utf8(valid).expect("Checked as valid above");
+                encoded.push_str(&valid.replace('%', "%25"));
+                let (invalid, after) = after.split_at(error.error_len().unwrap_or(after.len()));
+                for byte in invalid {
+                    encoded.push_str(&format!("%{:02X}", byte));
+                }
+                rest = after;
+            }
+        }
+    }
+}
+
+/// Retrieves the image metadata for a given file.
+fn image_metadata<P: AsRef<Path>>(path: P) -> Result<ImageMetadata, ImageError> {
+    let mut file = BufReader::new(File::open(path)?);
+    extract_metadata_from_reader(&mut file)
+}
+
+/// Extracts the image metadata from a reader, decoding the strings as per the options and
+/// only working out the tag types when they're going to be output
+pub(crate) fn extract_reader_with_options<R: BufRead + Seek>(
+    reader: &mut R,
+    options: &Options,
+) -> Result<ImageMetadata, ImageError> {
+    crate::read_metadata(reader, |metadata, exif| {
+        #[cfg(feature = "encoding_rs")]
+        if options.string_encoding != crate::StringEncoding::Utf8 {
+            crate::encoding::decode_strings(metadata, exif, options.string_encoding);
+        }
+        if options.debug_tags {
+            metadata.tag_types = crate::debug_tags::tag_types(exif);
+        }
+    })
+}
+
+/// Write the metadata out to a file
+pub(crate) fn write_metadata_to_file<P: AsRef<Path>, T: Serialize + ?Sized>(
+    path: P,
+    metadata: &T,
+) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    // All these json serde errors will actually be io errors anyway so the conversion
+    // back to io wont be lossy.
+    serde_json::to_writer_pretty(&mut file, metadata).map_err(std::io::Error::from)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone, Utc};
+    use indoc::indoc;
+
+    #[test]
+    fn test_file_metadata() {
+        let metadata = file_metadata("tests/images/JAM19896.jpg").unwrap();
+        assert_eq!(metadata.filename, "JAM19896.jpg".to_string());
+        assert_eq!(metadata.size, 953458);
+        // Git wont restore ctime/crtime so the best we can do is see if we get something sane
+        let created_time = metadata.created_time.expect("Created time expected");
+        let modified_time = metadata.created_time.expect("Modified time expected");
+        // A system time representing the start of 2020
+        let t_2020 = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let now = Utc::now();
+        assert!(created_time > t_2020);
+        assert!(created_time <= now);
+        assert!(modified_time > t_2020);
+        assert!(modified_time <= now);
+    }
+
+    #[test]
+    fn test_write_metadata_to_file() -> Result<(), ImageError> {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            created_time: Some(Utc.ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            orientation: Some(1),
+            capture_time: Some(Local.ymd(1986, 4, 27).and_hms(7, 30, 0).naive_local()),
+            camera_model: None,
+            camera_serial: Some("baz".to_string()),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+
+        let path = "target/test/metadata.json";
+        std::fs::create_dir_all("target/test")?;
+
+        write_metadata_to_file(path, &metadata)?;
+
+        assert_eq!(
+            std::fs::read_to_string(path)?,
+            indoc! {r#"
+        {
+          "filename": "test.jpg",
+          "size": 123456,
+          "created_time": "1986-04-27T07:30:00Z",
+          "orientation": 1,
+          "capture_time": "1986-04-27T07:30:00",
+          "camera_serial": "baz",
+          "best_capture_time": "1986-04-27T07:30:00Z"
+        }"#}
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file() -> Result<(), ImageError> {
+        // Lets copy our the file under target so we don't pollute the workspace
+        std::fs::create_dir_all("target/test")?;
+        let image_path = "target/test/JAM19896.jpg";
+        let expected_json_path = "target/test/JAM19896.json";
+        std::fs::copy("tests/images/JAM19896.jpg", image_path)?;
+
+        process_file(image_path)?;
+        let metadata: CombinedMetadata =
+            serde_json::from_slice(&std::fs::read(expected_json_path)?).unwrap();
+        assert_eq!(metadata.file_metadata.size, 953458);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/process_file_with")?;
+        let image_path = "target/test/process_file_with/JAM19896.jpg";
+        let json_path = "target/test/process_file_with/JAM19896.json";
+        std::fs::copy("tests/images/JAM19896.jpg", image_path)?;
+
+        process_file_with(image_path, |metadata| {
+            metadata.image_metadata.camera_owner = Some("Tim".to_string());
+            metadata
+                .extra_fields
+                .insert("album".to_string(), "Holidays".into());
+        })?;
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(json_path)?).unwrap();
+        assert_eq!(json["camera_owner"], "Tim");
+        assert_eq!(json["album"], "Holidays");
+
+        // The extra fields survive being read back in
+        let metadata: CombinedMetadata = serde_json::from_value(json).unwrap();
+        assert_eq!(metadata.extra_fields.len(), 1);
+        assert_eq!(metadata.file_metadata.size, 953458);
+        Ok(())
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn test_read_image_with_sha256() -> Result<(), ImageError> {
+        let options = Options {
+            checksum: Some(crate::ChecksumAlgorithm::Sha256),
+            ..Default::default()
+        };
+        let mut opened = 0;
+        let (image_metadata, sha256) = read_image(
+            || {
+                opened += 1;
+                File::open("tests/images/JAM19896.jpg")
+            },
+            &options,
+        )?;
+        assert_eq!(opened, 1);
+        assert_eq!(
+            sha256.as_deref(),
+            Some("1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3")
+        );
+        assert_eq!(
+            image_metadata.camera_model.as_deref(),
+            Some("Canon EOS 5D Mark IV")
+        );
+        assert_eq!(image_metadata.has_icc_profile, Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_to() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/relative_to");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("2020/01"))?;
+        let image_path = dir.join("2020/01/JAM26284.jpg");
+        std::fs::copy("tests/images/JAM26284.jpg", &image_path)?;
+
+        let options = Options {
+            relative_to: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options(&image_path, &options)?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "2020/01/JAM26284.jpg");
+
+        // The root given differently to the image path still works
+        let options = Options {
+            relative_to: Some(Path::new(".").join(dir).join("2020")),
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options(&image_path, &options)?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "01/JAM26284.jpg");
+
+        let error = extract_file_with_options("tests/images/JAM26284.jpg", &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tests/images/JAM26284.jpg is not under ./target/test/relative_to/2020"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_only() -> Result<(), ImageError> {
+        let options = Options {
+            file_only: true,
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options("tests/images/JAM19896.jpg", &options)?.unwrap();
+        assert_eq!(metadata.image_metadata, ImageMetadata::default());
+        let output = output::to_output_value(&metadata, &options).map_err(std::io::Error::from)?;
+        // Not every filesystem has a created time so that might not be there
+        let file_keys = [
+            "filename",
+            "size",
+            "created_time",
+            "modified_time",
+            "best_capture_time",
+        ];
+        for key in output.as_object().unwrap().keys() {
+            assert!(
+                file_keys.contains(&key.as_str()),
+                "{} isn't a file field",
+                key
+            );
+        }
+        assert_eq!(output["size"], 953458);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_file_size() -> Result<(), ImageError> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static SKIPPED: AtomicUsize = AtomicUsize::new(0);
+        fn on_skip(path: PathBuf, reason: String) {
+            assert_eq!(path, Path::new("tests/images/JAM19896.jpg"));
+            assert_eq!(
+                reason,
+                "Skipping as it's 953458 bytes, over the maximum file size of 600000 bytes"
+            );
+            SKIPPED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let options = Options {
+            max_file_size: Some(600_000),
+            on_skip: Some(on_skip),
+            ..Default::default()
+        };
+        // 953458 bytes
+        assert!(extract_file_with_options("tests/images/JAM19896.jpg", &options)?.is_none());
+        assert_eq!(SKIPPED.load(Ordering::SeqCst), 1);
+        // 574207 bytes
+        let metadata = extract_file_with_options("tests/images/JAM26284.jpg", &options)?;
+        assert_eq!(metadata.unwrap().file_metadata.size, 574207);
+        assert_eq!(SKIPPED.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_since_until() -> Result<(), ImageError> {
+        let images = [
+            "JAM19896.jpg",
+            "JAM26284.jpg",
+            "JAM26496.jpg",
+            "rotated_CCW90.jpg",
+        ];
+        let selected = |options: &Options| -> Result<Vec<&str>, ImageError> {
+            let mut selected = vec![];
+            for image in &images {
+                let path = format!("tests/images/{}", image);
+                if extract_file_with_options(path, options)?.is_some() {
+                    selected.push(*image);
+                }
+            }
+            Ok(selected)
+        };
+        let date =
+            |s: &str, end_of_day: bool| Some(crate::parse_date_bound(s, end_of_day).unwrap());
+
+        let options = Options {
+            since: date("2020-01-01", false),
+            ..Default::default()
+        };
+        assert_eq!(selected(&options)?, vec!["JAM26284.jpg", "JAM26496.jpg"]);
+
+        let options = Options {
+            until: date("2019-07-26", true),
+            ..Default::default()
+        };
+        assert_eq!(selected(&options)?, vec!["JAM19896.jpg"]);
+
+        let options = Options {
+            since: date("2020-01-30T09:30:00", false),
+            until: date("2020-01-30", true),
+            include_undated: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            selected(&options)?,
+            vec!["JAM26496.jpg", "rotated_CCW90.jpg"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_existing() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/skip_existing")?;
+        let image_path = "target/test/skip_existing/JAM19896.jpg";
+        let json_path = "target/test/skip_existing/JAM19896.json";
+        let _ = std::fs::remove_file(json_path);
+        std::fs::copy("tests/images/JAM19896.jpg", image_path)?;
+        let options = Options {
+            skip_existing: true,
+            ..Default::default()
+        };
+
+        assert!(process_file_with_options(image_path, &options)?.is_some());
+        // The json is now up to date so the image shouldn't get looked at
+        assert!(process_file_with_options(image_path, &options)?.is_none());
+
+        // Touching the image should see it reprocessed, sleeping first so the new
+        // modified time is clearly after the json's even on coarse filesystems
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        std::fs::copy("tests/images/JAM19896.jpg", image_path)?;
+        assert!(process_file_with_options(image_path, &options)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiff_based_raws() -> Result<(), ImageError> {
+        use exif::experimental::Writer;
+        use exif::{Field, In, Tag, Value};
+
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        std::fs::create_dir_all("target/test/raws")?;
+        for (filename, make, model) in &[
+            ("IMG_0001.CR2", "Canon", "Canon EOS 5D Mark IV"),
+            ("DSC_0001.NEF", "NIKON CORPORATION", "NIKON D850"),
+            ("DSC00001.ARW", "SONY", "ILCE-7RM3"),
+        ] {
+            // Only the tiff structure of the raw, without the image data
+            let fields = [
+                Field {
+                    tag: Tag::Make,
+                    ifd_num: In::PRIMARY,
+                    value: ascii(make),
+                },
+                Field {
+                    tag: Tag::Model,
+                    ifd_num: In::PRIMARY,
+                    value: ascii(model),
+                },
+                Field {
+                    tag: Tag::DateTimeOriginal,
+                    ifd_num: In::PRIMARY,
+                    value: ascii("2020:01:30 09:28:07"),
+                },
+            ];
+            let mut writer = Writer::new();
+            for field in &fields {
+                writer.push_field(field);
+            }
+            let mut bytes = std::io::Cursor::new(Vec::new());
+            writer.write(&mut bytes, false)?;
+            let image_path = Path::new("target/test/raws").join(filename);
+            std::fs::write(&image_path, bytes.into_inner())?;
+
+            let metadata = extract_file_with_options(&image_path, &Options::default())?.unwrap();
+            assert_eq!(
+                metadata.image_metadata.camera_model.as_deref(),
+                Some(*model)
+            );
+            assert_eq!(
+                metadata.image_metadata.capture_time,
+                Some(chrono::NaiveDate::from_ymd(2020, 1, 30).and_hms(9, 28, 7))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_island() -> Result<(), ImageError> {
+        use exif::experimental::Writer;
+        use exif::{Field, In, Tag, Value};
+
+        let zero = Value::Rational(vec![(0, 1).into(), (0, 1).into(), (0, 1).into()]);
+        let fields = [
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: zero.clone(),
+            },
+            Field {
+                tag: Tag::GPSLongitude,
+                ifd_num: In::PRIMARY,
+                value: zero,
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        writer.write(&mut bytes, false)?;
+        std::fs::create_dir_all("target/test")?;
+        let image_path = "target/test/null_island.tif";
+        std::fs::write(image_path, bytes.into_inner())?;
+
+        let metadata = extract_file_with_options(image_path, &Options::default())?.unwrap();
+        assert_eq!(metadata.image_metadata.gps_latitude, None);
+        assert_eq!(metadata.image_metadata.gps_longitude, None);
+        assert_eq!(metadata.image_metadata.geohash, None);
+        assert_eq!(metadata.image_metadata.warnings.len(), 1);
+
+        let options = Options {
+            allow_null_island: true,
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options(image_path, &options)?.unwrap();
+        assert_eq!(metadata.image_metadata.gps_latitude, Some(0.0));
+        assert_eq!(metadata.image_metadata.gps_longitude, Some(0.0));
+        assert_eq!(
+            metadata.image_metadata.geohash.as_deref(),
+            Some("s00000000")
+        );
+        assert!(metadata.image_metadata.warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_read_mapped() -> Result<(), ImageError> {
+        let mapped = Options {
+            mmap: true,
+            ..Default::default()
+        };
+        for image in &[
+            "tests/images/JAM19896.jpg",
+            "tests/images/JAM26496.jpg",
+            "tests/images/rotated_CCW90.jpg",
+        ] {
+            assert_eq!(
+                extract_file_with_options(image, &mapped)?,
+                extract_file_with_options(image, &Options::default())?
+            );
+        }
+
+        // An empty file can't be mapped on every platform, it fails either way
+        std::fs::create_dir_all("target/test")?;
+        let empty_path = "target/test/mmap_empty.jpg";
+        std::fs::write(empty_path, b"")?;
+        assert!(extract_file_with_options(empty_path, &mapped).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_error_stub() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/error_stub");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("broken.jpg");
+        std::fs::write(&image_path, b"not a jpeg")?;
+
+        let error = extract_file_with_options(&image_path, &Options::default()).unwrap_err();
+        let json_path = write_error_stub(&image_path, &error, &Options::default())?;
+        assert_eq!(json_path, dir.join("broken.json"));
+        let stub: serde_json::Value = serde_json::from_slice(&std::fs::read(json_path)?).unwrap();
+        assert_eq!(stub["filename"], "broken.jpg");
+        assert_eq!(stub["error"], error.to_string());
+
+        // The stub's filename follows the same options as the metadata's
+        let options = Options {
+            relative_to: Some(PathBuf::from("target/test")),
+            percent_encode_filenames: true,
+            ..Default::default()
+        };
+        let stub = error_stub(dir.join("100%.jpg"), &error, &options);
+        assert_eq!(stub["filename"], "error_stub/100%25.jpg");
+        let stub = error_stub("tests/images/100%.jpg", &error, &options);
+        assert_eq!(stub["filename"], "100%25.jpg");
+
+        assert_eq!("record".parse(), Ok(OnError::Record));
+        assert!("ignore".parse::<OnError>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_effort() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test")?;
+        let path = "target/test/not_an_image.jpg";
+        std::fs::write(path, b"not an image")?;
+
+        assert!(extract_file_with_options(path, &Options::default()).is_err());
+
+        let options = Options {
+            best_effort: true,
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options(path, &options)?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "not_an_image.jpg");
+        assert_eq!(metadata.file_metadata.size, 12);
+        assert_eq!(metadata.image_metadata.camera_model, None);
+        assert_eq!(metadata.image_metadata.warnings.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_percent_encode_filename() {
+        assert_eq!(percent_encode_filename(OsStr::new("café.jpg")), "café.jpg");
+        assert_eq!(
+            percent_encode_filename(OsStr::new("100%.jpg")),
+            "100%25.jpg"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_filename() -> Result<(), ImageError> {
+        use std::os::unix::ffi::OsStrExt;
+
+        std::fs::create_dir_all("target/test/non_utf8")?;
+        // "café" in latin-1, the é isn't valid UTF-8 on its own
+        let path = Path::new("target/test/non_utf8").join(OsStr::from_bytes(b"caf\xe9%.jpg"));
+        // Some filesystems, ie APFS, insist on UTF-8 names so there's nothing to test
+        if std::fs::copy("tests/images/JAM19896.jpg", &path).is_err() {
+            return Ok(());
+        }
+
+        let metadata = extract_file_with_options(&path, &Options::default())?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "caf\u{FFFD}%.jpg");
+
+        let options = Options {
+            percent_encode_filenames: true,
+            ..Default::default()
+        };
+        let metadata = extract_file_with_options(&path, &options)?.unwrap();
+        assert_eq!(metadata.file_metadata.filename, "caf%E9%25.jpg");
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_json_path() {
+        let options = Options::default();
+        assert_eq!(
+            options.json_path("photos/2020/a.jpg").unwrap(),
+            PathBuf::from("photos/2020/a.json")
+        );
+
+        let options = Options {
+            output_dir: Some(PathBuf::from("out")),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.json_path("photos/2020/a.jpg").unwrap(),
+            PathBuf::from("out/a.json")
+        );
+
+        let options = Options {
+            output_dir: Some(PathBuf::from("out")),
+            base_dir: Some(PathBuf::from("photos")),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.json_path("photos/2020/a.jpg").unwrap(),
+            PathBuf::from("out/2020/a.json")
+        );
+        assert!(options.json_path("elsewhere/a.jpg").is_err());
+    }
+}
@@ -0,0 +1,765 @@
+use crate::{debug_tags, CombinedMetadata, Options};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// The fields holding decimal GPS coordinates
+const GPS_FIELDS: &[&str] = &[
+    "gps_latitude",
+    "gps_longitude",
+    "gps_dest_latitude",
+    "gps_dest_longitude",
+];
+
+/// The ExifTool style group prefixed names for each field, the group being the IFD the
+/// tag is read from. Fields we work out ourselves go in the composite group, as they do
+/// in ExifTool
+const GROUPED_KEYS: &[(&str, &str)] = &[
+    ("filename", "file:FileName"),
+    ("size", "file:FileSize"),
+    ("created_time", "file:FileCreateDate"),
+    ("modified_time", "file:FileModifyDate"),
+    ("sha256", "file:SHA256"),
+    ("md5", "file:MD5"),
+    ("blake3", "file:BLAKE3"),
+    ("orientation", "tiff:Orientation"),
+    ("orientation_degrees", "composite:OrientationDegrees"),
+    ("orientation_mirrored", "composite:OrientationMirrored"),
+    ("thumbnail_orientation", "ifd1:Orientation"),
+    ("thumbnail_width", "ifd1:ImageWidth"),
+    ("thumbnail_height", "ifd1:ImageHeight"),
+    ("thumbnail_display_width", "composite:ThumbnailDisplayWidth"),
+    (
+        "thumbnail_display_height",
+        "composite:ThumbnailDisplayHeight",
+    ),
+    ("pixel_width", "exif:PixelXDimension"),
+    ("pixel_height", "exif:PixelYDimension"),
+    ("display_width", "composite:DisplayWidth"),
+    ("display_height", "composite:DisplayHeight"),
+    ("capture_time", "exif:DateTimeOriginal"),
+    ("digitized_time", "exif:DateTimeDigitized"),
+    ("modify_datetime", "tiff:DateTime"),
+    ("capture_time_offset", "exif:OffsetTimeOriginal"),
+    ("timezone_offset", "makernotes:TimeZone"),
+    ("camera_model", "tiff:Model"),
+    ("camera_serial", "exif:BodySerialNumber"),
+    ("camera_owner", "exif:CameraOwnerName"),
+    ("lens_model", "exif:LensModel"),
+    ("lens_serial", "exif:LensSerialNumber"),
+    ("lens_spec", "exif:LensSpecification"),
+    ("image_unique_id", "exif:ImageUniqueID"),
+    ("is_composite", "exif:CompositeImage"),
+    ("description", "tiff:ImageDescription"),
+    ("rating", "tiff:Rating"),
+    ("keywords", "tiff:XPKeywords"),
+    ("sensing_method", "exif:SensingMethod"),
+    ("file_source", "exif:FileSource"),
+    ("compression", "tiff:Compression"),
+    (
+        "photometric_interpretation",
+        "tiff:PhotometricInterpretation",
+    ),
+    ("gain_control", "exif:GainControl"),
+    ("iso", "exif:ISO"),
+    ("sensitivity_type", "exif:SensitivityType"),
+    (
+        "recommended_exposure_index",
+        "exif:RecommendedExposureIndex",
+    ),
+    ("spectral_sensitivity", "exif:SpectralSensitivity"),
+    ("oecf", "exif:OECF"),
+    ("white_balance_kelvin", "makernotes:ColorTemperature"),
+    ("noise_reduction", "dng:NoiseReductionApplied"),
+    (
+        "long_exposure_noise_reduction",
+        "makernotes:LongExposureNoiseReduction",
+    ),
+    ("camera_temperature_c", "makernotes:CameraTemperature"),
+    ("af_points_used", "makernotes:AFPointsInFocus"),
+    ("has_icc_profile", "composite:HasICCProfile"),
+    ("mpf_image_count", "mpf:NumberOfImages"),
+    ("num_components", "file:ColorComponents"),
+    ("mpf_images", "mpf:MPImages"),
+    ("as_shot_neutral", "dng:AsShotNeutral"),
+    ("color_matrix_1", "dng:ColorMatrix1"),
+    ("color_matrix_2", "dng:ColorMatrix2"),
+    ("exposure_time", "exif:ExposureTime"),
+    ("shutter_speed_apex", "exif:ShutterSpeedValue"),
+    ("f_number", "exif:FNumber"),
+    ("aperture_apex", "exif:ApertureValue"),
+    ("subject_area", "exif:SubjectArea"),
+    ("gps_latitude", "gps:Latitude"),
+    ("gps_longitude", "gps:Longitude"),
+    ("geohash", "composite:Geohash"),
+    ("gps_datetime", "gps:DateTime"),
+    ("gps_differential", "gps:Differential"),
+    ("gps_status", "gps:Status"),
+    ("gps_measure_mode", "gps:MeasureMode"),
+    ("gps_area_information", "gps:AreaInformation"),
+    ("gps_version", "gps:VersionID"),
+    ("gps_h_positioning_error_m", "gps:HPositioningError"),
+    ("gps_dest_latitude", "gps:DestLatitude"),
+    ("gps_dest_longitude", "gps:DestLongitude"),
+    ("gps_dest_bearing", "gps:DestBearing"),
+    ("gps_track", "gps:Track"),
+    ("gps_track_ref", "gps:TrackRef"),
+    ("gps_extra", "gps:Extra"),
+    ("best_capture_time", "composite:BestCaptureTime"),
+    ("has_gps", "composite:HasGPS"),
+    ("has_capture_time", "composite:HasCaptureTime"),
+    ("has_camera_info", "composite:HasCameraInfo"),
+    ("warnings", "composite:Warnings"),
+];
+
+/// The naming convention for the json keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyCase {
+    /// ie capture_time, matching the field names
+    Snake,
+    /// ie captureTime, for javascript consumers
+    Camel,
+}
+
+impl Default for KeyCase {
+    fn default() -> Self {
+        KeyCase::Snake
+    }
+}
+
+/// Parses "snake" or "camel" as passed on the command line
+impl FromStr for KeyCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "snake" => Ok(KeyCase::Snake),
+            "camel" => Ok(KeyCase::Camel),
+            _ => Err(format!("Expected snake or camel but got \"{}\"", s)),
+        }
+    }
+}
+
+/// Converts the metadata into the json value we write out, as per the output options
+pub fn to_output_value(
+    metadata: &CombinedMetadata,
+    options: &Options,
+) -> serde_json::Result<Value> {
+    let mut value = serde_json::to_value(metadata)?;
+    if let Value::Object(map) = &mut value {
+        if options.presence_flags {
+            add_presence_flags(map, metadata);
+        }
+        if let Some(precision) = options.geohash_precision {
+            set_geohash_precision(map, metadata, precision);
+        }
+        let warnings = &metadata.image_metadata.warnings;
+        if options.capture_warnings && !warnings.is_empty() {
+            map.insert("warnings".to_string(), warnings.clone().into());
+        }
+        if options.sort_keys {
+            sort_keys(map, metadata)?;
+        }
+    }
+    if let Some(digits) = options.float_precision {
+        round_floats(&mut value, digits);
+    }
+    if let Some(precision) = options.gps_precision {
+        round_gps(&mut value, precision);
+    }
+    if let Value::Object(map) = &mut value {
+        filter_fields(map, options);
+        if options.omit_empty {
+            omit_empty(map);
+        }
+        if options.debug_tags {
+            debug_tags::add_tag_types(map, &metadata.image_metadata.tag_types);
+        }
+        if options.nested {
+            nest(map, metadata)?;
+        }
+    }
+    if let Value::Object(map) = &mut value {
+        if options.grouped_keys {
+            group_keys(map);
+            if options.nested {
+                for key in &["file", "image"] {
+                    if let Some(Value::Object(nested)) = map.get_mut(*key) {
+                        group_keys(nested);
+                    }
+                }
+            }
+        }
+    }
+    if options.key_case == KeyCase::Camel {
+        value = camel_case_keys(value);
+    }
+    Ok(value)
+}
+
+fn add_presence_flags(map: &mut Map<String, Value>, metadata: &CombinedMetadata) {
+    map.insert("has_gps".to_string(), metadata.has_gps().into());
+    map.insert(
+        "has_capture_time".to_string(),
+        metadata.has_capture_time().into(),
+    );
+    map.insert(
+        "has_camera_info".to_string(),
+        metadata.has_camera_info().into(),
+    );
+}
+
+/// Works the geohash out again to the given number of characters, from the full precision
+/// coordinates rather than any rounded ones
+fn set_geohash_precision(
+    map: &mut Map<String, Value>,
+    metadata: &CombinedMetadata,
+    precision: usize,
+) {
+    let image_metadata = &metadata.image_metadata;
+    if let (Some(latitude), Some(longitude)) =
+        (image_metadata.gps_latitude, image_metadata.gps_longitude)
+    {
+        map.insert(
+            "geohash".to_string(),
+            crate::geohash(latitude, longitude, precision).into(),
+        );
+    }
+}
+
+/// Moves the file and image fields into `file` and `image` objects of their own, the
+/// fields derived from both stay at the top level
+fn nest(map: &mut Map<String, Value>, metadata: &CombinedMetadata) -> serde_json::Result<()> {
+    let file_keys = to_object(&metadata.file_metadata)?;
+    let image_keys = to_object(&metadata.image_metadata)?;
+    let mut file = Map::new();
+    let mut image = Map::new();
+    let mut derived = Map::new();
+    for (key, value) in std::mem::take(map) {
+        if file_keys.contains_key(&key) {
+            file.insert(key, value);
+        } else if image_keys.contains_key(&key) {
+            image.insert(key, value);
+        } else {
+            derived.insert(key, value);
+        }
+    }
+    map.insert("file".to_string(), Value::Object(file));
+    map.insert("image".to_string(), Value::Object(image));
+    map.extend(derived);
+    Ok(())
+}
+
+/// Drops any fields that are empty strings, arrays or objects
+fn omit_empty(map: &mut Map<String, Value>) {
+    let is_empty = |value: &Value| match value {
+        Value::String(s) => s.is_empty(),
+        Value::Array(values) => values.is_empty(),
+        Value::Object(map) => map.is_empty(),
+        _ => false,
+    };
+    *map = std::mem::take(map)
+        .into_iter()
+        .filter(|(_, value)| !is_empty(value))
+        .collect();
+}
+
+/// Drops any fields not in the include list, if there is one, or in the exclude list
+fn filter_fields(map: &mut Map<String, Value>, options: &Options) {
+    let keep = |key: &String| {
+        options
+            .include
+            .as_ref()
+            .map_or(true, |include| include.contains(key))
+            && !options.exclude.contains(key)
+    };
+    *map = std::mem::take(map)
+        .into_iter()
+        .filter(|(key, _)| keep(key))
+        .collect();
+}
+
+/// Renames the fields to their group prefixed names, ie camera_model to tiff:Model
+fn group_keys(map: &mut Map<String, Value>) {
+    *map = std::mem::take(map)
+        .into_iter()
+        .map(|(key, value)| {
+            let grouped = GROUPED_KEYS
+                .iter()
+                .find(|(field, _)| *field == key)
+                .map_or(key, |(_, grouped)| grouped.to_string());
+            (grouped, value)
+        })
+        .collect();
+}
+
+/// Renames all the object keys from snake_case to camelCase, ie capture_time to captureTime
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (to_camel_case(&key), camel_case_keys(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(camel_case_keys).collect()),
+        other => other,
+    }
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut parts = key.split('_');
+    let mut camel = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+/// Rounds every non integer number to the given number of significant digits
+fn round_floats(value: &mut Value, digits: u32) {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            if let Some(rounded) = number.as_f64().map(|n| round_significant(n, digits)) {
+                *value = Value::from(rounded);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| round_floats(v, digits)),
+        Value::Object(map) => map.values_mut().for_each(|v| round_floats(v, digits)),
+        _ => {}
+    }
+}
+
+fn round_significant(value: f64, digits: u32) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let scale = 10f64.powi(digits as i32 - 1 - magnitude);
+    (value * scale).round() / scale
+}
+
+/// Rounds the GPS coordinates to the given number of decimal places, the full precision
+/// is rarely meaningful and just bloats the output
+fn round_gps(value: &mut Value, precision: u32) {
+    let scale = 10f64.powi(precision as i32);
+    if let Value::Object(map) = value {
+        for field in GPS_FIELDS {
+            if let Some(coordinate) = map.get_mut(*field) {
+                if let Some(degrees) = coordinate.as_f64() {
+                    *coordinate = Value::from((degrees * scale).round() / scale);
+                }
+            }
+        }
+    }
+}
+
+/// Lays out the keys in a stable order that doesn't depend on how the structs happen to
+/// be declared, the file fields come first, then the image fields, then any fields derived
+/// from both, with each group sorted alphabetically. The flags and warnings we add on top
+/// count as derived fields
+fn sort_keys(map: &mut Map<String, Value>, metadata: &CombinedMetadata) -> serde_json::Result<()> {
+    let file_keys = to_object(&metadata.file_metadata)?;
+    let image_keys = to_object(&metadata.image_metadata)?;
+    let mut file_fields = Vec::new();
+    let mut image_fields = Vec::new();
+    let mut derived_fields = Vec::new();
+    for (key, value) in std::mem::take(map) {
+        if file_keys.contains_key(&key) {
+            file_fields.push((key, value));
+        } else if image_keys.contains_key(&key) {
+            image_fields.push((key, value));
+        } else {
+            derived_fields.push((key, value));
+        }
+    }
+
+    for mut fields in vec![file_fields, image_fields, derived_fields] {
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        map.extend(fields);
+    }
+    Ok(())
+}
+
+fn to_object<T: Serialize>(value: &T) -> serde_json::Result<Map<String, Value>> {
+    match serde_json::to_value(value)? {
+        Value::Object(map) => Ok(map),
+        other => Err(serde::ser::Error::custom(format!(
+            "Expected a json object but got {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileMetadata, ImageMetadata};
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    #[test]
+    fn test_sorted_keys() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            created_time: Some(Utc.ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            modified_time: Some(Utc.ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            orientation: Some(1),
+            capture_time: Some(NaiveDate::from_ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            camera_model: Some("foo".to_string()),
+            camera_serial: Some("bar".to_string()),
+            gps_latitude: Some(-44.7),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            sort_keys: true,
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "created_time",
+                "filename",
+                "modified_time",
+                "size",
+                "camera_model",
+                "camera_serial",
+                "capture_time",
+                "gps_latitude",
+                "orientation",
+                "best_capture_time",
+            ]
+        );
+
+        // The presence flags are derived fields so get sorted in with the others
+        let options = Options {
+            sort_keys: true,
+            presence_flags: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys[9..],
+            [
+                "best_capture_time",
+                "has_camera_info",
+                "has_capture_time",
+                "has_gps",
+            ]
+        );
+
+        // Without sorting we should just get the struct order
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys[..3], ["filename", "size", "created_time"]);
+    }
+
+    #[test]
+    fn test_gps_precision() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            gps_latitude: Some(-44.703_456_789_012),
+            gps_longitude: Some(169.132_543_21),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            gps_precision: Some(4),
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(output["gps_latitude"], -44.7035);
+        assert_eq!(output["gps_longitude"], 169.1325);
+
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        assert_eq!(output["gps_latitude"], -44.703_456_789_012);
+    }
+
+    #[test]
+    fn test_geohash_precision() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            gps_latitude: Some(57.64911),
+            gps_longitude: Some(10.40744),
+            geohash: Some("u4pruydqq".to_string()),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            geohash_precision: Some(5),
+            // The geohash comes from the coordinates before they're rounded
+            gps_precision: Some(1),
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(output["geohash"], "u4pru");
+
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        assert_eq!(output["geohash"], "u4pruydqq");
+    }
+
+    #[test]
+    fn test_filter_fields() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            camera_model: Some("foo".to_string()),
+            camera_serial: Some("bar".to_string()),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let keys = |options: &Options| {
+            let output = to_output_value(&metadata, options).unwrap();
+            output
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let options = Options {
+            exclude: vec!["camera_serial".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(keys(&options), vec!["filename", "size", "camera_model"]);
+
+        // Exclude wins when a field is in both
+        let options = Options {
+            include: Some(vec!["filename".to_string(), "camera_serial".to_string()]),
+            exclude: vec!["camera_serial".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(keys(&options), vec!["filename"]);
+    }
+
+    #[test]
+    fn test_camel_case_keys() {
+        assert_eq!(to_camel_case("capture_time"), "captureTime");
+        assert_eq!(to_camel_case("gps_datetime"), "gpsDatetime");
+        assert_eq!(to_camel_case("filename"), "filename");
+
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            capture_time: Some(NaiveDate::from_ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            key_case: KeyCase::Camel,
+            sort_keys: true,
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec!["filename", "size", "captureTime", "bestCaptureTime"]
+        );
+        assert_eq!(output["captureTime"], "1986-04-27T07:30:00");
+    }
+
+    #[test]
+    fn test_grouped_keys() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            capture_time: Some(NaiveDate::from_ymd(1986, 4, 27).and_hms(7, 30, 0)),
+            camera_model: Some("foo".to_string()),
+            gps_latitude: Some(-44.7),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            grouped_keys: true,
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "file:FileName",
+                "file:FileSize",
+                "exif:DateTimeOriginal",
+                "tiff:Model",
+                "gps:Latitude",
+                "composite:BestCaptureTime",
+            ]
+        );
+        assert_eq!(output["tiff:Model"], "foo");
+    }
+
+    #[test]
+    fn test_omit_empty() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            camera_model: Some("foo".to_string()),
+            description: Some("".to_string()),
+            subject_area: Some(vec![]),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        assert_eq!(output["description"], "");
+
+        let options = Options {
+            omit_empty: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        let keys = output.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, vec!["filename", "size", "camera_model"]);
+    }
+
+    #[test]
+    fn test_float_precision() {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 123456,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            // sqrt(2)^2.97 as worked out from the aperture APEX value
+            f_number: Some(2.8000000000000003),
+            exposure_time: Some(0.0015625),
+            gps_latitude: Some(-44.703456789),
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            float_precision: Some(6),
+            ..Default::default()
+        };
+
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(output["f_number"].to_string(), "2.8");
+        assert_eq!(output["exposure_time"].to_string(), "0.0015625");
+        assert_eq!(output["gps_latitude"].to_string(), "-44.7035");
+        // Integers are left alone
+        assert_eq!(output["size"].to_string(), "123456");
+    }
+
+    #[test]
+    fn test_nested() {
+        let bytes = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let image_metadata = crate::extract_metadata_from_bytes(&bytes).unwrap();
+        let file_metadata = FileMetadata {
+            filename: "JAM19896.jpg".to_string(),
+            size: 953458,
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+        let options = Options {
+            nested: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(output["file"]["filename"], "JAM19896.jpg");
+        assert_eq!(output["file"]["size"], 953458);
+        assert_eq!(output["image"]["camera_model"], "Canon EOS 5D Mark IV");
+        assert!(output["best_capture_time"].is_string());
+        assert!(output.get("filename").is_none());
+        assert!(output["file"].get("camera_model").is_none());
+
+        let options = Options {
+            nested: true,
+            grouped_keys: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(output["file"]["file:FileName"], "JAM19896.jpg");
+        assert_eq!(output["image"]["tiff:Model"], "Canon EOS 5D Mark IV");
+    }
+
+    #[test]
+    fn test_capture_warnings() {
+        let bytes = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let mut image_metadata = crate::extract_metadata_from_bytes(&bytes).unwrap();
+        image_metadata
+            .warnings
+            .push("Ignoring malformed capture time \"2019-07-26\"".to_string());
+        let file_metadata = FileMetadata {
+            filename: "JAM19896.jpg".to_string(),
+            size: 953458,
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        assert!(output.get("warnings").is_none());
+
+        let options = Options {
+            capture_warnings: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        assert_eq!(
+            output["warnings"],
+            serde_json::json!(["Ignoring malformed capture time \"2019-07-26\""])
+        );
+    }
+
+    #[test]
+    fn test_presence_flags() {
+        let bytes = std::fs::read("tests/images/JAM19896.jpg").unwrap();
+        let image_metadata = crate::extract_metadata_from_bytes(&bytes).unwrap();
+        let file_metadata = FileMetadata {
+            filename: "JAM19896.jpg".to_string(),
+            size: 953458,
+            ..Default::default()
+        };
+        let metadata = CombinedMetadata::new(file_metadata, image_metadata);
+
+        let output = to_output_value(&metadata, &Options::default()).unwrap();
+        assert!(output.get("has_gps").is_none());
+
+        let options = Options {
+            presence_flags: true,
+            ..Default::default()
+        };
+        let output = to_output_value(&metadata, &options).unwrap();
+        // The sample has no GPS but does have the capture time and camera model
+        assert_eq!(output["has_gps"], false);
+        assert_eq!(output["has_capture_time"], true);
+        assert_eq!(output["has_camera_info"], true);
+    }
+}
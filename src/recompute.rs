@@ -0,0 +1,144 @@
+use crate::native::write_metadata_to_file;
+use crate::{output, CombinedMetadata, ImageError, Options};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Re-derives the computed fields, ie best_capture_time, of previously written json
+/// using the current logic, without needing the original images. The file is rewritten
+/// in place and can hold a single image, an array of them as written for zip archives,
+/// or one image per line if it has a .jsonl extension
+pub fn recompute_file<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+) -> Result<Vec<CombinedMetadata>, ImageError> {
+    let (values, is_array) = read_output_file(path.as_ref())?;
+    let metadata = values
+        .into_iter()
+        .map(|value| serde_json::from_value(value).map(recompute))
+        .collect::<serde_json::Result<Vec<_>>>()
+        .map_err(std::io::Error::from)?;
+    let output = metadata
+        .iter()
+        .map(|metadata| output::to_output_value(metadata, options))
+        .collect::<serde_json::Result<Vec<_>>>()
+        .map_err(std::io::Error::from)?;
+
+    if is_json_lines(path.as_ref()) {
+        let mut file = BufWriter::new(File::create(&path)?);
+        for value in &output {
+            serde_json::to_writer(&mut file, value).map_err(std::io::Error::from)?;
+            writeln!(file)?;
+        }
+        file.flush()?;
+    } else if is_array {
+        write_metadata_to_file(&path, &output)?;
+    } else {
+        write_metadata_to_file(&path, &output[0])?;
+    }
+    Ok(metadata)
+}
+
+/// Reads previously written json, returning the images in it and whether they were
+/// written out as an array
+pub(crate) fn read_output_file(path: &Path) -> std::io::Result<(Vec<Value>, bool)> {
+    let contents = std::fs::read_to_string(path)?;
+    if is_json_lines(path) {
+        let values = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<Vec<Value>>>()?;
+        Ok((values, false))
+    } else {
+        match serde_json::from_str(&contents)? {
+            Value::Array(values) => Ok((values, true)),
+            value => Ok((vec![value], false)),
+        }
+    }
+}
+
+fn is_json_lines(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| {
+        ext.to_string_lossy().eq_ignore_ascii_case("jsonl")
+    })
+}
+
+/// Throws away the stored computed fields and works them out again, keeping any extra
+/// fields added by library users
+fn recompute(metadata: CombinedMetadata) -> CombinedMetadata {
+    let mut image_metadata = metadata.image_metadata;
+    image_metadata.derive_fields();
+    let mut recomputed = CombinedMetadata::new(metadata.file_metadata, image_metadata);
+    recomputed.extra_fields = metadata.extra_fields;
+    recomputed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use indoc::indoc;
+
+    #[test]
+    fn test_recompute_file() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/recompute")?;
+        // As written before best_capture_time or the display dimensions existed
+        let path = "target/test/recompute/old.json";
+        std::fs::write(
+            path,
+            indoc! {r#"
+            {
+              "filename": "JAM26496.jpg",
+              "size": 1024405,
+              "orientation": 6,
+              "pixel_width": 5040,
+              "pixel_height": 3360,
+              "capture_time": "2020-01-30T09:44:56",
+              "capture_time_offset": "+13:00"
+            }"#},
+        )?;
+
+        let metadata = recompute_file(path, &Options::default())?;
+        assert_eq!(
+            metadata[0].best_capture_time,
+            Some(Utc.ymd(2020, 1, 29).and_hms(20, 44, 56))
+        );
+
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(path)?).unwrap();
+        assert_eq!(written["best_capture_time"], "2020-01-29T20:44:56Z");
+        assert_eq!(written["display_width"], 3360);
+        assert_eq!(written["display_height"], 5040);
+        assert_eq!(written["capture_time"], "2020-01-30T09:44:56");
+        Ok(())
+    }
+
+    #[test]
+    fn test_recompute_json_lines() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/recompute")?;
+        let path = "target/test/recompute/old.jsonl";
+        std::fs::write(
+            path,
+            concat!(
+                r#"{"filename":"a.jpg","size":1,"capture_time":"2020-01-30T09:44:56"}"#,
+                "\n",
+                r#"{"filename":"b.jpg","size":2}"#,
+                "\n",
+            ),
+        )?;
+
+        let metadata = recompute_file(path, &Options::default())?;
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(path)?,
+            concat!(
+                r#"{"filename":"a.jpg","size":1,"capture_time":"2020-01-30T09:44:56","best_capture_time":"2020-01-30T09:44:56Z"}"#,
+                "\n",
+                r#"{"filename":"b.jpg","size":2}"#,
+                "\n",
+            )
+        );
+        Ok(())
+    }
+}
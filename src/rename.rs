@@ -0,0 +1,89 @@
+use crate::native::{apply_image_options, extract_reader_with_options};
+use crate::{ImageError, Options};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Renames the image after when it was captured, ie 20190726_132533.jpg, keeping it in
+/// the same directory. Another image already having that name gets a numeric suffix,
+/// ie 20190726_132533_1.jpg. When `copy` is set the original is left in place and a
+/// renamed copy is made instead. Returns the new path, or None if the image has no
+/// capture time to go by
+pub fn rename_by_capture_time<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+    copy: bool,
+) -> Result<Option<PathBuf>, ImageError> {
+    let path = path.as_ref();
+    // Closing the file before renaming it as Windows won't rename open files
+    let image_metadata = {
+        let mut file = BufReader::new(File::open(path)?);
+        apply_image_options(extract_reader_with_options(&mut file, options), options)?
+    };
+    let capture_time = match image_metadata.capture_time {
+        Some(capture_time) => capture_time,
+        None => return Ok(None),
+    };
+
+    let stem = capture_time.format("%Y%m%d_%H%M%S").to_string();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut new_path = dir.join(format!("{}{}", stem, extension));
+    let mut suffix = 0;
+    // An image that's already been renamed keeps its name
+    while new_path.exists() && new_path != path {
+        suffix += 1;
+        new_path = dir.join(format!("{}_{}{}", stem, suffix, extension));
+    }
+
+    if new_path != path {
+        if copy {
+            std::fs::copy(path, &new_path)?;
+        } else {
+            std::fs::rename(path, &new_path)?;
+        }
+    }
+    Ok(Some(new_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_by_capture_time() -> Result<(), ImageError> {
+        let dir = Path::new("target/test/rename");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("JAM19896.jpg");
+        std::fs::copy("tests/images/JAM19896.jpg", &image_path)?;
+
+        let options = Options::default();
+        let renamed = rename_by_capture_time(&image_path, &options, false)?;
+        assert_eq!(renamed, Some(dir.join("20190726_132533.jpg")));
+        assert!(!image_path.exists());
+
+        // Renaming again leaves it be
+        let renamed = rename_by_capture_time(dir.join("20190726_132533.jpg"), &options, false)?;
+        assert_eq!(renamed, Some(dir.join("20190726_132533.jpg")));
+
+        // A second image captured at the same time gets a suffix, copying leaves the
+        // original in place
+        std::fs::copy("tests/images/JAM19896.jpg", &image_path)?;
+        let renamed = rename_by_capture_time(&image_path, &options, true)?;
+        assert_eq!(renamed, Some(dir.join("20190726_132533_1.jpg")));
+        assert!(image_path.exists());
+
+        let no_capture_time = dir.join("rotated_CCW90.jpg");
+        std::fs::copy("tests/images/rotated_CCW90.jpg", &no_capture_time)?;
+        assert_eq!(
+            rename_by_capture_time(&no_capture_time, &options, false)?,
+            None
+        );
+        assert!(no_capture_time.exists());
+        Ok(())
+    }
+}
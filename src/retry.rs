@@ -0,0 +1,97 @@
+use crate::ImageError;
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+/// How many times to retry a transient IO error when not told otherwise
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// How long to wait before the first retry, this doubles with each attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Whether an IO error is the sort of thing that might go away if we try again, ie a
+/// flaky network mount. Errors like NotFound or PermissionDenied are never going to
+/// change so there's no point waiting around
+fn is_retryable(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    )
+}
+
+fn is_retryable_error(error: &ImageError) -> bool {
+    match error {
+        ImageError::IoError(err) | ImageError::ExifError(exif::Error::Io(err)) => {
+            is_retryable(err.kind())
+        }
+        _ => false,
+    }
+}
+
+/// Runs the operation, retrying with a backoff if it fails with a transient IO error
+pub fn with_retries<T, F>(retries: u32, mut operation: F) -> Result<T, ImageError>
+where
+    F: FnMut() -> Result<T, ImageError>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(error) if attempt < retries && is_retryable_error(&error) => {
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Error;
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(ErrorKind::Interrupted));
+        assert!(is_retryable(ErrorKind::WouldBlock));
+        assert!(is_retryable(ErrorKind::TimedOut));
+        assert!(!is_retryable(ErrorKind::NotFound));
+        assert!(!is_retryable(ErrorKind::PermissionDenied));
+        assert!(!is_retryable(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_with_retries() {
+        // Succeeds once the transient errors clear up
+        let mut attempts = 0;
+        let result = with_retries(2, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(Error::from(ErrorKind::Interrupted).into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up once out of retries
+        let mut attempts = 0;
+        let result: Result<(), _> = with_retries(2, || {
+            attempts += 1;
+            Err(Error::from(ErrorKind::Interrupted).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // Fails straight away on a permanent error
+        let mut attempts = 0;
+        let result: Result<(), _> = with_retries(2, || {
+            attempts += 1;
+            Err(Error::from(ErrorKind::NotFound).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
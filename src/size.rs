@@ -0,0 +1,53 @@
+/// Parses a file size argument like --max-file-size, either a number of bytes or a number
+/// with a K, M or G suffix for kibibytes, mebibytes or gibibytes, ie "500K" or "2GB"
+pub fn parse_file_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let (digits, multiplier) = UNITS
+        .iter()
+        .find_map(|(unit, multiplier)| upper.strip_suffix(unit).map(|digits| (digits, *multiplier)))
+        .unwrap_or((&upper, 1));
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "Expected a size like 1048576, 500K, 200MB or 2G but got \"{}\"",
+                s
+            )
+        })
+}
+
+/// The size suffixes we accept, the longer ones first so they're matched before the
+/// suffixes they end with
+const UNITS: &[(&str, u64)] = &[
+    ("KIB", 1 << 10),
+    ("MIB", 1 << 20),
+    ("GIB", 1 << 30),
+    ("KB", 1 << 10),
+    ("MB", 1 << 20),
+    ("GB", 1 << 30),
+    ("K", 1 << 10),
+    ("M", 1 << 20),
+    ("G", 1 << 30),
+    ("B", 1),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_size() {
+        assert_eq!(parse_file_size("1048576"), Ok(1_048_576));
+        assert_eq!(parse_file_size("500K"), Ok(512_000));
+        assert_eq!(parse_file_size("200MB"), Ok(200 * 1024 * 1024));
+        assert_eq!(parse_file_size("2 GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_file_size("1g"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_file_size("100B"), Ok(100));
+        assert!(parse_file_size("big").is_err());
+        assert!(parse_file_size("-1").is_err());
+        assert!(parse_file_size("").is_err());
+    }
+}
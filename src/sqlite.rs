@@ -0,0 +1,131 @@
+use crate::{output, CombinedMetadata, ImageError, Options};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, NO_PARAMS};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Writes the metadata for each image as a row in an sqlite database, the columns mirror
+/// the json fields and get added as new fields turn up so the table never needs to be
+/// migrated by hand
+pub struct SqliteWriter {
+    connection: Connection,
+    columns: HashSet<String>,
+}
+
+impl SqliteWriter {
+    /// Opens or creates the database, creating the images table if it's absent
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS images (path TEXT PRIMARY KEY NOT NULL)",
+            NO_PARAMS,
+        )?;
+        let columns = {
+            let mut statement = connection.prepare("PRAGMA table_info(images)")?;
+            let names = statement.query_map(NO_PARAMS, |row| row.get(1))?;
+            names.collect::<Result<HashSet<String>, _>>()?
+        };
+        Ok(SqliteWriter {
+            connection,
+            columns,
+        })
+    }
+
+    /// Inserts a row for the image, replacing any existing row for the same path so
+    /// reprocessing a file doesn't duplicate it
+    pub fn insert<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        metadata: &CombinedMetadata,
+        options: &Options,
+    ) -> Result<(), ImageError> {
+        let fields =
+            match output::to_output_value(metadata, options).map_err(std::io::Error::from)? {
+                Value::Object(fields) => fields,
+                _ => unreachable!("Metadata always serializes to an object"),
+            };
+
+        for name in fields.keys() {
+            if !self.columns.contains(name) {
+                self.connection.execute(
+                    &format!("ALTER TABLE images ADD COLUMN {}", quote_identifier(name)),
+                    NO_PARAMS,
+                )?;
+                self.columns.insert(name.clone());
+            }
+        }
+
+        let mut columns = vec![quote_identifier("path")];
+        let mut values = vec![SqlValue::Text(path.as_ref().to_string_lossy().to_string())];
+        for (name, value) in fields {
+            columns.push(quote_identifier(&name));
+            values.push(to_sql_value(value));
+        }
+        let placeholders = vec!["?"; columns.len()].join(", ");
+        self.connection.execute(
+            &format!(
+                "INSERT OR REPLACE INTO images ({}) VALUES ({})",
+                columns.join(", "),
+                placeholders
+            ),
+            values,
+        )?;
+        Ok(())
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Maps json onto the closest sqlite type, anything nested gets stored as json text
+fn to_sql_value(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SqlValue::Text(s),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract_file_with_options;
+
+    #[test]
+    fn test_sqlite_writer() -> Result<(), ImageError> {
+        std::fs::create_dir_all("target/test/sqlite")?;
+        let db_path = "target/test/sqlite/images.db";
+        let _ = std::fs::remove_file(db_path);
+
+        let options = Options::default();
+        let mut writer = SqliteWriter::open(db_path)?;
+        for path in &["tests/images/JAM19896.jpg", "tests/images/JAM26284.jpg"] {
+            let metadata = extract_file_with_options(path, &options)?.unwrap();
+            writer.insert(path, &metadata, &options)?;
+        }
+        // Reprocessing replaces rather than duplicates
+        let metadata = extract_file_with_options("tests/images/JAM19896.jpg", &options)?.unwrap();
+        writer.insert("tests/images/JAM19896.jpg", &metadata, &options)?;
+        drop(writer);
+
+        let connection = Connection::open(db_path)?;
+        let count: i64 =
+            connection.query_row("SELECT COUNT(*) FROM images", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(count, 2);
+        let (size, serial): (i64, String) = connection.query_row(
+            "SELECT size, camera_serial FROM images WHERE path = 'tests/images/JAM26284.jpg'",
+            NO_PARAMS,
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(size, 574207);
+        assert_eq!(serial, "025021000535");
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use crate::CombinedMetadata;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// How many of the most common camera models to report on
+const TOP_CAMERA_MODELS: usize = 5;
+
+/// Aggregate statistics over a batch of images
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub images: usize,
+    pub with_gps: usize,
+    pub with_capture_time: usize,
+    pub camera_models: HashMap<String, usize>,
+}
+
+/// A camera model and the number of images taken with it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CameraModelCount {
+    pub model: String,
+    pub count: usize,
+}
+
+impl Stats {
+    /// Adds an image into the stats
+    pub fn add(&mut self, metadata: &CombinedMetadata) {
+        let image_metadata = &metadata.image_metadata;
+        self.images += 1;
+        if image_metadata.gps_latitude.is_some() && image_metadata.gps_longitude.is_some() {
+            self.with_gps += 1;
+        }
+        if image_metadata.capture_time.is_some() {
+            self.with_capture_time += 1;
+        }
+        if let Some(model) = &image_metadata.camera_model {
+            *self.camera_models.entry(model.clone()).or_insert(0) += 1;
+        }
+    }
+
+    /// The most common camera models, most used first
+    pub fn top_camera_models(&self) -> Vec<CameraModelCount> {
+        let mut models = self
+            .camera_models
+            .iter()
+            .map(|(model, count)| CameraModelCount {
+                model: model.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        // Tie break on the name to keep the output stable
+        models.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.model.cmp(&b.model)));
+        models.truncate(TOP_CAMERA_MODELS);
+        models
+    }
+}
+
+/// Serializes with just the top camera models rather than every model seen
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct StatsOutput {
+            images: usize,
+            with_gps: usize,
+            with_capture_time: usize,
+            top_camera_models: Vec<CameraModelCount>,
+        }
+        StatsOutput {
+            images: self.images,
+            with_gps: self.with_gps,
+            with_capture_time: self.with_capture_time,
+            top_camera_models: self.top_camera_models(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Human readable form, ie
+/// "3 images, 0 with GPS, 3 with capture time, top camera models: Canon EOS 5D Mark IV (3)"
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} images, {} with GPS, {} with capture time, top camera models: ",
+            self.images, self.with_gps, self.with_capture_time
+        )?;
+        let models = self.top_camera_models();
+        if models.is_empty() {
+            return write!(f, "none");
+        }
+        for (i, model) in models.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} ({})", model.model, model.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileMetadata, ImageMetadata};
+
+    fn image(camera_model: Option<&str>, with_gps: bool) -> CombinedMetadata {
+        let file_metadata = FileMetadata {
+            filename: "test.jpg".to_string(),
+            size: 1,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            camera_model: camera_model.map(str::to_string),
+            gps_latitude: if with_gps { Some(-44.7) } else { None },
+            gps_longitude: if with_gps { Some(169.1) } else { None },
+            ..Default::default()
+        };
+        CombinedMetadata::new(file_metadata, image_metadata)
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        stats.add(&image(Some("Canon EOS 5D Mark IV"), true));
+        stats.add(&image(Some("Pixel 4a"), false));
+        stats.add(&image(Some("Canon EOS 5D Mark IV"), false));
+        stats.add(&image(None, false));
+
+        assert_eq!(
+            stats.to_string(),
+            "4 images, 1 with GPS, 0 with capture time, top camera models: Canon EOS 5D Mark IV (2), Pixel 4a (1)"
+        );
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            r#"{"images":4,"with_gps":1,"with_capture_time":0,"top_camera_models":[{"model":"Canon EOS 5D Mark IV","count":2},{"model":"Pixel 4a","count":1}]}"#
+        );
+    }
+}
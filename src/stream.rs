@@ -0,0 +1,79 @@
+use serde::Serialize;
+use std::io::Write;
+
+/// Writes a json array out an element at a time, so a batch of any size can be written
+/// without holding all of it in memory. Each element goes on its own line and is flushed
+/// as soon as it's written
+pub struct JsonArrayWriter<W: Write> {
+    writer: W,
+    empty: bool,
+}
+
+impl<W: Write> JsonArrayWriter<W> {
+    /// Starts the array, `finish` needs to be called to close it off
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(b"[")?;
+        Ok(JsonArrayWriter {
+            writer,
+            empty: true,
+        })
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&mut self, element: &T) -> std::io::Result<()> {
+        let separator: &[u8] = if self.empty { b"\n" } else { b",\n" };
+        self.writer.write_all(separator)?;
+        serde_json::to_writer(&mut self.writer, element).map_err(std::io::Error::from)?;
+        self.writer.flush()?;
+        self.empty = false;
+        Ok(())
+    }
+
+    /// Closes the array, handing back the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let end: &[u8] = if self.empty { b"]\n" } else { b"\n]\n" };
+        self.writer.write_all(end)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_json_array_writer() -> std::io::Result<()> {
+        let mut writer = JsonArrayWriter::new(vec![])?;
+        let mut written = writer.writer.len();
+        for i in 0..1000 {
+            writer.write(&json!({"filename": format!("{}.jpg", i), "size": i}))?;
+            // Each element is written out straight away rather than held onto
+            assert!(writer.writer.len() > written);
+            written = writer.writer.len();
+        }
+        let output = writer.finish()?;
+        let array: Vec<Value> = serde_json::from_slice(&output)?;
+        assert_eq!(array.len(), 1000);
+        assert_eq!(array[999]["filename"], "999.jpg");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_array_writer_edge_cases() -> std::io::Result<()> {
+        let output = JsonArrayWriter::new(vec![])?.finish()?;
+        assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+
+        let mut writer = JsonArrayWriter::new(vec![])?;
+        writer.write(&1)?;
+        let output = writer.finish()?;
+        assert_eq!(String::from_utf8(output).unwrap(), "[\n1\n]\n");
+
+        let mut writer = JsonArrayWriter::new(vec![])?;
+        writer.write(&1)?;
+        writer.write(&2)?;
+        let output = writer.finish()?;
+        assert_eq!(String::from_utf8(output).unwrap(), "[\n1,\n2\n]\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::CombinedMetadata;
+use chrono::Datelike;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What gets substituted for a placeholder the image has no value for
+pub const UNKNOWN: &str = "unknown";
+
+/// The placeholders that can be used in an output template
+const PLACEHOLDERS: &[&str] = &["year", "month", "day", "camera_model", "filename"];
+
+/// A templated output path, ie "{year}/{camera_model}/{filename}.json", with the
+/// placeholders filled in from each image's metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+impl OutputTemplate {
+    /// Fills in the placeholders for the given image, the values are sanitized so
+    /// a camera string can't add extra directories or climb out of the output directory
+    pub fn render(&self, metadata: &CombinedMetadata) -> PathBuf {
+        let capture_time = metadata.image_metadata.capture_time;
+        let rendered = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Placeholder(name) => {
+                    let value = match name.as_str() {
+                        "year" => capture_time.map(|time| format!("{:04}", time.year())),
+                        "month" => capture_time.map(|time| format!("{:02}", time.month())),
+                        "day" => capture_time.map(|time| format!("{:02}", time.day())),
+                        "camera_model" => metadata.image_metadata.camera_model.clone(),
+                        "filename" => Path::new(&metadata.file_metadata.filename)
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().to_string()),
+                        _ => unreachable!("Placeholders are checked when parsing"),
+                    };
+                    value.map_or_else(|| UNKNOWN.to_string(), |value| sanitize(&value))
+                }
+            })
+            .collect::<String>();
+        PathBuf::from(rendered)
+    }
+}
+
+/// Makes a value safe to use as a single path component
+fn sanitize(value: &str) -> String {
+    let sanitized = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    match sanitized.as_str() {
+        "" => UNKNOWN.to_string(),
+        "." | ".." => "_".to_string(),
+        _ => sanitized,
+    }
+}
+
+/// Parses a template as passed on the command line, placeholders are wrapped in braces
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in \"{}\"", s))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "Unknown placeholder {{{}}}, expected one of {}",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            parts.push(Part::Placeholder(name.to_string()));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(OutputTemplate { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileMetadata, ImageMetadata};
+    use chrono::NaiveDate;
+
+    fn metadata(camera_model: Option<&str>, with_capture_time: bool) -> CombinedMetadata {
+        let file_metadata = FileMetadata {
+            filename: "JAM19896.jpg".to_string(),
+            size: 1,
+            ..Default::default()
+        };
+        let image_metadata = ImageMetadata {
+            camera_model: camera_model.map(str::to_string),
+            capture_time: if with_capture_time {
+                Some(NaiveDate::from_ymd(2019, 7, 26).and_hms(13, 25, 33))
+            } else {
+                None
+            },
+            ..Default::default()
+        };
+        CombinedMetadata::new(file_metadata, image_metadata)
+    }
+
+    #[test]
+    fn test_render() {
+        let template: OutputTemplate = "{year}/{month}/{camera_model}/{filename}.json"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(&metadata(Some("Canon EOS 5D Mark IV"), true)),
+            PathBuf::from("2019/07/Canon EOS 5D Mark IV/JAM19896.json")
+        );
+        assert_eq!(
+            template.render(&metadata(None, false)),
+            PathBuf::from("unknown/unknown/unknown/JAM19896.json")
+        );
+    }
+
+    #[test]
+    fn test_render_sanitizes() {
+        let template: OutputTemplate = "{camera_model}/{filename}.json".parse().unwrap();
+        assert_eq!(
+            template.render(&metadata(Some("../../etc/passwd"), true)),
+            PathBuf::from(".._.._etc_passwd/JAM19896.json")
+        );
+        assert_eq!(
+            template.render(&metadata(Some(".."), true)),
+            PathBuf::from("_/JAM19896.json")
+        );
+        assert_eq!(
+            template.render(&metadata(Some("  "), true)),
+            PathBuf::from("unknown/JAM19896.json")
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("{year}/{filename}.json".parse::<OutputTemplate>().is_ok());
+        assert!("plain.json".parse::<OutputTemplate>().is_ok());
+        assert!("{year/{filename}.json".parse::<OutputTemplate>().is_err());
+        assert!("{lens}/{filename}.json".parse::<OutputTemplate>().is_err());
+        assert!("{year".parse::<OutputTemplate>().is_err());
+    }
+}
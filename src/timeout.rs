@@ -0,0 +1,73 @@
+use crate::ImageError;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Runs the given work on a worker thread, giving up on it if it doesn't finish within
+/// the timeout. Rust threads can't be killed so an abandoned worker carries on in the
+/// background, but the caller is free to move on to the next file. A panic in the work
+/// comes back as an error rather than taking the caller down with it
+pub fn run_with_timeout<T, F>(timeout: Duration, work: F) -> Result<T, ImageError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ImageError> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver will have gone away if we've timed out, nothing to do about that
+        let _ = sender.send(catch_unwind(AssertUnwindSafe(work)));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(result)) => result,
+        Ok(Err(payload)) => Err(ImageError::WorkerPanicked(panic_message(payload))),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(ImageError::Timeout(timeout)),
+        // The worker always sends something, even when it panics
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(ImageError::WorkerPanicked(
+            "Worker thread exited without a result".to_string(),
+        )),
+    }
+}
+
+/// The message a panic was raised with, panics with a formatted message carry a String
+/// and those with a literal a &str
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "Unknown panic".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_with_timeout() {
+        let result = run_with_timeout(Duration::from_secs(10), || Ok(42));
+        assert_eq!(result.unwrap(), 42);
+
+        let result = run_with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(42)
+        });
+        assert!(matches!(result, Err(ImageError::Timeout(_))));
+    }
+
+    #[test]
+    fn test_run_with_timeout_panic() {
+        let result: Result<u32, _> =
+            run_with_timeout(Duration::from_secs(10), || panic!("Bad parser {}", 42));
+        match result {
+            Err(ImageError::WorkerPanicked(message)) => assert_eq!(message, "Bad parser 42"),
+            other => panic!("Expected a panic error but got {:?}", other.map(|_| ())),
+        }
+
+        let result: Result<u32, _> =
+            run_with_timeout(Duration::from_secs(10), || panic!("Bad parser"));
+        assert_eq!(result.unwrap_err().to_string(), "Panicked: Bad parser");
+    }
+}
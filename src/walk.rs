@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+
+/// File extensions of the image formats we're able to extract exif data from.
+/// Canon's CR2, Nikon's NEF and Sony's ARW raws are tiffs underneath, so the standard
+/// tags read fine without needing to understand the rest of them
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "tif", "tiff", "dng", "cr2", "nef", "arw", "png", "heic", "heif", "avif",
+];
+
+/// Whether a path looks like an image we know how to read, going off its extension
+pub fn is_image_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .map_or(false, |ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether a path has one of the given extensions, ignoring case and any leading '.'
+/// on the extensions, ie "JPG" or ".jpg" both match "a.jpg"
+pub fn has_extension<P: AsRef<Path>, S: AsRef<str>>(path: P, extensions: &[S]) -> bool {
+    let ext = match path.as_ref().extension() {
+        Some(ext) => ext.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+    extensions
+        .iter()
+        .any(|wanted| wanted.as_ref().trim_start_matches('.').to_lowercase() == ext)
+}
+
+/// Whether a path is a dotfile or dot directory, this also covers the "._" AppleDouble
+/// resource forks macOS leaves behind on non-apple filesystems
+fn is_hidden_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .map_or(false, |name| name.to_string_lossy().starts_with('.'))
+}
+
+/// The images found under a directory, along with anything we weren't able to read
+#[derive(Debug, Default)]
+pub struct FoundImages {
+    pub images: Vec<PathBuf>,
+    /// Directories or entries that couldn't be read, ie due to permissions, or symlinks
+    /// looping back to a directory we're already in. These are skipped over rather than
+    /// stopping the walk
+    pub skipped: Vec<(PathBuf, std::io::Error)>,
+}
+
+/// Recursively finds all the images under a directory, in a stable sorted order.
+/// When skip_hidden is set any hidden files or directories are ignored. max_depth limits
+/// how many levels of subdirectories are descended into, 0 being just the files directly
+/// in the directory. Symlinked directories are followed, apart from those that loop back
+/// on themselves
+pub fn find_images<P: AsRef<Path>>(
+    dir: P,
+    skip_hidden: bool,
+    max_depth: Option<usize>,
+) -> FoundImages {
+    let mut found = FoundImages::default();
+    walk(
+        dir.as_ref(),
+        skip_hidden,
+        max_depth,
+        &mut vec![],
+        &mut found,
+    );
+    found
+}
+
+/// Walks the directory, `ancestors` being the canonical paths of the directories we're
+/// in the middle of walking, to catch symlinks back up to one of them
+fn walk(
+    dir: &Path,
+    skip_hidden: bool,
+    max_depth: Option<usize>,
+    ancestors: &mut Vec<PathBuf>,
+    found: &mut FoundImages,
+) {
+    let canonical_dir = match dir.canonicalize() {
+        Ok(canonical_dir) => canonical_dir,
+        Err(error) => {
+            found.skipped.push((dir.to_path_buf(), error));
+            return;
+        }
+    };
+    if ancestors.contains(&canonical_dir) {
+        let error = std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Symlink loops back to {}", canonical_dir.display()),
+        );
+        found.skipped.push((dir.to_path_buf(), error));
+        return;
+    }
+    ancestors.push(canonical_dir);
+    walk_entries(dir, skip_hidden, max_depth, ancestors, found);
+    ancestors.pop();
+}
+
+fn walk_entries(
+    dir: &Path,
+    skip_hidden: bool,
+    max_depth: Option<usize>,
+    ancestors: &mut Vec<PathBuf>,
+    found: &mut FoundImages,
+) {
+    let mut entries = vec![];
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(error) => {
+            found.skipped.push((dir.to_path_buf(), error));
+            return;
+        }
+    };
+    for entry in read_dir {
+        match entry {
+            Ok(entry) => entries.push(entry.path()),
+            Err(error) => found.skipped.push((dir.to_path_buf(), error)),
+        }
+    }
+    entries.sort();
+
+    for path in entries {
+        if skip_hidden && is_hidden_path(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            match max_depth {
+                Some(0) => {}
+                Some(depth) => walk(&path, skip_hidden, Some(depth - 1), ancestors, found),
+                None => walk(&path, skip_hidden, None, ancestors, found),
+            }
+        } else if is_image_path(&path) {
+            found.images.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_image_path() {
+        assert!(is_image_path("photos/JAM19896.jpg"));
+        assert!(is_image_path("photos/JAM19896.JPEG"));
+        assert!(is_image_path("photos/IMG_0001.CR2"));
+        assert!(is_image_path("photos/DSC_0001.nef"));
+        assert!(is_image_path("photos/DSC00001.ARW"));
+        assert!(!is_image_path("photos/JAM19896.json"));
+        assert!(!is_image_path("photos/README"));
+    }
+
+    #[test]
+    fn test_has_extension() {
+        assert!(has_extension("photos/a.JPG", &["jpg"]));
+        assert!(has_extension("photos/a.jpg", &["png", ".JPG"]));
+        assert!(!has_extension("photos/a.png", &["jpg"]));
+        assert!(!has_extension("photos/README", &["jpg"]));
+    }
+
+    #[test]
+    fn test_find_images() -> std::io::Result<()> {
+        let dir = Path::new("target/test/walk");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("2020/.thumbnails"))?;
+        for name in &[
+            "b.jpg",
+            "a.JPG",
+            "notes.txt",
+            "._a.JPG",
+            ".hidden.jpg",
+            "2020/c.tif",
+            "2020/.thumbnails/c.jpg",
+        ] {
+            std::fs::write(dir.join(name), b"")?;
+        }
+
+        assert_eq!(
+            find_images(dir, true, None).images,
+            vec![dir.join("2020/c.tif"), dir.join("a.JPG"), dir.join("b.jpg")]
+        );
+        assert_eq!(
+            find_images(dir, false, None).images,
+            vec![
+                dir.join("._a.JPG"),
+                dir.join(".hidden.jpg"),
+                dir.join("2020/.thumbnails/c.jpg"),
+                dir.join("2020/c.tif"),
+                dir.join("a.JPG"),
+                dir.join("b.jpg"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_images_max_depth() -> std::io::Result<()> {
+        let dir = Path::new("target/test/walk_max_depth");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("2020/january"))?;
+        for name in &["a.jpg", "2020/b.jpg", "2020/january/c.jpg"] {
+            std::fs::write(dir.join(name), b"")?;
+        }
+
+        assert_eq!(
+            find_images(dir, false, Some(0)).images,
+            vec![dir.join("a.jpg")]
+        );
+        assert_eq!(
+            find_images(dir, false, Some(1)).images,
+            vec![dir.join("2020/b.jpg"), dir.join("a.jpg")]
+        );
+        assert_eq!(find_images(dir, false, None).images.len(), 3);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_images_symlink_loop() -> std::io::Result<()> {
+        let dir = Path::new("target/test/walk_symlink_loop");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("sub"))?;
+        std::fs::create_dir_all(dir.join("other"))?;
+        std::fs::write(dir.join("a.jpg"), b"")?;
+        std::fs::write(dir.join("other/b.jpg"), b"")?;
+        std::os::unix::fs::symlink("..", dir.join("sub/up"))?;
+        std::os::unix::fs::symlink("../other", dir.join("sub/linked"))?;
+
+        let found = find_images(dir, false, None);
+        // Symlinks to directories outside of the walk are still followed
+        assert_eq!(
+            found.images,
+            vec![
+                dir.join("a.jpg"),
+                dir.join("other/b.jpg"),
+                dir.join("sub/linked/b.jpg"),
+            ]
+        );
+        assert_eq!(found.skipped.len(), 1);
+        assert_eq!(found.skipped[0].0, dir.join("sub/up"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_images_unreadable() -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::new("target/test/walk_unreadable");
+        if dir.exists() {
+            std::fs::set_permissions(dir.join("locked"), std::fs::Permissions::from_mode(0o755))?;
+            std::fs::remove_dir_all(dir)?;
+        }
+        std::fs::create_dir_all(dir.join("locked"))?;
+        std::fs::write(dir.join("a.jpg"), b"")?;
+        std::fs::write(dir.join("locked/b.jpg"), b"")?;
+        std::fs::set_permissions(dir.join("locked"), std::fs::Permissions::from_mode(0o000))?;
+
+        let found = find_images(dir, false, None);
+        let readable_anyway = std::fs::read_dir(dir.join("locked")).is_ok();
+        std::fs::set_permissions(dir.join("locked"), std::fs::Permissions::from_mode(0o755))?;
+        // Permissions don't stop root so there's nothing to test
+        if readable_anyway {
+            return Ok(());
+        }
+
+        assert_eq!(found.images, vec![dir.join("a.jpg")]);
+        assert_eq!(found.skipped.len(), 1);
+        assert_eq!(found.skipped[0].0, dir.join("locked"));
+        assert_eq!(
+            found.skipped[0].1.kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        Ok(())
+    }
+}
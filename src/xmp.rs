@@ -0,0 +1,357 @@
+use crate::ImageMetadata;
+use chrono::{DateTime, NaiveDateTime};
+use std::fmt::Write;
+#[cfg(feature = "native")]
+use std::path::{Path, PathBuf};
+
+/// Writes out the rating, keywords, GPS location and capture time as an XMP packet, in
+/// the namespaces photo managers like digiKam and Darktable read them from. Fields the
+/// image doesn't have are left out
+pub fn to_xmp(metadata: &ImageMetadata) -> String {
+    let mut properties = String::new();
+    if let Some(rating) = metadata.rating {
+        property(&mut properties, "xmp:Rating", &rating.to_string());
+    }
+    if let Some(capture_time) = metadata.capture_time {
+        let mut datetime = capture_time.format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+        datetime.push_str(metadata.capture_time_offset.as_deref().unwrap_or_default());
+        property(&mut properties, "exif:DateTimeOriginal", &datetime);
+    }
+    if let (Some(latitude), Some(longitude)) = (metadata.gps_latitude, metadata.gps_longitude) {
+        property(
+            &mut properties,
+            "exif:GPSLatitude",
+            &coordinate(latitude, 'N', 'S'),
+        );
+        property(
+            &mut properties,
+            "exif:GPSLongitude",
+            &coordinate(longitude, 'E', 'W'),
+        );
+    }
+    if !metadata.keywords.is_empty() {
+        properties.push_str("   <dc:subject>\n    <rdf:Bag>\n");
+        for keyword in &metadata.keywords {
+            let _ = writeln!(properties, "     <rdf:li>{}</rdf:li>", escape(keyword));
+        }
+        properties.push_str("    </rdf:Bag>\n   </dc:subject>\n");
+    }
+
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+            " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "  <rdf:Description rdf:about=\"\"\n",
+            "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n",
+            "    xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"\n",
+            "    xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n",
+            "{}",
+            "  </rdf:Description>\n",
+            " </rdf:RDF>\n",
+            "</x:xmpmeta>\n",
+            "<?xpacket end=\"w\"?>\n",
+        ),
+        properties
+    )
+}
+
+/// Writes the XMP sidecar for an image next to it, named after the whole filename as
+/// digiKam and Darktable expect, ie JAM19896.jpg.xmp. Returns the path of the sidecar
+#[cfg(feature = "native")]
+pub fn write_xmp_sidecar<P: AsRef<Path>>(
+    path: P,
+    metadata: &ImageMetadata,
+) -> std::io::Result<PathBuf> {
+    let mut sidecar = path.as_ref().as_os_str().to_os_string();
+    sidecar.push(".xmp");
+    let sidecar = PathBuf::from(sidecar);
+    std::fs::write(&sidecar, to_xmp(metadata))?;
+    Ok(sidecar)
+}
+
+/// Merges the rating, keywords, GPS location and capture time from an XMP packet into
+/// the metadata. The XMP is taken to hold later edits, so any of these it has win over
+/// the ones from the exif, with its keywords replacing rather than adding to any from the
+/// exif. Only these few properties are looked for, as either attributes or elements,
+/// rather than the XMP being parsed as a whole
+pub fn merge_xmp(metadata: &mut ImageMetadata, xmp: &str) {
+    if let Some(rating) = find_property(xmp, "xmp:Rating").and_then(|r| r.parse().ok()) {
+        metadata.rating = Some(rating).filter(|rating| *rating <= 5);
+    }
+    let keywords = find_keywords(xmp);
+    if !keywords.is_empty() {
+        metadata.keywords = keywords;
+    }
+    let latitude = find_property(xmp, "exif:GPSLatitude").and_then(|c| parse_coordinate(&c));
+    let longitude = find_property(xmp, "exif:GPSLongitude").and_then(|c| parse_coordinate(&c));
+    if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+        metadata.gps_latitude = Some(latitude);
+        metadata.gps_longitude = Some(longitude);
+    }
+    if let Some(datetime) = find_property(xmp, "exif:DateTimeOriginal") {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(&datetime) {
+            metadata.capture_time = Some(datetime.naive_local());
+            metadata.capture_time_offset = Some(datetime.format("%:z").to_string());
+        } else if let Ok(datetime) =
+            NaiveDateTime::parse_from_str(&datetime, "%Y-%m-%dT%H:%M:%S%.f")
+        {
+            metadata.capture_time = Some(datetime);
+            metadata.capture_time_offset = None;
+        }
+    }
+    metadata.derive_fields();
+}
+
+/// Reads the XMP sidecar next to an image into its metadata as per `merge_xmp`, looking
+/// for JAM19896.jpg.xmp then JAM19896.xmp, the latter being how Lightroom names them.
+/// Returns the path of the sidecar, or None if there wasn't one
+#[cfg(feature = "native")]
+pub fn merge_xmp_sidecar<P: AsRef<Path>>(
+    path: P,
+    metadata: &mut ImageMetadata,
+) -> std::io::Result<Option<PathBuf>> {
+    let mut sidecar = path.as_ref().as_os_str().to_os_string();
+    sidecar.push(".xmp");
+    let candidates = [PathBuf::from(sidecar), path.as_ref().with_extension("xmp")];
+    for sidecar in candidates.iter() {
+        match std::fs::read_to_string(sidecar) {
+            Ok(xmp) => {
+                merge_xmp(metadata, &xmp);
+                return Ok(Some(sidecar.clone()));
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(None)
+}
+
+/// The value of a simple property, written either as an attribute, ie
+/// `xmp:Rating="3"`, or as an element, ie `<xmp:Rating>3</xmp:Rating>`
+fn find_property(xmp: &str, name: &str) -> Option<String> {
+    for quote in &['"', '\''] {
+        let attribute = format!("{}={}", name, quote);
+        if let Some(start) = xmp.find(&attribute).map(|start| start + attribute.len()) {
+            let end = xmp[start..].find(*quote)?;
+            return Some(unescape(&xmp[start..start + end]));
+        }
+    }
+    element_contents(xmp, name).map(|value| unescape(value.trim()))
+}
+
+/// The items of the dc:subject bag, the keywords
+fn find_keywords(xmp: &str) -> Vec<String> {
+    let mut keywords = vec![];
+    let mut bag = match element_contents(xmp, "dc:subject") {
+        Some(bag) => bag,
+        None => return keywords,
+    };
+    while let Some(start) = bag.find("<rdf:li") {
+        let item = &bag[start..];
+        let (open_end, close) = match (item.find('>'), item.find("</rdf:li>")) {
+            (Some(open_end), Some(close)) if open_end < close => (open_end, close),
+            _ => break,
+        };
+        let keyword = unescape(item[open_end + 1..close].trim());
+        if !keyword.is_empty() {
+            keywords.push(keyword);
+        }
+        bag = &item[close..];
+    }
+    keywords
+}
+
+/// Everything between the opening and closing tags of the first element with the name
+fn element_contents<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = xmp.find(&open)? + open.len();
+    let end = xmp[start..].find(&format!("</{}>", name))?;
+    Some(&xmp[start..start + end])
+}
+
+/// Parses XMP's "DDD,MM.mmk" or "DDD,MM,SSk" coordinates into signed decimal degrees
+fn parse_coordinate(coordinate: &str) -> Option<f64> {
+    let coordinate = coordinate.trim();
+    let direction = coordinate.chars().last()?;
+    let sign = match direction.to_ascii_uppercase() {
+        'N' | 'E' => 1.0,
+        'S' | 'W' => -1.0,
+        _ => return None,
+    };
+    let parts = coordinate[..coordinate.len() - 1]
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let degrees = match *parts.as_slice() {
+        [degrees, minutes] => degrees + minutes / 60.0,
+        [degrees, minutes, seconds] => degrees + minutes / 60.0 + seconds / 3600.0,
+        _ => return None,
+    };
+    Some(sign * degrees)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn property(properties: &mut String, name: &str, value: &str) {
+    let _ = writeln!(properties, "   <{0}>{1}</{0}>", name, escape(value));
+}
+
+/// XMP writes coordinates as whole degrees then decimal minutes followed by the
+/// direction, ie "44,42.00000000S"
+fn coordinate(degrees: f64, positive: char, negative: char) -> String {
+    let direction = if degrees < 0.0 { negative } else { positive };
+    let degrees = degrees.abs();
+    let minutes = degrees.fract() * 60.0;
+    format!("{},{:.8}{}", degrees.trunc(), minutes, direction)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_to_xmp() {
+        let metadata = ImageMetadata {
+            rating: Some(4),
+            keywords: vec!["Wānaka".to_string(), "lake & mountains".to_string()],
+            capture_time: Some(NaiveDate::from_ymd(2019, 7, 26).and_hms(13, 25, 33)),
+            capture_time_offset: Some("+12:00".to_string()),
+            gps_latitude: Some(-44.7),
+            gps_longitude: Some(169.16),
+            ..Default::default()
+        };
+        let xmp = to_xmp(&metadata);
+        assert!(xmp.contains("<xmp:Rating>4</xmp:Rating>"));
+        assert!(xmp
+            .contains("<exif:DateTimeOriginal>2019-07-26T13:25:33+12:00</exif:DateTimeOriginal>"));
+        assert!(xmp.contains("<exif:GPSLatitude>44,42.00000000S</exif:GPSLatitude>"));
+        assert!(xmp.contains("<exif:GPSLongitude>169,9.60000000E</exif:GPSLongitude>"));
+        assert!(xmp.contains(
+            "<dc:subject>\n    <rdf:Bag>\n     <rdf:li>Wānaka</rdf:li>\n     <rdf:li>lake &amp; mountains</rdf:li>\n"
+        ));
+
+        let xmp = to_xmp(&ImageMetadata::default());
+        assert!(!xmp.contains("exif:DateTimeOriginal>"));
+        assert!(!xmp.contains("dc:subject"));
+    }
+
+    #[test]
+    fn test_merge_xmp() {
+        // As Lightroom writes it, with the simple properties as attributes
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmp:Rating="5"
+    exif:DateTimeOriginal="2019-07-26T13:25:33.50+12:00"
+    exif:GPSLatitude="44,42,0S"
+    exif:GPSLongitude="169,9.6E">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>Wānaka</rdf:li>
+     <rdf:li>lake &amp; mountains</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+        let mut metadata = ImageMetadata {
+            rating: Some(2),
+            keywords: vec!["lake".to_string()],
+            camera_model: Some("Canon EOS 5D Mark IV".to_string()),
+            ..Default::default()
+        };
+        merge_xmp(&mut metadata, xmp);
+        assert_eq!(metadata.rating, Some(5));
+        assert_eq!(
+            metadata.keywords,
+            vec!["Wānaka".to_string(), "lake & mountains".to_string()]
+        );
+        assert_eq!(
+            metadata.capture_time,
+            Some(NaiveDate::from_ymd(2019, 7, 26).and_hms_milli(13, 25, 33, 500))
+        );
+        assert_eq!(metadata.capture_time_offset, Some("+12:00".to_string()));
+        assert!((metadata.gps_latitude.unwrap() + 44.7).abs() < 1e-9);
+        assert!((metadata.gps_longitude.unwrap() - 169.16).abs() < 1e-9);
+        assert!(metadata.geohash.is_some());
+        // What the XMP doesn't have is left alone
+        assert_eq!(
+            metadata.camera_model,
+            Some("Canon EOS 5D Mark IV".to_string())
+        );
+
+        // What we write reads back the same
+        let mut round_tripped = ImageMetadata::default();
+        merge_xmp(&mut round_tripped, &to_xmp(&metadata));
+        assert_eq!(round_tripped.rating, metadata.rating);
+        assert_eq!(round_tripped.keywords, metadata.keywords);
+        assert_eq!(round_tripped.capture_time, metadata.capture_time);
+
+        let mut untouched = ImageMetadata {
+            rating: Some(2),
+            ..Default::default()
+        };
+        merge_xmp(&mut untouched, "<x:xmpmeta/>");
+        assert_eq!(untouched.rating, Some(2));
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_merge_xmp_sidecar() -> Result<(), crate::ImageError> {
+        let dir = Path::new("target/test/merge_xmp_sidecar");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("JAM19896.jpg");
+        std::fs::copy("tests/images/JAM19896.jpg", &image_path)?;
+
+        let mut metadata = crate::extract_file_with_options(&image_path, &Default::default())?
+            .unwrap()
+            .image_metadata;
+        assert_eq!(merge_xmp_sidecar(&image_path, &mut metadata)?, None);
+        assert_eq!(metadata.rating, None);
+
+        std::fs::write(
+            dir.join("JAM19896.xmp"),
+            r#"<rdf:Description xmp:Rating="3"/>"#,
+        )?;
+        assert_eq!(
+            merge_xmp_sidecar(&image_path, &mut metadata)?,
+            Some(dir.join("JAM19896.xmp"))
+        );
+        assert_eq!(metadata.rating, Some(3));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_write_xmp_sidecar() -> Result<(), crate::ImageError> {
+        let dir = Path::new("target/test/xmp_sidecar");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let image_path = dir.join("JAM19896.jpg");
+        std::fs::copy("tests/images/JAM19896.jpg", &image_path)?;
+
+        let metadata = crate::extract_file_with_options(&image_path, &Default::default())?;
+        let sidecar = write_xmp_sidecar(&image_path, &metadata.unwrap().image_metadata)?;
+        assert_eq!(sidecar, dir.join("JAM19896.jpg.xmp"));
+        let xmp = std::fs::read_to_string(sidecar)?;
+        assert!(xmp.contains("<exif:DateTimeOriginal>"));
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 use assert_cmd::Command;
+use exif::experimental::Writer;
+use exif::{Field, In, Tag, Value as ExifValue};
 use serde_json::Value;
+use std::path::Path;
 
 /// A simple top level test to check the cli
 #[test]
@@ -43,3 +46,651 @@ fn test_cli_sad() {
         );
     }
 }
+
+/// Check the summary line is printed under --verbose, and that --quiet overrides it
+#[test]
+fn test_cli_verbose() {
+    std::fs::create_dir_all("target/test/verbose").expect("Failed to create directory");
+    let image_path = "target/test/verbose/JAM19896.jpg";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--verbose")
+        .arg(image_path)
+        .assert()
+        .success()
+        .stderr("JAM19896.jpg: Canon EOS 5D Mark IV, 2019-07-26, ISO 1000\n");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--verbose")
+        .arg("--quiet")
+        .arg(image_path)
+        .assert()
+        .success()
+        .stderr("");
+}
+
+/// Writes out a tiff file containing nothing but GPS coordinates, our sample images
+/// don't have any location data
+fn write_geotagged_tiff(path: &str, latitude: (u32, u32), longitude: (u32, u32)) {
+    let dms = |(degrees, minutes): (u32, u32)| {
+        ExifValue::Rational(vec![
+            (degrees, 1).into(),
+            (minutes, 1).into(),
+            (0, 1).into(),
+        ])
+    };
+    let fields = vec![
+        Field {
+            tag: Tag::GPSLatitude,
+            ifd_num: In::PRIMARY,
+            value: dms(latitude),
+        },
+        Field {
+            tag: Tag::GPSLatitudeRef,
+            ifd_num: In::PRIMARY,
+            value: ExifValue::Ascii(vec![b"S".to_vec()]),
+        },
+        Field {
+            tag: Tag::GPSLongitude,
+            ifd_num: In::PRIMARY,
+            value: dms(longitude),
+        },
+        Field {
+            tag: Tag::GPSLongitudeRef,
+            ifd_num: In::PRIMARY,
+            value: ExifValue::Ascii(vec![b"E".to_vec()]),
+        },
+    ];
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut file = std::fs::File::create(path).expect("Failed to create test file");
+    writer
+        .write(&mut file, false)
+        .expect("Failed to write test file");
+}
+
+/// Only images within the radius should get json written out
+#[test]
+fn test_cli_near() {
+    std::fs::create_dir_all("target/test/near").expect("Failed to create directory");
+    // Wanaka and Auckland
+    write_geotagged_tiff("target/test/near/wanaka.tif", (44, 42), (169, 8));
+    write_geotagged_tiff("target/test/near/auckland.tif", (36, 51), (174, 46));
+    std::fs::copy("tests/images/JAM19896.jpg", "target/test/near/JAM19896.jpg")
+        .expect("Failed to copy test file");
+    for json in &["wanaka", "auckland", "JAM19896"] {
+        let _ = std::fs::remove_file(format!("target/test/near/{}.json", json));
+    }
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--near=-44.69,169.13,10")
+        .arg("target/test/near/wanaka.tif")
+        .arg("target/test/near/auckland.tif")
+        .arg("target/test/near/JAM19896.jpg")
+        .assert()
+        .success();
+
+    assert!(Path::new("target/test/near/wanaka.json").exists());
+    assert!(!Path::new("target/test/near/auckland.json").exists());
+    assert!(!Path::new("target/test/near/JAM19896.json").exists());
+}
+
+/// Stats over the sample images, which shouldn't write out any json files
+#[test]
+fn test_cli_stats() {
+    std::fs::create_dir_all("target/test/stats").expect("Failed to create directory");
+    let image_path = "target/test/stats/JAM19896.jpg";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+    let _ = std::fs::remove_file("target/test/stats/JAM19896.json");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--stats")
+        .arg("--format")
+        .arg("json")
+        .arg(image_path)
+        .arg("tests/images/JAM26284.jpg")
+        .arg("tests/images/JAM26496.jpg")
+        .arg("tests/images/rotated_CCW90.jpg")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stats: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats.get("images").unwrap().as_u64(), Some(4));
+    assert_eq!(stats.get("with_capture_time").unwrap().as_u64(), Some(3));
+    assert!(!Path::new("target/test/stats/JAM19896.json").exists());
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--stats")
+        .arg(image_path)
+        .assert()
+        .success()
+        .stdout(
+        "1 images, 0 with GPS, 1 with capture time, top camera models: Canon EOS 5D Mark IV (1)\n",
+    );
+}
+
+/// Recursing into a directory, the hidden files here aren't real images so would
+/// fail to process if they weren't skipped
+#[test]
+fn test_cli_recursive_skip_hidden() {
+    let dir = Path::new("target/test/recursive");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir.join("nested/.thumbnails")).expect("Failed to create directory");
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("nested/JAM19896.jpg"))
+        .expect("Failed to copy test file");
+    std::fs::write(dir.join("._JAM19896.jpg"), b"").expect("Failed to write test file");
+    std::fs::write(dir.join("nested/.thumbnails/JAM19896.jpg"), b"")
+        .expect("Failed to write test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--recursive")
+        .arg("--skip-hidden")
+        .arg(dir)
+        .assert()
+        .success();
+    assert!(dir.join("nested/JAM19896.json").exists());
+    assert!(!dir.join("._JAM19896.json").exists());
+    assert!(!dir.join("nested/.thumbnails/JAM19896.json").exists());
+
+    // Hidden files are included by default
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--recursive").arg(dir).assert().failure();
+}
+
+/// A normal file should finish well within the timeout
+#[test]
+fn test_cli_timeout() {
+    std::fs::create_dir_all("target/test/timeout").expect("Failed to create directory");
+    let image_path = "target/test/timeout/JAM19896.jpg";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--timeout")
+        .arg("5")
+        .arg(image_path)
+        .assert()
+        .success()
+        .stderr("");
+    assert!(Path::new("target/test/timeout/JAM19896.json").exists());
+}
+
+#[test]
+fn test_cli_exclude() {
+    std::fs::create_dir_all("target/test/exclude").expect("Failed to create directory");
+    let image_path = "target/test/exclude/JAM19896.jpg";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--exclude")
+        .arg("camera_serial,orientation")
+        .arg(image_path)
+        .assert()
+        .success();
+
+    let metadata: Value =
+        serde_json::from_slice(&std::fs::read("target/test/exclude/JAM19896.json").unwrap())
+            .unwrap();
+    assert!(metadata.get("camera_serial").is_none());
+    assert!(metadata.get("orientation").is_none());
+    assert_eq!(
+        metadata.get("camera_model").unwrap().as_str(),
+        Some("Canon EOS 5D Mark IV")
+    );
+    assert_eq!(metadata.get("size").unwrap().as_u64(), Some(953458));
+}
+
+/// Mirroring a nested source tree into an output directory
+#[test]
+fn test_cli_base_dir() {
+    let source_dir = Path::new("target/test/base_dir/source");
+    let output_dir = Path::new("target/test/base_dir/output");
+    let _ = std::fs::remove_dir_all("target/test/base_dir");
+    for dir in &["2019", "2020/january"] {
+        std::fs::create_dir_all(source_dir.join(dir)).expect("Failed to create directory");
+        std::fs::copy(
+            "tests/images/JAM19896.jpg",
+            source_dir.join(dir).join("IMG.jpg"),
+        )
+        .expect("Failed to copy test file");
+    }
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--recursive")
+        .arg("--output-dir")
+        .arg(output_dir)
+        .arg("--base-dir")
+        .arg(source_dir)
+        .arg(source_dir)
+        .assert()
+        .success();
+
+    assert!(output_dir.join("2019/IMG.json").exists());
+    assert!(output_dir.join("2020/january/IMG.json").exists());
+    assert!(!source_dir.join("2019/IMG.json").exists());
+}
+
+/// --strip-prefix is the same as --base-dir, images outside the prefix are an error
+#[test]
+fn test_cli_strip_prefix() {
+    let source_dir = Path::new("target/test/strip_prefix/photos");
+    let output_dir = Path::new("target/test/strip_prefix/meta");
+    let _ = std::fs::remove_dir_all("target/test/strip_prefix");
+    std::fs::create_dir_all(source_dir.join("2019")).expect("Failed to create directory");
+    let image_path = source_dir.join("2019/IMG.jpg");
+    std::fs::copy("tests/images/JAM19896.jpg", &image_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--output-dir")
+        .arg(output_dir)
+        .arg("--strip-prefix")
+        .arg(source_dir)
+        .arg(&image_path)
+        .assert()
+        .success();
+    assert!(output_dir.join("2019/IMG.json").exists());
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--output-dir")
+        .arg(output_dir)
+        .arg("--strip-prefix")
+        .arg(source_dir.join("2020"))
+        .arg(&image_path)
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("is not under the base directory"));
+}
+
+/// All the images printed out as one object keyed by their capture day
+#[test]
+fn test_cli_group_by_day() {
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--group-by-day")
+        .arg("--include")
+        .arg("filename")
+        .arg("tests/images/JAM19896.jpg")
+        .arg("tests/images/JAM26284.jpg")
+        .arg("tests/images/JAM26496.jpg")
+        .arg("tests/images/rotated_CCW90.jpg")
+        .assert()
+        .success();
+
+    let groups: Value = serde_json::from_slice(&output.get_output().stdout).unwrap();
+    assert_eq!(
+        groups,
+        serde_json::json!({
+            "2019-07-26": [{"filename": "JAM19896.jpg"}],
+            "2020-01-30": [{"filename": "JAM26284.jpg"}, {"filename": "JAM26496.jpg"}],
+            "unknown": [{"filename": "rotated_CCW90.jpg"}],
+        })
+    );
+    assert!(!Path::new("tests/images/JAM19896.json").exists());
+}
+
+/// All the images printed out as one object keyed by filename, or path when the
+/// filenames clash
+#[test]
+fn test_cli_format_map() {
+    std::fs::create_dir_all("target/test/format_map").expect("Failed to create directory");
+    let copy_path = "target/test/format_map/JAM19896.jpg";
+    std::fs::copy("tests/images/JAM19896.jpg", copy_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--format")
+        .arg("map")
+        .arg("tests/images/JAM19896.jpg")
+        .arg("tests/images/JAM26284.jpg")
+        .arg(copy_path)
+        .assert()
+        .success();
+
+    let map: Value = serde_json::from_slice(&output.get_output().stdout).unwrap();
+    let keys = map.as_object().unwrap().keys().collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        vec!["tests/images/JAM19896.jpg", "JAM26284.jpg", copy_path]
+    );
+    assert_eq!(map["JAM26284.jpg"]["size"], 574207);
+}
+
+/// When recursing, a file that fails shouldn't stop the rest from being processed
+#[test]
+fn test_cli_recursive_failures() {
+    let dir = Path::new("target/test/recursive_failures");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create directory");
+    std::fs::write(dir.join("A_corrupt.jpg"), b"not an image").expect("Failed to write test file");
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("B_JAM19896.jpg"))
+        .expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let assert = cmd.arg("--recursive").arg(dir).assert().failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("A_corrupt.jpg"));
+    assert!(stderr.ends_with(
+        "Skipped 0 unreadable directories or files and 1 files that failed to process\n"
+    ));
+    assert!(dir.join("B_JAM19896.json").exists());
+}
+
+#[test]
+fn test_cli_output_template() {
+    let output_dir = Path::new("target/test/output_template");
+    let _ = std::fs::remove_dir_all(output_dir);
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--output-dir")
+        .arg(output_dir)
+        .arg("--output-template")
+        .arg("{year}/{camera_model}/{filename}.json")
+        .arg("tests/images/JAM19896.jpg")
+        .arg("tests/images/rotated_CCW90.jpg")
+        .assert()
+        .success();
+
+    assert!(output_dir
+        .join("2019/Canon EOS 5D Mark IV/JAM19896.json")
+        .exists());
+    assert!(output_dir
+        .join("unknown/unknown/rotated_CCW90.json")
+        .exists());
+}
+
+/// Without a terminal to confirm on nothing gets overwritten
+#[test]
+fn test_cli_interactive() {
+    std::fs::create_dir_all("target/test/interactive").expect("Failed to create directory");
+    let image_path = "target/test/interactive/JAM19896.jpg";
+    let json_path = "target/test/interactive/JAM19896.json";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+    std::fs::write(json_path, "{}").expect("Failed to write json");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--interactive")
+        .arg(image_path)
+        .write_stdin("n\n")
+        .assert()
+        .success();
+
+    assert_eq!(std::fs::read_to_string(json_path).unwrap(), "{}");
+}
+
+/// Only the images with the included extensions get processed when recursing
+#[test]
+fn test_cli_include_exclude_ext() {
+    let dir = Path::new("target/test/include_ext");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create directory");
+    // The exif reader goes by the contents so a jpeg named as a png does fine here
+    for name in &["a.jpg", "b.JPG", "c.png"] {
+        std::fs::copy("tests/images/JAM19896.jpg", dir.join(name))
+            .expect("Failed to copy test file");
+    }
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("-r")
+        .arg("--include-ext")
+        .arg("jpg")
+        .arg(dir)
+        .assert()
+        .success();
+    assert!(dir.join("a.json").exists());
+    assert!(dir.join("b.json").exists());
+    assert!(!dir.join("c.json").exists());
+
+    std::fs::remove_file(dir.join("a.json")).unwrap();
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("-r")
+        .arg("--exclude-ext")
+        .arg("JPG")
+        .arg(dir)
+        .assert()
+        .success();
+    assert!(!dir.join("a.json").exists());
+    assert!(dir.join("c.json").exists());
+}
+
+/// Patterns get expanded by us rather than the shell
+#[cfg(feature = "glob")]
+#[test]
+fn test_cli_glob() {
+    let dir = Path::new("target/test/glob");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create directory");
+    for name in &["JAM19896.jpg", "JAM26284.jpg", "rotated_CCW90.jpg"] {
+        std::fs::copy(Path::new("tests/images").join(name), dir.join(name))
+            .expect("Failed to copy test file");
+    }
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--glob")
+        .arg("target/test/glob/JAM*.jpg")
+        .assert()
+        .success();
+    assert!(dir.join("JAM19896.json").exists());
+    assert!(dir.join("JAM26284.json").exists());
+    assert!(!dir.join("rotated_CCW90.json").exists());
+}
+
+/// Images that change after their json was written get flagged up
+#[cfg(feature = "sha2")]
+#[test]
+fn test_cli_verify() {
+    std::fs::create_dir_all("target/test/verify").expect("Failed to create directory");
+    let image_path = "target/test/verify/JAM19896.jpg";
+    let json_path = "target/test/verify/JAM19896.json";
+    std::fs::copy("tests/images/JAM19896.jpg", image_path).expect("Failed to copy test file");
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--sha256").arg(image_path).assert().success();
+    let metadata: Value = serde_json::from_slice(&std::fs::read(json_path).unwrap()).unwrap();
+    assert_eq!(
+        metadata.get("sha256").unwrap().as_str(),
+        Some("1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3")
+    );
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--verify")
+        .arg(json_path)
+        .assert()
+        .success()
+        .stdout("");
+
+    let mut bytes = std::fs::read(image_path).unwrap();
+    bytes.push(0);
+    std::fs::write(image_path, bytes).unwrap();
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd.arg("--verify").arg(json_path).assert().failure();
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(stdout.contains("JAM19896.jpg has changed"));
+}
+
+#[test]
+fn test_cli_rename() {
+    let dir = Path::new("target/test/cli_rename");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("JAM19896.jpg")).unwrap();
+    std::fs::copy(
+        "tests/images/rotated_CCW90.jpg",
+        dir.join("rotated_CCW90.jpg"),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--rename")
+        .arg(dir.join("JAM19896.jpg"))
+        .arg(dir.join("rotated_CCW90.jpg"))
+        .assert()
+        .success();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("No capture time to rename by"));
+
+    assert!(dir.join("20190726_132533.jpg").exists());
+    assert!(!dir.join("JAM19896.jpg").exists());
+    assert!(dir.join("rotated_CCW90.jpg").exists());
+    // Nothing but renaming happens
+    assert!(!dir.join("20190726_132533.json").exists());
+}
+
+#[test]
+fn test_cli_combined() {
+    let dir = Path::new("target/test/cli_combined");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    for i in 0..50 {
+        std::fs::copy("tests/images/JAM26284.jpg", dir.join(format!("{}.jpg", i))).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd.arg("-r").arg("--combined").arg(dir).assert().success();
+    let images: Vec<Value> = serde_json::from_slice(&output.get_output().stdout).unwrap();
+    assert_eq!(images.len(), 50);
+    assert_eq!(images[0]["size"], 574207);
+    assert!(!dir.join("0.json").exists());
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn test_cli_dedupe() {
+    let dir = Path::new("target/test/cli_dedupe");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("a.jpg")).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("b.jpg")).unwrap();
+    std::fs::copy("tests/images/JAM26284.jpg", dir.join("c.jpg")).unwrap();
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--dedupe")
+        .arg(dir.join("a.jpg"))
+        .arg(dir.join("b.jpg"))
+        .arg(dir.join("c.jpg"))
+        .assert()
+        .success();
+    let groups: Value = serde_json::from_slice(&output.get_output().stdout).unwrap();
+    assert_eq!(
+        groups,
+        serde_json::json!([{
+            "hash": "1ec36114ec12dd0701eb453d630f4cf5845243723f1429d6b43843f0b8fc4ce3",
+            "paths": [
+                dir.join("a.jpg").to_string_lossy(),
+                dir.join("b.jpg").to_string_lossy()
+            ]
+        }])
+    );
+    assert!(!dir.join("a.json").exists());
+}
+
+#[test]
+fn test_cli_max_file_size() {
+    let dir = Path::new("target/test/cli_max_file_size");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("large.jpg")).unwrap();
+    std::fs::copy("tests/images/JAM26284.jpg", dir.join("small.jpg")).unwrap();
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    let output = cmd
+        .arg("--max-file-size")
+        .arg("600K")
+        .arg(dir.join("large.jpg"))
+        .arg(dir.join("small.jpg"))
+        .assert()
+        .success();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("large.jpg: Skipping as it's 953458 bytes"));
+    assert!(!dir.join("large.json").exists());
+    assert!(dir.join("small.json").exists());
+}
+
+#[test]
+fn test_cli_aliases() {
+    let dir = Path::new("target/test/cli_aliases");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("JAM19896.jpg")).unwrap();
+    std::fs::write(
+        dir.join("aliases.json"),
+        r#"{"Canon EOS 5D Mark IV": "EOS 5D Mark IV"}"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--aliases")
+        .arg(dir.join("aliases.json"))
+        .arg(dir.join("JAM19896.jpg"))
+        .assert()
+        .success();
+    let json: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("JAM19896.json")).unwrap()).unwrap();
+    assert_eq!(json["camera_model"], "EOS 5D Mark IV");
+}
+
+#[test]
+fn test_cli_on_error() {
+    let dir = Path::new("target/test/cli_on_error");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("broken.jpg"), b"not a jpeg").unwrap();
+    std::fs::copy("tests/images/JAM26284.jpg", dir.join("JAM26284.jpg")).unwrap();
+    let run = |policy: &str| {
+        let _ = std::fs::remove_file(dir.join("broken.json"));
+        let _ = std::fs::remove_file(dir.join("JAM26284.json"));
+        let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+        cmd.arg("--on-error")
+            .arg(policy)
+            .arg(dir.join("broken.jpg"))
+            .arg(dir.join("JAM26284.jpg"))
+            .assert()
+    };
+
+    let output = run("stop").failure();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("broken.jpg, we hit an error"));
+    assert!(!dir.join("JAM26284.json").exists());
+
+    let output = run("skip").success();
+    assert!(output.get_output().stderr.is_empty());
+    assert!(!dir.join("broken.json").exists());
+    assert!(dir.join("JAM26284.json").exists());
+
+    run("record").success();
+    let stub: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("broken.json")).unwrap()).unwrap();
+    assert_eq!(stub["filename"], "broken.jpg");
+    assert!(stub["error"].is_string());
+    assert!(dir.join("JAM26284.json").exists());
+}
+
+#[test]
+fn test_cli_merge_sidecar() {
+    let dir = Path::new("target/test/cli_merge_sidecar");
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy("tests/images/JAM19896.jpg", dir.join("JAM19896.jpg")).unwrap();
+    std::fs::write(
+        dir.join("JAM19896.jpg.xmp"),
+        r#"<rdf:Description rdf:about="" xmp:Rating="4"/>"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("image-metadata").unwrap();
+    cmd.arg("--merge-sidecar")
+        .arg(dir.join("JAM19896.jpg"))
+        .assert()
+        .success();
+    let json: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("JAM19896.json")).unwrap()).unwrap();
+    assert_eq!(json["rating"], 4);
+    assert_eq!(json["camera_model"], "Canon EOS 5D Mark IV");
+}